@@ -1,27 +1,141 @@
+use crate::transaction::ClientId;
 use rust_decimal::Decimal;
-use serde::Serialize;
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
+use std::str::FromStr;
+use thiserror::Error;
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct Account {
     pub available: Decimal,
     pub held: Decimal,
     pub locked: bool,
+    /// Why the account is locked. `None` on locked accounts from older snapshots, which are
+    /// treated like chargeback locks.
+    #[serde(default)]
+    pub lock_cause: Option<LockCause>,
+    /// Transactions currently charged back, so reversing the last one can lift the lock.
+    /// Zero on accounts from older snapshots, which then stay locked.
+    #[serde(default)]
+    pub chargebacks: u32,
+    /// Set the first time any of the account's transactions is disputed, and never cleared.
+    #[serde(default)]
+    pub had_dispute: bool,
+    /// Deposits applied to the account.
+    #[serde(default)]
+    pub deposits: u64,
+    /// Disputes opened against the account's transactions.
+    #[serde(default)]
+    pub disputes: u64,
+    /// Set once the account's disputes exceeded the configured share of its deposits.
+    #[serde(default)]
+    pub dispute_ratio_breached: bool,
+    /// Disputes this client filed against other clients' transactions, counted when those
+    /// are rejected.
+    #[serde(default)]
+    pub suspicious_disputes: u32,
+    /// Whether any transaction ever changed the balances. Accounts opened by a record that then
+    /// failed, e.g. an over-limit withdrawal, stay unfunded. Assumed for older snapshots.
+    #[serde(default = "funded_default")]
+    pub funded: bool,
+    /// Lifetime sum of the deposits applied to the account.
+    #[serde(default)]
+    pub deposited: Decimal,
+    /// Lifetime sum of the withdrawals paid out of the account, fees not included.
+    #[serde(default)]
+    pub withdrawn: Decimal,
+    /// How far withdrawals may take available below zero. Zero, no overdraft, on accounts
+    /// from older snapshots.
+    #[serde(default)]
+    pub overdraft_limit: Decimal,
+    /// Decimal places this account's amounts are written with, e.g. 2 for a fiat client next
+    /// to crypto ones. `None` uses the default of four.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_scale: Option<u32>,
+    /// The latest timestamp among the transactions applied to the account, if they carried any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_activity: Option<u64>,
+    /// The memo of the most recent transaction applied to the account that carried one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+fn funded_default() -> bool {
+    true
+}
+
+/// What locked an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LockCause {
+    /// A chargeback. Only lifted by reversing every chargeback on the account.
+    Chargeback,
+    /// A manual `freeze` by an operator, lifted again by `unfreeze`.
+    Freeze,
+}
+
+/// Why a withdrawal was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum WithdrawError {
+    #[error("account is locked")]
+    Locked,
+    #[error("insufficient available funds")]
+    InsufficientFunds,
+}
+
+/// Which reason a refused withdrawal reports when the account is both locked and short of
+/// funds. The withdrawal fails either way, this only decides how it's counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WithdrawErrorPriority {
+    #[default]
+    Locked,
+    InsufficientFunds,
 }
 
 /// Notes on chargebacks and locking:
 /// - Multiple transactions can be disputed and later charged back. On the first chargeback
 ///   we lock the account (per spec), but still allow chargebacks to complete for transactions
 ///   that were already under dispute before the lock.
-/// - We don’t track a separate list or count of chargebacks. Locking is a boolean that becomes
-///   true after the first chargeback. Per-transaction state is tracked via the disputed flag,
-///   and a chargeback clears that flag to prevent double-chargeback of the same tx.
+/// - Resolves get the same exception: a dispute opened before the lock can still be resolved,
+///   releasing its funds back to available. Otherwise they'd stay held forever. No new holds
+///   can be placed on a locked account, so only pre-lock disputes ever have anything to release.
+/// - Locking is a boolean that becomes true after the first chargeback. Per-transaction state
+///   is tracked via the disputed flag, and a chargeback clears that flag to prevent
+///   double-chargeback of the same tx. We only count the charged back transactions, so that
+///   reversing the last of them after review can lift the lock again.
+/// - Disputes of withdrawals work the other way round from deposits. The money already left,
+///   so a dispute credits it back as held, a resolve drops that credit (the withdrawal stands)
+///   and a chargeback releases it to available (the withdrawal is reversed).
 impl Account {
     pub fn new() -> Self {
         Self::default()
     }
 
-    pub fn total(&self) -> Decimal {
-        self.available + self.held
+    /// An account whose withdrawals may take available down to `-limit`.
+    pub fn with_overdraft(limit: Decimal) -> Self {
+        Self {
+            overdraft_limit: limit,
+            ..Self::default()
+        }
+    }
+
+    /// An account written with `scale` decimal places rather than the default four.
+    pub fn with_output_scale(scale: u32) -> Self {
+        Self {
+            output_scale: Some(scale),
+            ..Self::default()
+        }
+    }
+
+    /// `None` if the sum doesn't fit a `Decimal`, which takes adversarially large balances.
+    pub fn total(&self) -> Option<Decimal> {
+        self.available.checked_add(self.held)
+    }
+
+    /// Rounds both balances to exactly `scale` decimal places.
+    pub fn rescale(&mut self, scale: u32) {
+        self.available.rescale(scale);
+        self.held.rescale(scale);
     }
 
     pub fn deposit(&mut self, amount: Decimal) {
@@ -31,58 +145,488 @@ impl Account {
     }
 
     pub fn withdraw(&mut self, amount: Decimal) -> bool {
-        if !self.locked && self.available >= amount {
-            self.available -= amount;
-            true
-        } else {
-            false
+        self.try_withdraw(amount).is_ok()
+    }
+
+    /// Like `withdraw`, but reports why the withdrawal was refused. A locked account reports
+    /// `Locked` even if it couldn't cover the amount either.
+    pub fn try_withdraw(&mut self, amount: Decimal) -> Result<(), WithdrawError> {
+        self.try_withdraw_with(amount, WithdrawErrorPriority::Locked)
+    }
+
+    /// Like `try_withdraw`, with `priority` picking the reason when both apply.
+    pub fn try_withdraw_with(
+        &mut self,
+        amount: Decimal,
+        priority: WithdrawErrorPriority,
+    ) -> Result<(), WithdrawError> {
+        let insufficient = self.available - amount < -self.overdraft_limit;
+        match (self.locked, insufficient, priority) {
+            (true, true, WithdrawErrorPriority::InsufficientFunds) | (false, true, _) => {
+                Err(WithdrawError::InsufficientFunds)
+            }
+            (true, _, _) => Err(WithdrawError::Locked),
+            (false, false, _) => {
+                self.available -= amount;
+                Ok(())
+            }
+        }
+    }
+
+    /// Adjusts available by a signed amount. Returns false, leaving the account untouched, if
+    /// the account is locked or, unless `allow_negative_total`, the total would drop below zero.
+    pub fn correct(&mut self, amount: Decimal, allow_negative_total: bool) -> bool {
+        let new_total = self.total().and_then(|total| total.checked_add(amount));
+        if self.locked || (!allow_negative_total && new_total.is_none_or(|t| t < Decimal::ZERO)) {
+            return false;
         }
+
+        self.available += amount;
+        true
     }
 
-    pub fn hold_funds(&mut self, amount: Decimal) {
+    /// Returns the amount actually moved to held, zero if the hold was refused.
+    pub fn hold_funds(&mut self, amount: Decimal) -> Decimal {
         if !self.locked && self.available >= amount {
             self.available -= amount;
             self.held += amount;
+            amount
+        } else {
+            Decimal::ZERO
         }
     }
 
-    pub fn release_funds(&mut self, amount: Decimal) {
-        if !self.locked && self.held >= amount {
+    /// Returns the amount actually released, zero if the release was refused.
+    pub fn release_funds(&mut self, amount: Decimal) -> Decimal {
+        if self.held >= amount {
             self.held -= amount;
             self.available += amount;
+            amount
+        } else {
+            Decimal::ZERO
+        }
+    }
+
+    /// Returns the amount actually charged back, zero if the chargeback was refused.
+    pub fn chargeback(&mut self, amount: Decimal) -> Decimal {
+        if self.held >= amount {
+            self.held -= amount;
+            self.locked = true;
+            self.lock_cause = Some(LockCause::Chargeback);
+            amount
+        } else {
+            Decimal::ZERO
+        }
+    }
+
+    /// Disputing a withdrawal provisionally credits the withdrawn amount back as held funds, so
+    /// held and total go up while available is untouched. Returns the amount credited, zero if
+    /// the account is locked.
+    pub fn hold_reversal(&mut self, amount: Decimal) -> Decimal {
+        if self.locked {
+            return Decimal::ZERO;
+        }
+        self.held += amount;
+        amount
+    }
+
+    /// Resolving a withdrawal dispute means the withdrawal stands: the provisional credit is
+    /// simply dropped. Returns the amount dropped, zero if refused.
+    pub fn drop_reversal(&mut self, amount: Decimal) -> Decimal {
+        if self.held >= amount {
+            self.held -= amount;
+            amount
+        } else {
+            Decimal::ZERO
         }
     }
 
-    pub fn chargeback(&mut self, amount: Decimal) {
+    /// Charging back a withdrawal reverses it: the provisional credit becomes available again,
+    /// and the account is locked like for any chargeback. Returns the amount reversed.
+    pub fn chargeback_withdrawal(&mut self, amount: Decimal) -> Decimal {
         if self.held >= amount {
             self.held -= amount;
+            self.available += amount;
+            self.locked = true;
+            self.lock_cause = Some(LockCause::Chargeback);
+            amount
+        } else {
+            Decimal::ZERO
+        }
+    }
+
+    /// Reverses a deposit chargeback after review: the charged back funds become available again.
+    pub fn reverse_chargeback(&mut self, amount: Decimal) {
+        self.available += amount;
+        self.lift_chargeback();
+    }
+
+    /// Reverses a withdrawal chargeback: the withdrawal stands after all, so the amount it had
+    /// credited back leaves available again. Returns false, leaving the account untouched, if
+    /// available no longer covers it.
+    pub fn reverse_withdrawal_chargeback(&mut self, amount: Decimal) -> bool {
+        if self.available < amount {
+            return false;
+        }
+        self.available -= amount;
+        self.lift_chargeback();
+        true
+    }
+
+    /// Unlocks the account once its last chargeback is reversed.
+    fn lift_chargeback(&mut self) {
+        if self.chargebacks == 1 && self.lock_cause == Some(LockCause::Chargeback) {
+            self.locked = false;
+            self.lock_cause = None;
+        }
+        self.chargebacks = self.chargebacks.saturating_sub(1);
+    }
+
+    /// Locks the account without touching its balances. A no-op on an already locked account,
+    /// so it never masks a chargeback lock.
+    pub fn freeze(&mut self) {
+        if !self.locked {
             self.locked = true;
+            self.lock_cause = Some(LockCause::Freeze);
+        }
+    }
+
+    /// Locked by a chargeback with nothing left on it, the end of the account's lifecycle as far
+    /// as downstream tracking is concerned. A frozen account is never closed.
+    pub fn closed(&self) -> bool {
+        self.locked
+            && self.lock_cause != Some(LockCause::Freeze)
+            && self.total() == Some(Decimal::ZERO)
+    }
+
+    /// Lifts a freeze. Returns false, leaving the account untouched, if it's locked for any
+    /// other reason.
+    pub fn unfreeze(&mut self) -> bool {
+        match (self.locked, self.lock_cause) {
+            (false, _) => true,
+            (true, Some(LockCause::Freeze)) => {
+                self.locked = false;
+                self.lock_cause = None;
+                true
+            }
+            (true, _) => false,
+        }
+    }
+
+    /// Folds `other` into this account, for merging two clients: balances and counters add up,
+    /// flags carry over from either side. Returns false, leaving the account untouched, if
+    /// the balances would overflow.
+    ///
+    /// The result is locked if either side was. A chargeback lock outranks a freeze, since
+    /// unfreezing must not lift it. The overdraft limit and memo stay this account's own,
+    /// the memo only falling back to `other`'s.
+    pub fn merge(&mut self, other: &Account) -> bool {
+        let sum = |a: Decimal, b: Decimal| a.checked_add(b);
+        let (Some(available), Some(held), Some(deposited), Some(withdrawn)) = (
+            sum(self.available, other.available),
+            sum(self.held, other.held),
+            sum(self.deposited, other.deposited),
+            sum(self.withdrawn, other.withdrawn),
+        ) else {
+            return false;
+        };
+
+        let cause = |account: &Account| account.locked.then_some(account.lock_cause);
+        self.lock_cause = match (cause(self), cause(other)) {
+            (None, None) => None,
+            (Some(Some(LockCause::Freeze)) | None, Some(Some(LockCause::Freeze)) | None) => {
+                Some(LockCause::Freeze)
+            }
+            // Older snapshots' locks without a cause count as chargeback locks
+            _ => Some(LockCause::Chargeback),
+        };
+        self.locked |= other.locked;
+
+        self.available = available;
+        self.held = held;
+        self.deposited = deposited;
+        self.withdrawn = withdrawn;
+        self.chargebacks += other.chargebacks;
+        self.deposits += other.deposits;
+        self.disputes += other.disputes;
+        self.suspicious_disputes += other.suspicious_disputes;
+        self.had_dispute |= other.had_dispute;
+        self.dispute_ratio_breached |= other.dispute_ratio_breached;
+        self.funded |= other.funded;
+        self.last_activity = self.last_activity.max(other.last_activity);
+        if self.memo.is_none() {
+            self.memo = other.memo.clone();
+        }
+        true
+    }
+}
+
+/// How the `total` column is derived when rounding the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TotalPolicy {
+    /// Round the exact total on its own. Cheap and exact, but in edge cases
+    /// `rounded available + rounded held != rounded total`.
+    #[default]
+    Independent,
+    /// Use the sum of the already rounded available and held, so the columns always add up.
+    SumOfRounded,
+}
+
+impl FromStr for TotalPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "independent" => Ok(TotalPolicy::Independent),
+            "sum-of-rounded" => Ok(TotalPolicy::SumOfRounded),
+            other => anyhow::bail!(
+                "Unknown total policy {other:?}, expected independent or sum-of-rounded"
+            ),
+        }
+    }
+}
+
+/// How the `locked` column is written, since downstream systems disagree on booleans.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BoolFormat {
+    /// serde's own `true`/`false`.
+    #[default]
+    TrueFalse,
+    OneZero,
+    YesNo,
+}
+
+impl BoolFormat {
+    /// Writes a bool column in this format, as a plain bool for the default one.
+    pub fn serialize_field<S: SerializeStruct>(
+        self,
+        row: &mut S,
+        key: &'static str,
+        value: bool,
+    ) -> Result<(), S::Error> {
+        match self {
+            BoolFormat::TrueFalse => row.serialize_field(key, &value),
+            format => row.serialize_field(key, format.format(value)),
+        }
+    }
+
+    pub fn format(self, value: bool) -> &'static str {
+        match (self, value) {
+            (BoolFormat::TrueFalse, true) => "true",
+            (BoolFormat::TrueFalse, false) => "false",
+            (BoolFormat::OneZero, true) => "1",
+            (BoolFormat::OneZero, false) => "0",
+            (BoolFormat::YesNo, true) => "yes",
+            (BoolFormat::YesNo, false) => "no",
         }
     }
+
+    /// Reads back a value written in this format.
+    pub fn parse(self, text: &str) -> Option<bool> {
+        [true, false]
+            .into_iter()
+            .find(|&value| self.format(value) == text)
+    }
+}
+
+impl FromStr for BoolFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "true/false" => Ok(BoolFormat::TrueFalse),
+            "1/0" => Ok(BoolFormat::OneZero),
+            "yes/no" => Ok(BoolFormat::YesNo),
+            other => {
+                anyhow::bail!("Unknown bool format {other:?}, expected true/false, 1/0 or yes/no")
+            }
+        }
+    }
+}
+
+/// Decimal places amounts are written with, unless the account has its own `output_scale`.
+pub const OUTPUT_SCALE: u32 = 4;
+
+/// Output-time settings applied when turning an `Account` into an `AccountOutput`.
+#[derive(Debug, Clone, Default)]
+pub struct OutputConfig {
+    pub total_policy: TotalPolicy,
+    /// Balances whose absolute value is below this are written as exactly zero,
+    /// hiding residue left over from imported precision. Off by default to keep output exact.
+    pub zero_epsilon: Option<Decimal>,
+    /// Adds the extra per-account columns on top of the standard five.
+    pub verbose: bool,
+    /// Leave out accounts whose balances never changed, see `Account::funded`.
+    pub hide_unfunded: bool,
+    /// Adds the lifetime deposited and withdrawn columns.
+    pub net_flow: bool,
+    /// Adds the `closed` column, see `Account::closed`.
+    pub closed_status: bool,
+    pub bool_format: BoolFormat,
+    /// Pad amounts to exactly their scale, `5.0000` rather than `5`, for downstream parsers that
+    /// insist on a fixed layout.
+    pub fixed_decimals: bool,
+}
+
+impl OutputConfig {
+    fn snap(&self, value: Decimal) -> Decimal {
+        match self.zero_epsilon {
+            Some(epsilon) if value.abs() < epsilon => Decimal::ZERO,
+            _ => value,
+        }
+    }
+
+    /// Rounding only ever drops digits, padding takes a rescale, which `Decimal` then keeps
+    /// when it's written out.
+    fn round(&self, value: Decimal, scale: u32) -> Decimal {
+        let mut value = value.round_dp(scale);
+        if self.fixed_decimals {
+            value.rescale(scale);
+        }
+        value
+    }
 }
 
 // Output format for CSV
-#[derive(Debug, Serialize)]
+#[derive(Debug)]
 pub struct AccountOutput {
-    pub client: u16,
+    pub client: ClientId,
     pub available: Decimal,
     pub held: Decimal,
+    /// Capped at the largest `Decimal` when the balances don't add up to one, see `total_overflow`.
     pub total: Decimal,
     pub locked: bool,
+    /// How `locked` and `closed` are serialized. Not a column.
+    pub bool_format: BoolFormat,
+    /// Set when `total` was capped rather than computed. Not a column, the run warns instead.
+    pub total_overflow: bool,
+    // Verbose-only columns, left out of the output entirely when not set
+    pub had_dispute: Option<bool>,
+    pub memo: Option<String>,
+    // Net flow columns, likewise only present when asked for
+    pub deposited: Option<Decimal>,
+    pub withdrawn: Option<Decimal>,
+    pub closed: Option<bool>,
+}
+
+/// Written by hand rather than derived, so `locked` and `closed` can follow `bool_format`.
+impl Serialize for AccountOutput {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut row = serializer.serialize_struct("AccountOutput", 10)?;
+        row.serialize_field("client", &self.client)?;
+        row.serialize_field("available", &self.available)?;
+        row.serialize_field("held", &self.held)?;
+        row.serialize_field("total", &self.total)?;
+        self.bool_format
+            .serialize_field(&mut row, "locked", self.locked)?;
+
+        // The optional columns are left out entirely when not set
+        if let Some(had_dispute) = self.had_dispute {
+            row.serialize_field("had_dispute", &had_dispute)?;
+        }
+        if let Some(memo) = &self.memo {
+            row.serialize_field("memo", memo)?;
+        }
+        if let Some(deposited) = self.deposited {
+            row.serialize_field("deposited", &deposited)?;
+        }
+        if let Some(withdrawn) = self.withdrawn {
+            row.serialize_field("withdrawn", &withdrawn)?;
+        }
+        if let Some(closed) = self.closed {
+            self.bool_format
+                .serialize_field(&mut row, "closed", closed)?;
+        }
+        row.end()
+    }
 }
 
 impl AccountOutput {
-    pub fn from_account(client: u16, account: &Account) -> Self {
+    pub fn from_account(client: ClientId, account: &Account) -> Self {
+        Self::from_account_with(client, account, &OutputConfig::default())
+    }
+
+    pub fn from_account_with(client: ClientId, account: &Account, config: &OutputConfig) -> Self {
+        let scale = account.output_scale.unwrap_or(OUTPUT_SCALE);
+        let round = |value| config.round(value, scale);
+        let available = round(config.snap(account.available));
+        let held = round(config.snap(account.held));
+        let total = match config.total_policy {
+            TotalPolicy::Independent => account.total().map(|t| round(config.snap(t))),
+            TotalPolicy::SumOfRounded => available.checked_add(held),
+        };
+
         Self {
             client,
-            available: account.available.round_dp(4),
-            held: account.held.round_dp(4),
-            total: account.total().round_dp(4),
+            available,
+            held,
+            total: total.unwrap_or_else(|| account.available.saturating_add(account.held)),
             locked: account.locked,
+            bool_format: config.bool_format,
+            total_overflow: total.is_none(),
+            had_dispute: config.verbose.then_some(account.had_dispute),
+            memo: config
+                .verbose
+                .then(|| account.memo.clone().unwrap_or_default()),
+            deposited: config.net_flow.then(|| round(account.deposited)),
+            withdrawn: config.net_flow.then(|| round(account.withdrawn)),
+            closed: config.closed_status.then(|| account.closed()),
         }
     }
 }
 
+/// A copy of an account's balances, exact rather than rounded for output, see
+/// `PaymentEngine::account`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct AccountSnapshot {
+    pub available: Decimal,
+    pub held: Decimal,
+    /// Capped at the largest `Decimal` when the balances don't add up to one.
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl From<&Account> for AccountSnapshot {
+    fn from(account: &Account) -> Self {
+        Self {
+            available: account.available,
+            held: account.held,
+            total: account.available.saturating_add(account.held),
+            locked: account.locked,
+        }
+    }
+}
+
+/// Change in an account's state relative to a baseline, for CDC-style consumers.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct AccountDiff {
+    pub client: ClientId,
+    pub available_delta: Decimal,
+    pub held_delta: Decimal,
+    /// The current locked state, included whether or not it changed.
+    pub locked: bool,
+}
+
+impl AccountDiff {
+    pub const COLUMNS: [&str; 4] = ["client", "available_delta", "held_delta", "locked"];
+
+    /// Returns `None` when neither the balances nor the locked state changed. Bookkeeping such as
+    /// counters or the memo doesn't make a row on its own.
+    pub fn between(client: ClientId, before: &Account, after: &Account) -> Option<Self> {
+        let state = |account: &Account| (account.available, account.held, account.locked);
+        if state(before) == state(after) {
+            return None;
+        }
+
+        Some(Self {
+            client,
+            available_delta: after.available - before.available,
+            held_delta: after.held - before.held,
+            locked: after.locked,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -94,7 +638,7 @@ mod tests {
         account.deposit(Decimal::from_str("10.0").unwrap());
 
         assert_eq!(account.available, Decimal::from_str("10.0").unwrap());
-        assert_eq!(account.total(), Decimal::from_str("10.0").unwrap());
+        assert_eq!(account.total(), Some(Decimal::from_str("10.0").unwrap()));
 
         let result = account.withdraw(Decimal::from_str("5.0").unwrap());
         assert!(result);
@@ -111,6 +655,73 @@ mod tests {
         assert_eq!(account.available, Decimal::from_str("5.0").unwrap());
     }
 
+    #[test]
+    fn test_overdraft() {
+        let mut account = Account::with_overdraft(Decimal::from_str("50.0").unwrap());
+        account.deposit(Decimal::from_str("10.0").unwrap());
+
+        // Within the limit available goes negative
+        assert!(account.withdraw(Decimal::from_str("40.0").unwrap()));
+        assert_eq!(account.available, Decimal::from_str("-30.0").unwrap());
+
+        // Right up to the limit is fine, one step beyond it isn't
+        assert_eq!(
+            account.try_withdraw(Decimal::from_str("20.0001").unwrap()),
+            Err(WithdrawError::InsufficientFunds)
+        );
+        assert!(account.withdraw(Decimal::from_str("20.0").unwrap()));
+        assert_eq!(account.available, Decimal::from_str("-50.0").unwrap());
+        assert!(!account.withdraw(Decimal::from_str("0.0001").unwrap()));
+
+        let output = AccountOutput::from_account(ClientId(1), &account);
+        assert_eq!(output.available, Decimal::from_str("-50.0").unwrap());
+        assert_eq!(output.total, Decimal::from_str("-50.0").unwrap());
+    }
+
+    #[test]
+    fn test_withdraw_failure_reasons() {
+        let mut account = Account::new();
+        account.deposit(Decimal::from_str("5.0").unwrap());
+
+        assert_eq!(
+            account.try_withdraw(Decimal::from_str("10.0").unwrap()),
+            Err(WithdrawError::InsufficientFunds)
+        );
+
+        account.locked = true;
+        assert_eq!(
+            account.try_withdraw(Decimal::from_str("1.0").unwrap()),
+            Err(WithdrawError::Locked)
+        );
+        assert_eq!(account.available, Decimal::from_str("5.0").unwrap());
+    }
+
+    #[test]
+    fn test_withdraw_error_priority() {
+        let mut account = Account::new();
+        account.deposit(Decimal::from_str("5.0").unwrap());
+        account.locked = true;
+        let amount = Decimal::from_str("10.0").unwrap();
+
+        // Locked and insufficient at once
+        assert_eq!(account.try_withdraw(amount), Err(WithdrawError::Locked));
+        assert_eq!(
+            account.try_withdraw_with(amount, WithdrawErrorPriority::Locked),
+            Err(WithdrawError::Locked)
+        );
+        assert_eq!(
+            account.try_withdraw_with(amount, WithdrawErrorPriority::InsufficientFunds),
+            Err(WithdrawError::InsufficientFunds)
+        );
+
+        // Only locked, so the priority doesn't matter
+        assert_eq!(
+            account.try_withdraw_with(Decimal::ONE, WithdrawErrorPriority::InsufficientFunds),
+            Err(WithdrawError::Locked)
+        );
+        assert_eq!(account.available, Decimal::from_str("5.0").unwrap());
+    }
+
     #[test]
     fn test_hold_and_release_funds() {
         let mut account = Account::new();
@@ -119,7 +730,7 @@ mod tests {
         account.hold_funds(Decimal::from_str("3.0").unwrap());
         assert_eq!(account.available, Decimal::from_str("7.0").unwrap());
         assert_eq!(account.held, Decimal::from_str("3.0").unwrap());
-        assert_eq!(account.total(), Decimal::from_str("10.0").unwrap());
+        assert_eq!(account.total(), Some(Decimal::from_str("10.0").unwrap()));
 
         account.release_funds(Decimal::from_str("2.0").unwrap());
         assert_eq!(account.available, Decimal::from_str("9.0").unwrap());
@@ -136,7 +747,7 @@ mod tests {
 
         assert_eq!(account.available, Decimal::from_str("5.0").unwrap());
         assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total(), Decimal::from_str("5.0").unwrap());
+        assert_eq!(account.total(), Some(Decimal::from_str("5.0").unwrap()));
         assert!(account.locked);
     }
 
@@ -161,12 +772,119 @@ mod tests {
         account.deposit(Decimal::from_str("10.123456").unwrap());
         account.hold_funds(Decimal::from_str("2.5678").unwrap());
 
-        let output = AccountOutput::from_account(123, &account);
+        let output = AccountOutput::from_account(ClientId(123), &account);
 
-        assert_eq!(output.client, 123);
+        assert_eq!(output.client, ClientId(123));
         assert_eq!(output.available, Decimal::from_str("7.5557").unwrap());
         assert_eq!(output.held, Decimal::from_str("2.5678").unwrap());
         assert_eq!(output.total, Decimal::from_str("10.1235").unwrap());
         assert!(!output.locked);
     }
+
+    #[test]
+    fn test_total_policy_sum_of_rounded() {
+        let account = Account {
+            available: Decimal::from_str("1.00005").unwrap(),
+            held: Decimal::from_str("1.00005").unwrap(),
+            ..Account::default()
+        };
+
+        // Rounded independently the columns don't add up: 1.0000 + 1.0000 != 2.0001
+        let independent = AccountOutput::from_account(ClientId(1), &account);
+        assert_eq!(independent.total, Decimal::from_str("2.0001").unwrap());
+        assert_ne!(independent.available + independent.held, independent.total);
+
+        let config = OutputConfig {
+            total_policy: TotalPolicy::SumOfRounded,
+            ..OutputConfig::default()
+        };
+        let consistent = AccountOutput::from_account_with(ClientId(1), &account, &config);
+        assert_eq!(consistent.total, Decimal::from_str("2.0000").unwrap());
+        assert_eq!(consistent.available + consistent.held, consistent.total);
+    }
+
+    #[test]
+    fn test_total_overflow_is_flagged() {
+        let account = Account {
+            available: Decimal::MAX,
+            held: Decimal::MAX,
+            ..Account::default()
+        };
+        assert_eq!(account.total(), None);
+
+        for total_policy in [TotalPolicy::Independent, TotalPolicy::SumOfRounded] {
+            let config = OutputConfig {
+                total_policy,
+                ..OutputConfig::default()
+            };
+            let output = AccountOutput::from_account_with(ClientId(1), &account, &config);
+            assert!(output.total_overflow);
+            assert_eq!(output.total, Decimal::MAX);
+        }
+
+        // A correction can't be checked against a total that doesn't exist
+        let mut account = account;
+        assert!(!account.correct(Decimal::ONE, false));
+        assert!(account.correct(Decimal::NEGATIVE_ONE, true));
+    }
+
+    #[test]
+    fn test_zero_epsilon_snaps_tiny_balances() {
+        let account = Account {
+            available: Decimal::from_str("0.0002").unwrap(),
+            held: Decimal::from_str("0.002").unwrap(),
+            ..Account::default()
+        };
+
+        // Off by default, the residue is kept
+        let exact = AccountOutput::from_account(ClientId(1), &account);
+        assert_eq!(exact.available, Decimal::from_str("0.0002").unwrap());
+
+        let config = OutputConfig {
+            zero_epsilon: Some(Decimal::from_str("0.001").unwrap()),
+            ..OutputConfig::default()
+        };
+        let output = AccountOutput::from_account_with(ClientId(1), &account, &config);
+
+        // Under the epsilon: snapped to zero. Over it: untouched.
+        assert_eq!(output.available, Decimal::ZERO);
+        assert_eq!(output.held, Decimal::from_str("0.002").unwrap());
+        assert_eq!(output.total, Decimal::from_str("0.0022").unwrap());
+    }
+
+    #[test]
+    fn test_verbose_columns() {
+        let account = Account {
+            had_dispute: true,
+            ..Account::default()
+        };
+
+        let output = AccountOutput::from_account(ClientId(1), &account);
+        assert_eq!(output.had_dispute, None);
+
+        let config = OutputConfig {
+            verbose: true,
+            ..OutputConfig::default()
+        };
+        let output = AccountOutput::from_account_with(ClientId(1), &account, &config);
+        assert_eq!(output.had_dispute, Some(true));
+    }
+
+    #[test]
+    fn test_diff_columns_match_fields() {
+        let after = Account {
+            locked: true,
+            ..Account::new()
+        };
+        let diff = AccountDiff::between(ClientId(1), &Account::new(), &after).unwrap();
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.serialize(diff).unwrap();
+        let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+        // An empty diff output writes `COLUMNS` as its header, so it must match the rows'
+        assert_eq!(
+            csv.lines().next(),
+            Some(AccountDiff::COLUMNS.join(",").as_str())
+        );
+    }
 }
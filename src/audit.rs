@@ -0,0 +1,189 @@
+use crate::config::EngineConfig;
+use crate::engine::PaymentEngine;
+use crate::snapshot::Snapshot;
+use anyhow::Result;
+use rust_decimal::Decimal;
+use std::io::Write;
+
+/// The outcome of one audit check. Passed when nothing failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditCheck {
+    pub name: &'static str,
+    /// One line per account or transaction that failed the check.
+    pub failures: Vec<String>,
+}
+
+impl AuditCheck {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Runs every self-consistency check over a snapshot, in a fixed order. A snapshot saved by a
+/// healthy run passes them all, so any failure means a bookkeeping bug or an edited file.
+pub fn audit(snapshot: &Snapshot) -> Vec<AuditCheck> {
+    let check = |name, failures| AuditCheck { name, failures };
+    let engine = PaymentEngine::from_snapshot(snapshot.clone(), EngineConfig::default());
+
+    let held_consistency = match engine.verify_held_consistency() {
+        Ok(()) => Vec::new(),
+        Err(clients) => clients
+            .into_iter()
+            .map(|client| format!("client {client}: held doesn't match its open disputes"))
+            .collect(),
+    };
+
+    let totals = snapshot
+        .accounts
+        .iter()
+        .filter(|(_, account)| account.total().is_none())
+        .map(|(client, _)| format!("client {client}: available + held overflows"))
+        .collect();
+
+    let mut lock_state = Vec::new();
+    for (client, account) in &snapshot.accounts {
+        if !account.locked && account.lock_cause.is_some() {
+            lock_state.push(format!(
+                "client {client}: has a lock cause but isn't locked"
+            ));
+        }
+        if !account.locked && account.chargebacks > 0 {
+            lock_state.push(format!("client {client}: charged back but not locked"));
+        }
+    }
+
+    let mut dispute_state = Vec::new();
+    for (tx, stored) in &snapshot.transactions {
+        if stored.held < Decimal::ZERO || stored.held > stored.amount.abs() {
+            dispute_state.push(format!(
+                "tx {tx}: holds {} of an amount of {}",
+                stored.held, stored.amount
+            ));
+        }
+        if !stored.disputed && !stored.held.is_zero() {
+            dispute_state.push(format!("tx {tx}: holds funds without an open dispute"));
+        }
+        if stored.disputed && !stored.had_dispute {
+            dispute_state.push(format!("tx {tx}: disputed but never marked as such"));
+        }
+    }
+
+    let orphaned_transactions = snapshot
+        .transactions
+        .iter()
+        .filter(|(_, stored)| !snapshot.accounts.contains_key(&stored.client))
+        .map(|(tx, stored)| format!("tx {tx}: client {} has no account", stored.client))
+        .collect();
+
+    vec![
+        check("held_consistency", held_consistency),
+        check("totals", totals),
+        check("lock_state", lock_state),
+        check("dispute_state", dispute_state),
+        check("orphaned_transactions", orphaned_transactions),
+    ]
+}
+
+/// Writes one `<check>: pass` or `<check>: FAIL` line per check, each failure indented below
+/// its check.
+pub fn write_report<W: Write>(checks: &[AuditCheck], mut output: W) -> Result<()> {
+    for check in checks {
+        let result = if check.passed() { "pass" } else { "FAIL" };
+        writeln!(output, "{}: {result}", check.name)?;
+        for failure in &check.failures {
+            writeln!(output, "  {failure}")?;
+        }
+    }
+    output.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{ClientId, TransactionRecord, TransactionType, TxId};
+    use std::str::FromStr;
+
+    fn record(
+        tx_type: TransactionType,
+        client: u16,
+        tx: u32,
+        amount: Option<&str>,
+    ) -> TransactionRecord {
+        TransactionRecord {
+            tx_type,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount: amount.map(|a| Decimal::from_str(a).unwrap()),
+            memo: None,
+            timestamp: None,
+        }
+    }
+
+    fn snapshot() -> Snapshot {
+        let mut engine = PaymentEngine::new();
+        for record in [
+            record(TransactionType::Deposit, 1, 1, Some("10.0")),
+            record(TransactionType::Deposit, 1, 2, Some("5.0")),
+            record(TransactionType::Deposit, 2, 3, Some("7.0")),
+            record(TransactionType::Dispute, 1, 2, None),
+            record(TransactionType::Dispute, 2, 3, None),
+            record(TransactionType::Chargeback, 2, 3, None),
+        ] {
+            engine.process_transaction(record).unwrap();
+        }
+        engine.snapshot()
+    }
+
+    fn failed(checks: &[AuditCheck]) -> Vec<&'static str> {
+        checks
+            .iter()
+            .filter(|check| !check.passed())
+            .map(|check| check.name)
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_snapshot_passes() {
+        let checks = audit(&snapshot());
+        assert_eq!(checks.len(), 5);
+        assert!(failed(&checks).is_empty(), "{checks:?}");
+
+        let mut report = Vec::new();
+        write_report(&checks, &mut report).unwrap();
+        assert!(
+            String::from_utf8(report)
+                .unwrap()
+                .starts_with("held_consistency: pass\n")
+        );
+    }
+
+    #[test]
+    fn test_tampered_snapshot_fails() {
+        let mut snapshot = snapshot();
+        // Someone edited client 1's held funds, unlocked client 2 and moved tx 1 to a client
+        // that doesn't exist
+        snapshot.accounts.get_mut(&ClientId(1)).unwrap().held = Decimal::from(1);
+        snapshot.accounts.get_mut(&ClientId(2)).unwrap().locked = false;
+        snapshot.transactions.get_mut(&TxId(1)).unwrap().client = ClientId(9);
+
+        let checks = audit(&snapshot);
+        assert_eq!(
+            failed(&checks),
+            ["held_consistency", "lock_state", "orphaned_transactions"]
+        );
+        assert_eq!(
+            checks[0].failures,
+            ["client 1: held doesn't match its open disputes"]
+        );
+
+        let mut report = Vec::new();
+        write_report(&checks, &mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(
+            report.contains("lock_state: FAIL\n  client 2: has a lock cause"),
+            "{report}"
+        );
+        assert!(report.contains("totals: pass\n"), "{report}");
+    }
+}
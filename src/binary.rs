@@ -0,0 +1,311 @@
+use crate::reader::{RecordError, RecordSource};
+use crate::transaction::{ClientId, TransactionRecord, TransactionType, TxId, check_amount};
+use anyhow::{Context, Result};
+use rust_decimal::Decimal;
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+
+/// Opens every binary transaction file, followed by nothing but fixed-width records.
+pub const MAGIC: [u8; 4] = *b"TXB1";
+
+/// Type (1 byte), client (2), tx (4), amount present (1) and the amount in `Decimal`'s own
+/// 16 byte representation, integers little endian.
+pub const RECORD_SIZE: usize = 24;
+
+/// Type codes in the binary format. Custom types have no code and can't be written.
+const TYPES: [TransactionType; 9] = [
+    TransactionType::Deposit,
+    TransactionType::Withdrawal,
+    TransactionType::Dispute,
+    TransactionType::Resolve,
+    TransactionType::Chargeback,
+    TransactionType::Correction,
+    TransactionType::Freeze,
+    TransactionType::Unfreeze,
+    TransactionType::ChargebackReversal,
+];
+
+/// Reads the compact binary format, for pipelines where CSV parsing dominates the runtime.
+/// Records can be decoded without any text handling, but carry no memo or timestamp.
+pub struct BinaryReader<R: Read> {
+    input: BufReader<R>,
+    buffer: [u8; RECORD_SIZE],
+    done: bool,
+}
+
+impl<R: Read> BinaryReader<R> {
+    /// Checks the magic bytes up front. A completely empty input is just no transactions.
+    pub fn new(input: R) -> Result<Self> {
+        let mut input = BufReader::new(input);
+        let mut magic = [0; MAGIC.len()];
+        let read = read_full(&mut input, &mut magic).context("Failed to read binary header")?;
+
+        if read != 0 && magic != MAGIC {
+            anyhow::bail!("Not a binary transaction file");
+        }
+
+        Ok(Self {
+            input,
+            buffer: [0; RECORD_SIZE],
+            done: read == 0,
+        })
+    }
+}
+
+impl<R: Read> Iterator for BinaryReader<R> {
+    type Item = Result<TransactionRecord, RecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match read_full(&mut self.input, &mut self.buffer) {
+            Ok(0) => {
+                self.done = true;
+                None
+            }
+            Ok(RECORD_SIZE) => Some(decode(&self.buffer)),
+            // A cut off last record, nothing can follow it
+            Ok(_) => {
+                self.done = true;
+                Some(Err(io::Error::from(ErrorKind::UnexpectedEof).into()))
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e.into()))
+            }
+        }
+    }
+}
+
+impl<R: Read> RecordSource for BinaryReader<R> {}
+
+/// Like `read_exact`, but returns how much was read when the input ends early.
+fn read_full(input: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match input.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+fn decode(buffer: &[u8; RECORD_SIZE]) -> Result<TransactionRecord, RecordError> {
+    let tx_type = TYPES
+        .get(usize::from(buffer[0]))
+        .cloned()
+        .ok_or(RecordError::Field("type"))?;
+    let client = u16::from_le_bytes([buffer[1], buffer[2]]);
+    let tx = u32::from_le_bytes([buffer[3], buffer[4], buffer[5], buffer[6]]);
+
+    let amount = match buffer[7] {
+        0 => None,
+        1 => {
+            let mut bytes = [0; 16];
+            bytes.copy_from_slice(&buffer[8..]);
+            let amount = Decimal::deserialize(bytes);
+            Some(check_amount(amount, &amount.to_string())?)
+        }
+        _ => return Err(RecordError::Field("amount")),
+    };
+
+    Ok(TransactionRecord {
+        tx_type,
+        client: ClientId(client),
+        tx: TxId(tx),
+        amount,
+        memo: None,
+        timestamp: None,
+    })
+}
+
+/// Writes records in the binary format read by `BinaryReader`, e.g. to convert a CSV file once
+/// and ingest it many times.
+pub struct BinaryWriter<W: Write> {
+    output: BufWriter<W>,
+}
+
+impl<W: Write> BinaryWriter<W> {
+    pub fn new(output: W) -> Result<Self> {
+        let mut output = BufWriter::new(output);
+        output.write_all(&MAGIC).context("Failed to write output")?;
+        Ok(Self { output })
+    }
+
+    /// Fails on custom transaction types, which have no binary code. Memos and timestamps
+    /// are dropped.
+    pub fn write(&mut self, record: &TransactionRecord) -> Result<()> {
+        let code = TYPES
+            .iter()
+            .position(|tx_type| *tx_type == record.tx_type)
+            .with_context(|| format!("No binary code for type {}", record.tx_type.as_str()))?;
+
+        let mut buffer = [0; RECORD_SIZE];
+        buffer[0] = code as u8;
+        buffer[1..3].copy_from_slice(&u16::from(record.client).to_le_bytes());
+        buffer[3..7].copy_from_slice(&u32::from(record.tx).to_le_bytes());
+        if let Some(amount) = record.amount {
+            buffer[7] = 1;
+            buffer[8..].copy_from_slice(&amount.serialize());
+        }
+
+        self.output
+            .write_all(&buffer)
+            .context("Failed to write output")
+    }
+
+    pub fn finish(mut self) -> Result<W> {
+        self.output.flush().context("Failed to flush output")?;
+        self.output
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .context("Failed to flush output")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::{InputConfig, ParseMode, TransactionReader};
+    use std::str::FromStr;
+    use std::time::Instant;
+
+    fn record(
+        tx_type: TransactionType,
+        client: u16,
+        tx: u32,
+        amount: Option<&str>,
+    ) -> TransactionRecord {
+        TransactionRecord {
+            tx_type,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount: amount.map(|a| Decimal::from_str(a).unwrap()),
+            memo: None,
+            timestamp: None,
+        }
+    }
+
+    fn encode(records: &[TransactionRecord]) -> Vec<u8> {
+        let mut writer = BinaryWriter::new(Vec::new()).unwrap();
+        for record in records {
+            writer.write(record).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let records: Vec<_> = TYPES
+            .into_iter()
+            .enumerate()
+            .map(|(i, tx_type)| record(tx_type, i as u16, 4_000_000_000 + i as u32, None))
+            .chain([
+                record(
+                    TransactionType::Deposit,
+                    u16::MAX,
+                    1,
+                    Some("1.2345678901234567890123456789"),
+                ),
+                record(
+                    TransactionType::Correction,
+                    2,
+                    2,
+                    Some("-1000000000000000000"),
+                ),
+                record(TransactionType::Withdrawal, 3, 3, Some("0.0001")),
+            ])
+            .collect();
+
+        let bytes = encode(&records);
+        assert_eq!(bytes.len(), MAGIC.len() + records.len() * RECORD_SIZE);
+
+        let decoded: Vec<_> = BinaryReader::new(bytes.as_slice())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(decoded, records);
+        // Scale survives too, not just the value
+        assert_eq!(decoded[9].amount.unwrap().scale(), 28);
+    }
+
+    #[test]
+    fn test_bad_input() {
+        assert!(BinaryReader::new(&b"type,client,tx,amount\n"[..]).is_err());
+        assert_eq!(BinaryReader::new(&b""[..]).unwrap().count(), 0);
+
+        let custom = record(TransactionType::Custom("bonus".into()), 1, 1, None);
+        assert!(
+            BinaryWriter::new(Vec::new())
+                .unwrap()
+                .write(&custom)
+                .is_err()
+        );
+
+        let mut bytes = encode(&[
+            record(TransactionType::Deposit, 1, 1, Some("1.0")),
+            record(TransactionType::Deposit, 1, 2, Some("1.0")),
+        ]);
+        // An unknown type code only loses that record
+        bytes[MAGIC.len()] = 200;
+        // A truncated last record is an error, not silently dropped
+        bytes.truncate(bytes.len() - 1);
+
+        let results: Vec<_> = BinaryReader::new(bytes.as_slice()).unwrap().collect();
+        assert!(matches!(results[0], Err(RecordError::Field("type"))));
+        assert!(matches!(results[1], Err(RecordError::Io(_))));
+        assert_eq!(results.len(), 2);
+    }
+
+    /// Binary vs CSV ingestion:
+    /// `cargo test --release --features binary bench_binary_input -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_binary_input() {
+        let records: Vec<_> = (1..=1_000_000u32)
+            .map(|tx| {
+                let amount = format!("{}.{:04}", tx % 1000, tx % 10_000);
+                record(
+                    TransactionType::Deposit,
+                    (tx % 1000) as u16,
+                    tx,
+                    Some(&amount),
+                )
+            })
+            .collect();
+
+        let mut csv = String::from("type,client,tx,amount\n");
+        for r in &records {
+            csv.push_str(&format!(
+                "deposit,{},{},{}\n",
+                r.client,
+                r.tx,
+                r.amount.unwrap()
+            ));
+        }
+        let binary = encode(&records);
+
+        for parse_mode in [ParseMode::Serde, ParseMode::ByteRecord] {
+            let config = InputConfig {
+                parse_mode,
+                ..InputConfig::default()
+            };
+            let start = Instant::now();
+            let count = TransactionReader::new(csv.as_bytes(), &config)
+                .unwrap()
+                .count();
+            println!(
+                "csv {parse_mode:?}: {count} records in {:?}",
+                start.elapsed()
+            );
+        }
+
+        let start = Instant::now();
+        let count = BinaryReader::new(binary.as_slice()).unwrap().count();
+        println!("binary: {count} records in {:?}", start.elapsed());
+    }
+}
@@ -0,0 +1,651 @@
+use crate::account::BoolFormat;
+use crate::config::{
+    AccountCreation, CorrectionPolicy, CrossClientDisputePolicy, DecimalContext,
+    DisputeAmountPolicy, DisputeRatioAction, DisputeRatioGuard, DisputeSequencing, EngineConfig,
+    IngestionMode,
+};
+use crate::output::{self, DecimalFormat, OutputColumn, OutputFormat};
+use crate::reader::{self, InputConfig, InputFormat, ParseMode};
+use crate::transaction::{MAX_SCALE, PrecisionPolicy};
+use anyhow::{Context, Result};
+use rust_decimal::Decimal;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Command line options. Kept hand-rolled since we only have a handful of flags.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Path to the input CSV, or `-` for stdin. A SQLite database with `sqlite_table`.
+    pub input: String,
+    pub input_config: InputConfig,
+    pub input_format: InputFormat,
+    /// Read transactions from this table of a SQLite database instead of CSV.
+    pub sqlite_table: Option<String>,
+    /// Flush the output every N account rows instead of once at the end.
+    pub flush_every: Option<NonZeroUsize>,
+    /// Parse records on this many threads, applying them in input order.
+    pub parse_threads: Option<NonZeroUsize>,
+    /// Print a periodic progress line to stderr while reading.
+    pub progress: bool,
+    /// Skip records identical to the one right before them, as emitted by retrying feeds.
+    pub dedup: bool,
+    /// Abort once more than this many records have been rejected.
+    pub max_rejects: Option<u64>,
+    /// Abort on input that means the feed itself is broken, rather than skipping it.
+    pub strict: bool,
+    /// Stop reading once this much time has passed and output what was processed so far.
+    pub time_budget: Option<Duration>,
+    /// Start from a previously saved engine state instead of an empty one.
+    pub snapshot: Option<PathBuf>,
+    /// Save the engine state here once the input has been processed.
+    pub save_snapshot: Option<PathBuf>,
+    /// Write a JSON sidecar recording the input, record counts and generation time here.
+    pub metadata: Option<PathBuf>,
+    /// Append the accounts missing from this partially written CSV instead of writing to stdout.
+    pub resume: Option<PathBuf>,
+    /// Write the run summary to this path as JSON.
+    pub summary_json: Option<PathBuf>,
+    /// Send the run summary as JSON to this unix socket path, or `tcp:` address.
+    pub report_to: Option<String>,
+    /// Write the accounts idle for longer than `dormant_after` here as CSV.
+    pub dormancy_report: Option<PathBuf>,
+    /// Seconds without activity after which an account counts as dormant, measured against
+    /// the latest timestamp in the input.
+    pub dormant_after: Option<u64>,
+    /// Output only the changes relative to the loaded snapshot.
+    pub diff: bool,
+    /// Print the run summary instead of the account table.
+    pub stats_only: bool,
+    /// Only parse and check the records, reporting the invalid ones by line, without applying
+    /// any of them.
+    pub validate_only: bool,
+    /// Check the held funds bookkeeping before writing any output.
+    pub verify: bool,
+    /// Exit with a distinct code when the input holds no transactions at all.
+    pub fail_on_empty: bool,
+    /// Auto-resolve disputes still open at the end after more than this many further records.
+    pub dispute_timeout: Option<u64>,
+    /// Auto-charge back disputes still open at the end after more than this many further
+    /// records. Exclusive with `dispute_timeout`.
+    pub chargeback_timeout: Option<u64>,
+    /// Leave out account rows that fail to serialize instead of aborting the output.
+    pub skip_unwritable: bool,
+    /// Emit only these account columns, in this order.
+    pub output_columns: Option<Vec<OutputColumn>>,
+    pub output_format: OutputFormat,
+    /// Decimal precision and scale of the balance columns in Parquet output.
+    pub decimal_format: DecimalFormat,
+    pub engine: EngineConfig,
+}
+
+impl Options {
+    pub fn usage(program: &str) -> String {
+        format!(
+            "Usage: {program} [OPTIONS] <transactions.csv | ->
+       {program} audit <snapshot.json>
+
+Options:
+  --config <PATH>           Read options from a TOML file of flag = value pairs, with the
+                            command line overriding it
+  --no-<FLAG>               Drop FLAG from the --config file, e.g. to turn a switch off
+  --no-header               The input has no header row
+  --columns <SPEC>          Column order for --no-header input [default: type,client,tx,amount]
+  --round-excess-precision  Round amounts beyond 28 decimal places instead of dropping them
+  --fast-parse              Parse rows from byte records instead of through serde
+  --delimiter <CHAR>        Input field delimiter [default: ,]
+  --amount-locale <LOCALE>  Amounts are written as us (1234.50) or eu (1.234,50) [default: us]
+  --trim <MODE>             Strip whitespace from all, headers, fields or none [default: all]
+  --max-record-size <BYTES> Skip input lines longer than BYTES
+  --lenient-amounts         Accept digit grouping in amounts, e.g. 1,000.50
+  --grouping-separator <C>  Digit grouping character for --lenient-amounts [default: ,]
+  --parse-threads <N>       Parse records on N threads, still applying them in input order
+  --input-format <FORMAT>   Read csv or binary input (binary feature) [default: csv]
+  --sqlite-table <TABLE>    Read TABLE of the SQLite database at the input path (sqlite feature)
+  --flush-every <N>         Flush the output every N account rows
+  --dedup                   Skip records identical to the record right before them
+  --progress                Print periodic progress (records, elapsed, rate) to stderr
+  --max-rejects <N>         Abort once more than N records have been rejected
+  --strict                  Abort on an unparseable amount, or a resolve or chargeback
+                            without an open dispute
+  --time-budget <SECONDS>   Stop reading after SECONDS and output the partial results
+  --snapshot <PATH>         Load the initial engine state from a snapshot
+  --save-snapshot <PATH>    Save the final engine state as a snapshot
+  --metadata <PATH>         Write run provenance (input, counts, time) to PATH as JSON
+  --resume <PATH>           Finish a partial CSV output at PATH, adding only accounts not in it
+  --summary-json <PATH>     Write the run summary (every counter and total) to PATH as JSON
+  --report-to <SOCKET>      Send the run summary as JSON to a unix socket or tcp:<ADDRESS>
+                            (report feature)
+  --dormancy-report <PATH>  Write the accounts idle for over --dormant-after to PATH as CSV
+  --dormant-after <SECONDS> Idle time making an account dormant, from the timestamp column
+  --disputes-only           Only apply dispute, resolve and chargeback records
+  --diff                    Output per-account changes relative to --snapshot
+  --stats-only              Print the run summary instead of the account table
+  --validate-only           Only report invalid records by line, without applying any
+  --amount-histogram        Add deposit and withdrawal amount histograms to the summary
+  --verify                  Fail if any account's held funds don't match its open disputes
+  --fail-on-empty           Exit with code 2 if the input holds no transactions
+  --verbose                 Add extra per-account columns (had_dispute, memo)
+  --hide-unfunded           Leave out accounts whose balances never changed
+  --net-flow                Add lifetime deposited and withdrawn columns
+  --closed-status           Add a closed column, set on accounts a chargeback locked at zero
+  --skip-unwritable         Leave out account rows that fail to serialize instead of aborting
+  --fixed-decimals          Always write amounts with four decimal places, e.g. 5.0000
+  --total-policy <POLICY>   Round total on its own (independent, default) or as the sum of
+                            the rounded available and held (sum-of-rounded)
+  --bool-format <FORMAT>    Write locked and closed as true/false (default), 1/0 or yes/no
+  --output-columns <SPEC>   Account columns to emit and their order, e.g. client,total,locked
+  --format <FORMAT>         csv, json, or parquet (parquet feature) [default: csv]
+  --decimal <P,S>           Precision and scale of Parquet balance columns [default: 38,4]
+  --deposit-first           Only deposits open accounts, other records for new clients are rejected
+  --max-accounts <N>        Reject records for new clients once N accounts exist
+  --withdrawal-fee <FEE>    Charge a flat (0.5) or percentage (1.5%) fee on withdrawals
+  --fee-account <CLIENT>    Credit collected withdrawal fees to this client
+  --max-held <AMOUNT>       Reject disputes that would hold more than AMOUNT on an account
+  --max-digits <N>          Reject records leaving a balance with more than N significant digits
+  --round-max-digits <N>    Round amounts instead, so balances keep N significant digits
+  --working-scale <N>       Round amounts and balances to N decimal places as they're applied
+  --zero-epsilon <AMOUNT>   Output balances smaller than AMOUNT as zero
+  --check-dispute-amounts   Warn when a dispute's amount differs from the disputed tx
+  --partial-disputes        Dispute/resolve/chargeback amounts only apply to that portion of a tx
+  --reject-cross-client     Reject disputes of another client's tx, flagging the disputer
+  --max-dispute-ratio <R>   Flag accounts with more than R disputes per deposit, e.g. 0.2
+  --dispute-ratio-freeze    Also freeze accounts flagged by --max-dispute-ratio
+  --dispute-window <N|Ns>   Reject disputes over N records (or N seconds) after their tx
+  --dispute-timeout <N>     Resolve disputes still open after N further records at the end
+  --chargeback-timeout <N>  Charge back disputes still open after N further records instead
+  --unbounded-corrections   Allow corrections to take an account's total below zero"
+        )
+    }
+
+    /// Parses the full argument list, including the program name in first position. Options
+    /// from a `--config` file are applied first, for the command line to replace.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self> {
+        let mut args = args.into_iter();
+        let program = args.next().unwrap_or_else(|| "tx-processor".to_string());
+        let mut args: Vec<String> = args.collect();
+
+        let mut parser = Parser::new(&program);
+        if let Some((path, from_file)) = take_config_file(&mut args)? {
+            parser.apply(from_file)?;
+            if let Some(value) = parser.input.take() {
+                anyhow::bail!("{path}: stray value {value:?}, only flags taking a value have one");
+            }
+        }
+        parser.apply(args)?;
+        parser.finish()
+    }
+}
+
+/// Options as they build up over the config file and then the command line, with what can only
+/// be checked once both are in.
+struct Parser<'a> {
+    program: &'a str,
+    options: Options,
+    input: Option<String>,
+    lenient_amounts: bool,
+    grouping_separator: Option<char>,
+    max_dispute_ratio: Option<Decimal>,
+    dispute_ratio_action: DisputeRatioAction,
+}
+
+impl<'a> Parser<'a> {
+    fn new(program: &'a str) -> Self {
+        Self {
+            program,
+            options: Options::default(),
+            input: None,
+            lenient_amounts: false,
+            grouping_separator: None,
+            max_dispute_ratio: None,
+            dispute_ratio_action: DisputeRatioAction::default(),
+        }
+    }
+
+    /// Applies one source's flags over what earlier sources set.
+    fn apply(&mut self, args: Vec<String>) -> Result<()> {
+        let mut args = args.into_iter();
+        let options = &mut self.options;
+        // One source can't give both, but the command line may replace the file's choice
+        let mut dispute_timeout = false;
+        let mut chargeback_timeout = false;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--no-header" => options.input_config.has_headers = false,
+                "--columns" => {
+                    let spec: String = value(&mut args, &arg)?;
+                    options.input_config.columns = reader::parse_columns(&spec)?;
+                }
+                "--round-excess-precision" => {
+                    options.input_config.precision = PrecisionPolicy::Round
+                }
+                "--fast-parse" => options.input_config.parse_mode = ParseMode::ByteRecord,
+                "--delimiter" => {
+                    let delimiter: char = value(&mut args, &arg)?;
+                    if !delimiter.is_ascii() {
+                        anyhow::bail!("--delimiter must be a single ASCII character");
+                    }
+                    options.input_config.delimiter = delimiter as u8;
+                }
+                "--amount-locale" => {
+                    let locale: String = value(&mut args, &arg)?;
+                    options.input_config.amount_format = reader::parse_amount_locale(&locale)?;
+                }
+                "--trim" => {
+                    let mode: String = value(&mut args, &arg)?;
+                    options.input_config.trim = reader::parse_trim(&mode)?;
+                }
+                "--max-record-size" => {
+                    options.input_config.max_record_size = Some(value(&mut args, &arg)?)
+                }
+                "--lenient-amounts" => self.lenient_amounts = true,
+                "--grouping-separator" => self.grouping_separator = Some(value(&mut args, &arg)?),
+                "--parse-threads" => options.parse_threads = Some(value(&mut args, &arg)?),
+                "--input-format" => {
+                    options.input_format = value::<String>(&mut args, &arg)?.parse()?
+                }
+                "--sqlite-table" => options.sqlite_table = Some(value(&mut args, &arg)?),
+                "--flush-every" => options.flush_every = Some(value(&mut args, &arg)?),
+                "--progress" => options.progress = true,
+                "--dedup" => options.dedup = true,
+                "--time-budget" => {
+                    let seconds: f64 = value(&mut args, &arg)?;
+                    let budget = Duration::try_from_secs_f64(seconds)
+                        .context("--time-budget must be a non-negative number of seconds")?;
+                    options.time_budget = Some(budget);
+                }
+                "--max-rejects" => options.max_rejects = Some(value(&mut args, &arg)?),
+                "--strict" => {
+                    options.strict = true;
+                    options.engine.dispute_sequencing = DisputeSequencing::Strict;
+                }
+                "--snapshot" => options.snapshot = Some(value(&mut args, &arg)?),
+                "--metadata" => options.metadata = Some(value(&mut args, &arg)?),
+                "--resume" => options.resume = Some(value(&mut args, &arg)?),
+                "--summary-json" => options.summary_json = Some(value(&mut args, &arg)?),
+                "--report-to" => options.report_to = Some(value(&mut args, &arg)?),
+                "--dormancy-report" => options.dormancy_report = Some(value(&mut args, &arg)?),
+                "--dormant-after" => options.dormant_after = Some(value(&mut args, &arg)?),
+                "--save-snapshot" => options.save_snapshot = Some(value(&mut args, &arg)?),
+                "--diff" => options.diff = true,
+                "--stats-only" => options.stats_only = true,
+                "--validate-only" => options.validate_only = true,
+                "--amount-histogram" => options.engine.amount_histogram = true,
+                "--verify" => options.verify = true,
+                "--fail-on-empty" => options.fail_on_empty = true,
+                "--verbose" => options.engine.output.verbose = true,
+                "--hide-unfunded" => options.engine.output.hide_unfunded = true,
+                "--net-flow" => options.engine.output.net_flow = true,
+                "--closed-status" => options.engine.output.closed_status = true,
+                "--skip-unwritable" => options.skip_unwritable = true,
+                "--fixed-decimals" => options.engine.output.fixed_decimals = true,
+                "--total-policy" => {
+                    options.engine.output.total_policy =
+                        value::<String>(&mut args, &arg)?.parse()?
+                }
+                "--bool-format" => {
+                    options.engine.output.bool_format = value::<String>(&mut args, &arg)?.parse()?
+                }
+                "--output-columns" => {
+                    let spec: String = value(&mut args, &arg)?;
+                    options.output_columns = Some(output::parse_output_columns(&spec)?);
+                }
+                "--format" | "--output-format" => {
+                    options.output_format = value::<String>(&mut args, &arg)?.parse()?
+                }
+                "--decimal" => {
+                    options.decimal_format = value::<String>(&mut args, &arg)?.parse()?
+                }
+                "--disputes-only" => options.engine.ingestion = IngestionMode::DisputesOnly,
+                "--deposit-first" => {
+                    options.engine.account_creation = AccountCreation::DepositFirst
+                }
+                "--max-accounts" => options.engine.max_accounts = Some(value(&mut args, &arg)?),
+                "--withdrawal-fee" => {
+                    options.engine.withdrawal_fee = Some(value::<String>(&mut args, &arg)?.parse()?)
+                }
+                "--fee-account" => options.engine.fee_account = Some(value(&mut args, &arg)?),
+                "--max-held" => options.engine.max_held = Some(value(&mut args, &arg)?),
+                "--check-dispute-amounts" => {
+                    options.engine.dispute_amounts = DisputeAmountPolicy::CrossCheck
+                }
+                "--partial-disputes" => options.engine.partial_disputes = true,
+                "--reject-cross-client" => {
+                    options.engine.cross_client_disputes = CrossClientDisputePolicy::Reject
+                }
+                "--max-dispute-ratio" => self.max_dispute_ratio = Some(value(&mut args, &arg)?),
+                "--dispute-ratio-freeze" => self.dispute_ratio_action = DisputeRatioAction::Freeze,
+                "--dispute-window" => {
+                    options.engine.dispute_window = Some(value::<String>(&mut args, &arg)?.parse()?)
+                }
+                "--dispute-timeout" => {
+                    options.dispute_timeout = Some(value(&mut args, &arg)?);
+                    options.chargeback_timeout = None;
+                    dispute_timeout = true;
+                }
+                "--chargeback-timeout" => {
+                    options.chargeback_timeout = Some(value(&mut args, &arg)?);
+                    options.dispute_timeout = None;
+                    chargeback_timeout = true;
+                }
+                "--unbounded-corrections" => {
+                    options.engine.corrections = CorrectionPolicy::Unbounded
+                }
+                "--max-digits" | "--round-max-digits" => {
+                    let max_digits: u32 = value(&mut args, &arg)?;
+                    if !(1..=MAX_SCALE as u32).contains(&max_digits) {
+                        anyhow::bail!("{arg} must be between 1 and {MAX_SCALE}");
+                    }
+                    let policy = match arg.as_str() {
+                        "--max-digits" => PrecisionPolicy::Reject,
+                        _ => PrecisionPolicy::Round,
+                    };
+                    options.engine.decimal_context = Some(DecimalContext { max_digits, policy });
+                }
+                "--working-scale" => {
+                    let scale: u32 = value(&mut args, &arg)?;
+                    if scale as usize > MAX_SCALE {
+                        anyhow::bail!("--working-scale can be at most {MAX_SCALE}");
+                    }
+                    options.engine.working_scale = Some(scale);
+                }
+                "--zero-epsilon" => {
+                    options.engine.output.zero_epsilon = Some(value(&mut args, &arg)?)
+                }
+                flag if flag.starts_with("--") => {
+                    anyhow::bail!("Unknown option: {flag}\n{}", Options::usage(self.program))
+                }
+                _ if self.input.is_none() => self.input = Some(arg),
+                _ => anyhow::bail!(Options::usage(self.program)),
+            }
+        }
+
+        if dispute_timeout && chargeback_timeout {
+            anyhow::bail!("--dispute-timeout and --chargeback-timeout are mutually exclusive");
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Options> {
+        let options = &mut self.options;
+        match (self.lenient_amounts, self.grouping_separator) {
+            (true, separator) => {
+                options.input_config.grouping_separator = Some(separator.unwrap_or(','))
+            }
+            (false, Some(_)) => anyhow::bail!("--grouping-separator requires --lenient-amounts"),
+            (false, None) => {}
+        }
+
+        match (self.max_dispute_ratio, self.dispute_ratio_action) {
+            (Some(max_ratio), action) => {
+                options.engine.dispute_ratio = Some(DisputeRatioGuard { max_ratio, action })
+            }
+            (None, DisputeRatioAction::Freeze) => {
+                anyhow::bail!("--dispute-ratio-freeze requires --max-dispute-ratio")
+            }
+            (None, DisputeRatioAction::Warn) => {}
+        }
+
+        if options.dormancy_report.is_some() != options.dormant_after.is_some() {
+            anyhow::bail!("--dormancy-report and --dormant-after go together");
+        }
+
+        if options.diff && options.snapshot.is_none() {
+            anyhow::bail!("--diff requires a --snapshot to compare against");
+        }
+
+        if options.output_format != OutputFormat::Csv
+            && (options.diff || options.output_columns.is_some())
+        {
+            anyhow::bail!("--diff and --output-columns are only supported with CSV output");
+        }
+
+        if options.resume.is_some()
+            && (options.output_format != OutputFormat::Csv
+                || options.diff
+                || options.stats_only
+                || options.output_columns.is_some())
+        {
+            anyhow::bail!("--resume only works with the standard CSV account table");
+        }
+
+        if options.output_format != OutputFormat::Csv
+            && options.engine.output.bool_format != BoolFormat::default()
+        {
+            anyhow::bail!("--bool-format is only supported with CSV output");
+        }
+
+        if options.output_format != OutputFormat::Csv && options.skip_unwritable {
+            anyhow::bail!("--skip-unwritable is only supported with CSV output");
+        }
+
+        self.options.input = self
+            .input
+            .ok_or_else(|| anyhow::anyhow!(Options::usage(self.program)))?;
+        Ok(self.options)
+    }
+}
+
+/// Takes `--config <PATH>` out of the command line and turns the file into flags of its own,
+/// returned along with the path. Each key is a flag without its dashes: `true` turns a switch
+/// on, `false` leaves it off, anything else is the flag's value. A `--no-<key>` on the command
+/// line drops that key from the file, which is how a switch the file turns on is turned off.
+fn take_config_file(args: &mut Vec<String>) -> Result<Option<(String, Vec<String>)>> {
+    let Some(position) = args.iter().position(|arg| arg == "--config") else {
+        return Ok(None);
+    };
+    let path = args
+        .get(position + 1)
+        .context("Missing value for --config")?
+        .clone();
+    args.drain(position..position + 2);
+
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read config file: {path}"))?;
+    let mut table: toml::Table = content
+        .parse()
+        .with_context(|| format!("Invalid config file: {path}"))?;
+
+    // A `--no-<key>` cancels out with the file's key, anything else stays on the command line
+    args.retain(|arg| {
+        arg.strip_prefix("--no-")
+            .is_none_or(|key| table.remove(key).is_none())
+    });
+
+    let mut from_file = Vec::new();
+    for (key, value) in table {
+        if key == "config" {
+            anyhow::bail!("{path}: config files can't include other config files");
+        }
+        let flag = format!("--{key}");
+        match value {
+            toml::Value::Boolean(true) => from_file.push(flag),
+            toml::Value::Boolean(false) => {}
+            toml::Value::String(value) => from_file.extend([flag, value]),
+            toml::Value::Integer(value) => from_file.extend([flag, value.to_string()]),
+            toml::Value::Float(value) => from_file.extend([flag, value.to_string()]),
+            other => anyhow::bail!("{path}: unsupported {} value for {key}", other.type_str()),
+        }
+    }
+
+    Ok(Some((path, from_file)))
+}
+
+/// Takes and parses the value following `flag`.
+fn value<T>(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let raw = args
+        .next()
+        .with_context(|| format!("Missing value for {flag}"))?;
+    raw.parse()
+        .with_context(|| format!("Invalid value for {flag}: {raw}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::TotalPolicy;
+    use crate::config::{DisputeWindow, WithdrawalFee};
+
+    fn parse(args: &[&str]) -> Result<Options> {
+        Options::parse(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn test_parse_input_only() {
+        let options = parse(&["tx-processor", "input.csv"]).unwrap();
+        assert_eq!(options.input, "input.csv");
+        assert_eq!(options.input_config.precision, PrecisionPolicy::Reject);
+    }
+
+    #[test]
+    fn test_parse_flags() {
+        let options = parse(&[
+            "tx-processor",
+            "--round-excess-precision",
+            "--flush-every",
+            "100",
+            "--snapshot",
+            "state.json",
+            "--disputes-only",
+            "-",
+        ])
+        .unwrap();
+        assert_eq!(options.input, "-");
+        assert_eq!(options.input_config.precision, PrecisionPolicy::Round);
+        assert_eq!(options.flush_every, NonZeroUsize::new(100));
+        assert_eq!(options.snapshot, Some(PathBuf::from("state.json")));
+        assert_eq!(options.engine.ingestion, IngestionMode::DisputesOnly);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse(&["tx-processor"]).is_err());
+        assert!(parse(&["tx-processor", "a.csv", "b.csv"]).is_err());
+        assert!(parse(&["tx-processor", "--bogus", "a.csv"]).is_err());
+        assert!(parse(&["tx-processor", "a.csv", "--flush-every"]).is_err());
+        assert!(parse(&["tx-processor", "--flush-every", "0", "a.csv"]).is_err());
+        assert!(parse(&["tx-processor", "--diff", "a.csv"]).is_err());
+        assert!(parse(&["tx-processor", "--dispute-window", "1d", "a.csv"]).is_err());
+        assert!(
+            parse(&[
+                "tx-processor",
+                "--format",
+                "json",
+                "--skip-unwritable",
+                "a.csv"
+            ])
+            .is_err()
+        );
+        assert!(
+            parse(&[
+                "tx-processor",
+                "--dispute-timeout",
+                "10",
+                "--chargeback-timeout",
+                "10",
+                "a.csv"
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_total_policy() {
+        let policy = |args: &[&str]| parse(args).unwrap().engine.output.total_policy;
+        assert_eq!(policy(&["tx-processor", "a.csv"]), TotalPolicy::Independent);
+        assert_eq!(
+            policy(&["tx-processor", "--total-policy", "sum-of-rounded", "a.csv"]),
+            TotalPolicy::SumOfRounded
+        );
+        assert!(parse(&["tx-processor", "--total-policy", "sum", "a.csv"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_dispute_window() {
+        let window = |spec| {
+            parse(&["tx-processor", "--dispute-window", spec, "a.csv"])
+                .unwrap()
+                .engine
+                .dispute_window
+        };
+        assert_eq!(window("500"), Some(DisputeWindow::Records(500)));
+        assert_eq!(window("86400s"), Some(DisputeWindow::Seconds(86_400)));
+    }
+
+    #[test]
+    fn test_parse_config_file() {
+        let path = std::env::temp_dir().join(format!("config-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "partial-disputes = true\n\
+             verbose = false\n\
+             max-held = \"100.5\"\n\
+             max-accounts = 10\n\
+             withdrawal-fee = \"1.5%\"\n\
+             dispute-window = \"86400s\"\n",
+        )
+        .unwrap();
+        let config = path.to_str().unwrap();
+
+        let options = parse(&["tx-processor", "--config", config, "a.csv"]);
+        let overridden = parse(&[
+            "tx-processor",
+            "--max-accounts",
+            "20",
+            "--config",
+            config,
+            "a.csv",
+        ]);
+        std::fs::write(&path, "dispute-timeout = 10\nverbose = true\n").unwrap();
+        let replaced = parse(&[
+            "tx-processor",
+            "--config",
+            config,
+            "--chargeback-timeout",
+            "5",
+            "--no-verbose",
+            "a.csv",
+        ]);
+        let unset = parse(&["tx-processor", "--config", config, "--no-max-held", "a.csv"]);
+        std::fs::write(&path, "verbose = [true]\n").unwrap();
+        let unsupported = parse(&["tx-processor", "--config", config, "a.csv"]);
+        std::fs::write(&path, "bogus = true\n").unwrap();
+        let unknown = parse(&["tx-processor", "--config", config, "a.csv"]);
+        std::fs::remove_file(&path).unwrap();
+
+        let engine = options.unwrap().engine;
+        assert!(engine.partial_disputes);
+        assert!(!engine.output.verbose);
+        assert_eq!(engine.max_held, Some(Decimal::from_str("100.5").unwrap()));
+        assert_eq!(engine.max_accounts, Some(10));
+        assert_eq!(
+            engine.withdrawal_fee,
+            Some(WithdrawalFee::Percent(Decimal::from_str("1.5").unwrap()))
+        );
+        assert_eq!(engine.dispute_window, Some(DisputeWindow::Seconds(86_400)));
+
+        // The command line wins, wherever it comes relative to --config
+        let overridden = overridden.unwrap();
+        assert_eq!(overridden.engine.max_accounts, Some(20));
+        assert_eq!(overridden.engine.max_held, engine.max_held);
+        assert_eq!(overridden.input, "a.csv");
+
+        // Even where the file's choice excludes the command line's, or is a switch
+        let replaced = replaced.unwrap();
+        assert_eq!(replaced.dispute_timeout, None);
+        assert_eq!(replaced.chargeback_timeout, Some(5));
+        assert!(!replaced.engine.output.verbose);
+        // Only keys the file has can be dropped
+        assert!(unset.is_err());
+
+        assert!(unsupported.is_err());
+        assert!(unknown.is_err());
+        assert!(parse(&["tx-processor", "--config", "/nonexistent.toml", "a.csv"]).is_err());
+    }
+}
@@ -0,0 +1,240 @@
+use crate::account::{OutputConfig, WithdrawErrorPriority};
+use crate::transaction::{ClientId, PrecisionPolicy, StoredTransaction};
+use anyhow::Context;
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+/// Which records the engine applies.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IngestionMode {
+    #[default]
+    All,
+    /// Only apply dispute, resolve and chargeback records, skipping deposits and withdrawals.
+    /// Meant for replaying the dispute workflow against state loaded from a snapshot.
+    DisputesOnly,
+}
+
+/// What to do with an amount carried on a dispute record.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DisputeAmountPolicy {
+    /// The amount is ignored, the stored transaction's amount is always used.
+    #[default]
+    Ignore,
+    /// Compare it with the stored transaction's amount and count mismatches, which usually
+    /// point at an upstream bug. The dispute still goes ahead with the stored amount, or with
+    /// partial disputes the portion it names, in which case only a larger amount is a mismatch.
+    CrossCheck,
+}
+
+/// How old a transaction may get and still be disputed, like a card scheme's chargeback time
+/// limit. Disputes of older transactions are rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeWindow {
+    /// At most this many records after the one that stored the transaction.
+    Records(u64),
+    /// At most this many seconds after it, going by the `timestamp` column. Disputes or
+    /// transactions without a timestamp aren't limited.
+    Seconds(u64),
+}
+
+impl DisputeWindow {
+    /// Whether a dispute at `sequence` and `timestamp` comes too late for `tx`.
+    pub fn closed(self, tx: &StoredTransaction, sequence: u64, timestamp: Option<u64>) -> bool {
+        match self {
+            DisputeWindow::Records(max) => sequence.saturating_sub(tx.stored_at) > max,
+            DisputeWindow::Seconds(max) => timestamp
+                .zip(tx.timestamp)
+                .is_some_and(|(now, stored)| now.saturating_sub(stored) > max),
+        }
+    }
+}
+
+impl FromStr for DisputeWindow {
+    type Err = anyhow::Error;
+
+    /// Parses `500` as a number of records and `86400s` as seconds.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim();
+        let window = match s.strip_suffix('s') {
+            Some(secs) => secs.trim().parse().map(DisputeWindow::Seconds),
+            None => s.parse().map(DisputeWindow::Records),
+        };
+        window.with_context(|| format!("Invalid dispute window {s:?}"))
+    }
+}
+
+/// What happens to an account whose disputes outnumber the allowed share of its deposits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DisputeRatioAction {
+    /// Flag and count it, and warn at the end of the run.
+    #[default]
+    Warn,
+    /// Flag it and freeze it too, pending review.
+    Freeze,
+}
+
+/// A fraud heuristic: legitimate accounts dispute only a small share of their deposits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisputeRatioGuard {
+    /// Disputes allowed per deposit, e.g. `0.2` for one in five.
+    pub max_ratio: Decimal,
+    pub action: DisputeRatioAction,
+}
+
+impl DisputeRatioGuard {
+    pub fn breached(self, disputes: u64, deposits: u64) -> bool {
+        Decimal::from(disputes) > self.max_ratio * Decimal::from(deposits)
+    }
+}
+
+/// What to do with a dispute naming a transaction that belongs to another client.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CrossClientDisputePolicy {
+    /// Ignore it, like any dispute that doesn't match a stored transaction.
+    #[default]
+    Ignore,
+    /// Reject it and flag the disputing client's account as suspicious, since disputing
+    /// someone else's transaction may be a fraud attempt.
+    Reject,
+}
+
+/// What to make of a resolve or chargeback that doesn't follow an open dispute.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DisputeSequencing {
+    /// Ignore it, counting repeated resolves as duplicates.
+    #[default]
+    Lenient,
+    /// Reject it with a `SequenceError`, for pipelines where it means the dispute events
+    /// arrived corrupted or out of order.
+    Strict,
+}
+
+/// How far a `correction` may take an account down.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CorrectionPolicy {
+    /// Refuse a negative correction that would leave the account's total below zero.
+    #[default]
+    NonNegativeTotal,
+    /// Apply any correction, for ledgers where negative balances are legitimate.
+    Unbounded,
+}
+
+/// Which records may open an account for a client not seen before.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AccountCreation {
+    /// Any record does, so e.g. a failed withdrawal still leaves an empty account in the output.
+    #[default]
+    AnyTransaction,
+    /// Only a deposit does. Other records for unknown clients are rejected and counted.
+    DepositFirst,
+}
+
+/// A fee charged on top of every successful withdrawal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WithdrawalFee {
+    Flat(Decimal),
+    /// A percentage of the withdrawn amount, e.g. `1.5` for 1.5%.
+    Percent(Decimal),
+}
+
+impl WithdrawalFee {
+    pub fn fee_for(self, amount: Decimal) -> Decimal {
+        match self {
+            WithdrawalFee::Flat(fee) => fee,
+            WithdrawalFee::Percent(percent) => amount * percent / Decimal::ONE_HUNDRED,
+        }
+    }
+}
+
+impl FromStr for WithdrawalFee {
+    type Err = anyhow::Error;
+
+    /// Parses `0.5` as a flat fee and `1.5%` as a percentage.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim();
+        let (raw, percent) = match s.strip_suffix('%') {
+            Some(raw) => (raw, true),
+            None => (s, false),
+        };
+
+        let value = Decimal::from_str(raw.trim()).with_context(|| format!("Invalid fee {s:?}"))?;
+        if value < Decimal::ZERO {
+            anyhow::bail!("Fee can't be negative: {s}");
+        }
+
+        Ok(if percent {
+            WithdrawalFee::Percent(value)
+        } else {
+            WithdrawalFee::Flat(value)
+        })
+    }
+}
+
+/// Caps the significant digits of account balances. `Decimal` itself holds 28-29 digits and
+/// silently rounds beyond that, e.g. when a tiny amount lands on a huge balance. A tighter
+/// context turns that into an explicit, deterministic choice: reject the record, or round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecimalContext {
+    /// Significant digits a balance may have, counting both sides of the decimal point.
+    pub max_digits: u32,
+    /// `Reject` refuses a record that would leave a balance with more digits. `Round` rounds a
+    /// deposit, withdrawal or correction amount on the way in, so the available balance it
+    /// lands in fits, and refuses whatever still doesn't fit after that.
+    pub policy: PrecisionPolicy,
+}
+
+impl DecimalContext {
+    /// Whether the value has at most `max_digits` significant digits.
+    pub fn fits(self, value: Decimal) -> bool {
+        let mantissa = value.normalize().mantissa().unsigned_abs();
+        mantissa.checked_ilog10().map_or(0, |log| log + 1) <= self.max_digits
+    }
+
+    /// Rounds to `max_digits` significant digits, leaving values that fit untouched.
+    pub fn round(self, value: Decimal) -> Decimal {
+        if self.fits(value) {
+            return value;
+        }
+        value.round_sf(self.max_digits).unwrap_or(value)
+    }
+}
+
+/// Policies consumed by `PaymentEngine`. The defaults reproduce the original behaviour.
+#[derive(Debug, Clone, Default)]
+pub struct EngineConfig {
+    pub output: OutputConfig,
+    pub ingestion: IngestionMode,
+    /// Disputes that would push an account's held funds above this are rejected.
+    /// `None` means unlimited.
+    pub max_held: Option<Decimal>,
+    pub dispute_amounts: DisputeAmountPolicy,
+    /// A dispute carrying an amount only holds that portion of the disputed transaction.
+    /// Likewise, resolves and chargebacks carrying an amount only settle that much of what's
+    /// still held, so they can be interleaved on the same transaction.
+    pub partial_disputes: bool,
+    pub cross_client_disputes: CrossClientDisputePolicy,
+    pub dispute_sequencing: DisputeSequencing,
+    pub dispute_window: Option<DisputeWindow>,
+    pub dispute_ratio: Option<DisputeRatioGuard>,
+    pub corrections: CorrectionPolicy,
+    /// Once this many accounts exist, records for new clients are rejected, bounding memory
+    /// on pathological inputs. Existing accounts keep working. `None` means unlimited.
+    pub max_accounts: Option<usize>,
+    pub account_creation: AccountCreation,
+    pub withdrawal_fee: Option<WithdrawalFee>,
+    pub withdraw_error_priority: WithdrawErrorPriority,
+    /// Collected fees are credited to this account, even while it is locked, on top of being
+    /// totalled in the summary.
+    pub fee_account: Option<ClientId>,
+    /// Round amounts to this many decimal places on the way in and keep balances at exactly
+    /// this scale, bounding their precision. Amounts that round to zero are rejected. It isn't
+    /// a speedup, the rounding costs more than it saves, see `bench_working_scale`. `None`
+    /// keeps exact values at whatever scale they end up at.
+    pub working_scale: Option<u32>,
+    /// Caps the significant digits of balances, see `DecimalContext`. `None` leaves it to
+    /// `Decimal`'s own precision.
+    pub decimal_context: Option<DecimalContext>,
+    /// Bucket deposit and withdrawal amounts into a histogram per type in the summary, refused
+    /// ones included, so the buckets add up to the type's count.
+    pub amount_histogram: bool,
+}
@@ -0,0 +1,30 @@
+use crate::transaction::ClientId;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::File;
+use std::path::Path;
+
+/// An account without activity for longer than the dormancy threshold,
+/// see `PaymentEngine::dormant_accounts`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct DormantAccount {
+    pub client: ClientId,
+    /// Unix time in seconds of the account's latest transaction.
+    pub last_activity: u64,
+    /// Seconds between that and the latest timestamp in the input.
+    pub idle_secs: u64,
+}
+
+/// Writes the dormant accounts to `path` as CSV, with a header even when there are none.
+pub fn save_report(path: impl AsRef<Path>, accounts: &[DormantAccount]) -> Result<()> {
+    let path = path.as_ref();
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create dormancy report: {}", path.display()))?;
+
+    let mut writer = csv::Writer::from_writer(file);
+    writer.write_record(["client", "last_activity", "idle_secs"])?;
+    for account in accounts {
+        writer.serialize(account)?;
+    }
+    writer.flush().context("Failed to write dormancy report")
+}
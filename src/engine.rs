@@ -1,7 +1,25 @@
-use crate::account::{Account, AccountOutput};
-use crate::transaction::{StoredTransaction, TransactionRecord, TransactionType};
+use crate::account::{Account, AccountDiff, AccountOutput, AccountSnapshot, WithdrawError};
+use crate::config::{
+    AccountCreation, CorrectionPolicy, CrossClientDisputePolicy, DisputeAmountPolicy,
+    DisputeRatioAction, DisputeSequencing, EngineConfig, IngestionMode,
+};
+use crate::dormancy::DormantAccount;
+use crate::handler::CustomHandler;
+use crate::reader::{InputConfig, TransactionReader};
+use crate::snapshot::Snapshot;
+use crate::summary::ProcessSummary;
+use crate::transaction::{
+    ClientId, DisputeEntry, PrecisionPolicy, StoredTransaction, TransactionRecord, TransactionType,
+    TxId,
+};
 use anyhow::{Context, Result};
-use std::collections::HashMap;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::path::Path;
+use thiserror::Error;
 
 /// The core payment processing engine that manages account states and transaction history.
 /// In a real world application, this would likely be backed by a persistent data store,
@@ -11,99 +29,1046 @@ pub struct PaymentEngine {
     /// A HashMap is probably the best structure for in-memory calculation
     /// because we need to frequently look for accounts using the ID.
     /// This will yield a constant time lookup, which is probably the best we can do.
-    accounts: HashMap<u16, Account>,
-    transactions: HashMap<u32, StoredTransaction>,
+    /// Its iteration order is arbitrary, so anything output from it is sorted by client first.
+    /// A BTreeMap would give that order for free but made lookups about 60% slower, see
+    /// `bench_account_lookups`.
+    accounts: HashMap<ClientId, Account>,
+    transactions: HashMap<TxId, StoredTransaction>,
+    /// The stored transactions of each client, in the order they were stored, so per-client
+    /// lookups don't have to scan every transaction. Kept in step with `transactions`.
+    client_index: HashMap<ClientId, Vec<TxId>>,
+    summary: ProcessSummary,
+    config: EngineConfig,
+    handlers: HashMap<String, Box<dyn CustomHandler>>,
+    finalizer: Option<Finalizer>,
+    account_factory: Option<AccountFactory>,
+    /// Number of records handed to the engine so far, used as a clock for dispute ages.
+    sequence: u64,
+    /// Every balance change in the order it happened, only kept with `with_event_log`.
+    events: Option<Vec<LedgerEvent>>,
 }
 
+/// End-of-run hook, see `PaymentEngine::set_finalizer`.
+pub type Finalizer = Box<dyn FnOnce(&mut PaymentEngine)>;
+
+/// Opens new accounts, see `PaymentEngine::set_account_factory`.
+pub type AccountFactory = Box<dyn Fn(ClientId) -> Account>;
+
 impl PaymentEngine {
     pub fn new() -> Self {
+        Self::with_config(EngineConfig::default())
+    }
+
+    pub fn with_config(config: EngineConfig) -> Self {
         Self {
             accounts: HashMap::new(),
             transactions: HashMap::new(),
+            client_index: HashMap::new(),
+            summary: ProcessSummary::default(),
+            config,
+            handlers: HashMap::new(),
+            finalizer: None,
+            account_factory: None,
+            sequence: 0,
+            events: None,
+        }
+    }
+
+    /// Keeps a log of every change to a balance, see `events`, whether a record made it or one of
+    /// the engine's own operations did. Off by default, since a long run would otherwise hold an
+    /// entry per record.
+    pub fn with_event_log(mut self) -> Self {
+        self.events = Some(Vec::new());
+        self
+    }
+
+    /// The balance changes logged so far, oldest first. Always empty without `with_event_log`.
+    pub fn events(&self) -> &[LedgerEvent] {
+        self.events.as_deref().unwrap_or_default()
+    }
+
+    /// Sets a hook run once after all records have been read but before any output is produced,
+    /// for end-of-day operations such as auto-resolving stale disputes or applying interest.
+    pub fn set_finalizer(&mut self, finalizer: impl FnOnce(&mut PaymentEngine) + 'static) {
+        self.finalizer = Some(Box::new(finalizer));
+    }
+
+    /// Runs the finalizer, if one is set. It only ever runs once.
+    pub fn finalize(&mut self) {
+        if let Some(finalizer) = self.finalizer.take() {
+            finalizer(self);
+        }
+    }
+
+    /// Sets how accounts are opened for clients seen for the first time, e.g. to give some of
+    /// them an overdraft facility. Without a factory they start out as `Account::new()`.
+    pub fn set_account_factory(&mut self, factory: impl Fn(ClientId) -> Account + 'static) {
+        self.account_factory = Some(Box::new(factory));
+    }
+
+    /// One client's balances, without going through the whole account table.
+    pub fn account(&self, client: ClientId) -> Option<AccountSnapshot> {
+        self.accounts.get(&client).map(AccountSnapshot::from)
+    }
+
+    pub fn account_mut(&mut self, client: ClientId) -> Option<&mut Account> {
+        self.accounts.get_mut(&client)
+    }
+
+    /// Registers a handler for a custom transaction type, replacing any previous handler
+    /// for the same type name.
+    pub fn register_handler(&mut self, handler: impl CustomHandler + 'static) {
+        self.handlers
+            .insert(handler.type_name().to_string(), Box::new(handler));
+    }
+
+    /// Restores an engine from a previously saved snapshot.
+    pub fn from_snapshot(snapshot: Snapshot, config: EngineConfig) -> Self {
+        // Carry on the clock from where the snapshotted run left off
+        let sequence = snapshot
+            .transactions
+            .values()
+            .map(|tx| tx.sequence)
+            .max()
+            .unwrap_or_default();
+
+        // The snapshot's transactions come sorted by tx id, the closest to storage order it has
+        let mut client_index: HashMap<_, Vec<_>> = HashMap::new();
+        for (&tx, stored) in &snapshot.transactions {
+            client_index.entry(stored.client).or_default().push(tx);
+        }
+
+        Self {
+            sequence,
+            accounts: snapshot.accounts.into_iter().collect(),
+            transactions: snapshot.transactions.into_iter().collect(),
+            client_index,
+            ..Self::with_config(config)
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            accounts: self
+                .accounts
+                .iter()
+                .map(|(&client, account)| (client, account.clone()))
+                .collect(),
+            transactions: self
+                .transactions
+                .iter()
+                .map(|(&tx, stored)| (tx, stored.clone()))
+                .collect(),
         }
     }
 
     /// We want to decouple the file reading/parsing from the actual processing logic,
     /// this accepts a parsed transaction record and applies it to the appropriate account.
     pub fn process_transaction(&mut self, record: TransactionRecord) -> Result<()> {
+        self.sequence += 1;
+        let result = self.apply(record);
+
+        match result {
+            Ok(()) => self.summary.records_processed += 1,
+            Err(_) => self.summary.records_rejected += 1,
+        }
+
+        result
+    }
+
+    /// What `process_transaction` would reject a record for on its own, without looking at any
+    /// account: a missing or negative amount, or a custom type nothing is registered for.
+    pub fn check_record(&self, record: &TransactionRecord) -> Result<()> {
+        let mut record = record.clone();
+        self.round_to_working_scale(&mut record)?;
+        record.validate().context("Invalid transaction")?;
+        if let TransactionType::Custom(name) = &record.tx_type
+            && !self.handlers.contains_key(name)
+        {
+            anyhow::bail!("Unknown transaction type: {name}");
+        }
+        Ok(())
+    }
+
+    /// Rounds the amount to the working scale, if there is one. Rejects an amount that only
+    /// rounds to zero, which would otherwise be applied as a record moving nothing.
+    fn round_to_working_scale(&self, record: &mut TransactionRecord) -> Result<()> {
+        if let (Some(scale), Some(amount)) = (self.config.working_scale, record.amount) {
+            let rounded = amount.round_dp(scale);
+            if rounded.is_zero() && !amount.is_zero() {
+                anyhow::bail!("Amount {amount} rounds to zero at the working scale of {scale}");
+            }
+            record.amount = Some(rounded);
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, mut record: TransactionRecord) -> Result<()> {
+        // Rounded first, so validation sees the amount that will be applied
+        self.round_to_working_scale(&mut record)?;
         record.validate().context("Invalid transaction")?;
 
-        let account = self.accounts.entry(record.client).or_default();
+        if self.config.ingestion == IngestionMode::DisputesOnly && !record.is_dispute_family() {
+            self.summary.filtered += 1;
+            return Ok(());
+        }
+
+        if let TransactionType::Custom(name) = &record.tx_type
+            && !self.handlers.contains_key(name)
+        {
+            anyhow::bail!("Unknown transaction type: {name}");
+        }
+
+        if self.config.account_creation == AccountCreation::DepositFirst
+            && record.tx_type != TransactionType::Deposit
+            && !self.accounts.contains_key(&record.client)
+        {
+            self.summary.unknown_client_rejections += 1;
+            anyhow::bail!(
+                "Client {} has no account, only a deposit can open one",
+                record.client
+            );
+        }
+
+        if self
+            .config
+            .max_accounts
+            .is_some_and(|max| self.accounts.len() >= max)
+            && !self.accounts.contains_key(&record.client)
+        {
+            self.summary.account_limit_rejections += 1;
+            anyhow::bail!(
+                "Account limit reached, not creating an account for client {}",
+                record.client
+            );
+        }
+
+        let sequence = self.sequence;
+        let mut fee_charged = Decimal::ZERO;
+        // Money moved by a deposit or withdrawal, for the per-type volumes
+        let mut moved = None;
+        // Cleared for deposits and withdrawals the account refused
+        let mut applied = true;
+        let account = open_account(&mut self.accounts, &self.account_factory, record.client);
+        let balances_before = (account.available, account.held);
+        // A decimal context may have to undo the record once its result is known
+        let saved = self.config.decimal_context.map(|_| {
+            let stored = self.transactions.get(&record.tx).map(|tx| {
+                let position = self
+                    .client_index
+                    .get(&tx.client)
+                    .and_then(|txs| txs.iter().position(|&t| t == record.tx))
+                    .unwrap_or_default();
+                (tx.clone(), position)
+            });
+            (account.clone(), stored, self.summary.clone())
+        });
+
+        // Rounding the amount rather than the balance it lands in keeps the account and the
+        // stored transaction in agreement, so a later dispute holds what was really credited
+        if let Some(context) = self.config.decimal_context
+            && context.policy == PrecisionPolicy::Round
+            && let Some(amount) = record.amount
+        {
+            let available = account.available;
+            record.amount = Some(match record.tx_type {
+                TransactionType::Withdrawal => available - context.round(available - amount),
+                TransactionType::Deposit | TransactionType::Correction => {
+                    context.round(available + amount) - available
+                }
+                _ => amount,
+            });
+        }
 
-        match record.tx_type {
+        match &record.tx_type {
             TransactionType::Deposit => {
                 let amount = record.amount.context("Deposit missing amount")?;
                 account.deposit(amount);
+                moved = Some(Decimal::ZERO);
+                applied = !account.locked;
+                if applied {
+                    account.deposited += amount;
+                    account.deposits += 1;
+                    moved = Some(amount);
+                }
 
                 // Store transaction for potential disputes
-                self.transactions.insert(
+                store(
+                    &mut self.transactions,
+                    &mut self.client_index,
                     record.tx,
-                    StoredTransaction {
-                        client: record.client,
-                        amount,
-                        tx_type: TransactionType::Deposit,
-                        disputed: false,
-                    },
+                    StoredTransaction::new(record.client, amount, TransactionType::Deposit)
+                        .at(sequence)
+                        .with_timestamp(record.timestamp),
                 );
             }
 
             TransactionType::Withdrawal => {
                 let amount = record.amount.context("Withdrawal missing amount")?;
-                let success = account.withdraw(amount);
+                let fee = self
+                    .config
+                    .withdrawal_fee
+                    .map_or(Decimal::ZERO, |fee| fee.fee_for(amount));
+
+                // Only store successful withdrawals, failed ones are just counted by reason.
+                // The fee is taken with the amount, so both must be covered by available.
+                moved = Some(Decimal::ZERO);
+                match account.try_withdraw_with(amount + fee, self.config.withdraw_error_priority) {
+                    Ok(()) => {
+                        fee_charged = fee;
+                        moved = Some(amount);
+                        account.withdrawn += amount;
+                        store(
+                            &mut self.transactions,
+                            &mut self.client_index,
+                            record.tx,
+                            StoredTransaction::new(
+                                record.client,
+                                amount,
+                                TransactionType::Withdrawal,
+                            )
+                            .at(sequence)
+                            .with_timestamp(record.timestamp),
+                        );
+                    }
+                    Err(WithdrawError::Locked) => {
+                        applied = false;
+                        self.summary.withdrawals_failed_locked += 1
+                    }
+                    Err(WithdrawError::InsufficientFunds) => {
+                        applied = false;
+                        self.summary.withdrawals_failed_insufficient += 1
+                    }
+                }
+            }
 
-                // Only store successful withdrawals
-                if success {
-                    self.transactions.insert(
+            TransactionType::Correction => {
+                let amount = record.amount.context("Correction missing amount")?;
+                let unbounded = self.config.corrections == CorrectionPolicy::Unbounded;
+
+                if !account.correct(amount, unbounded) {
+                    anyhow::bail!(
+                        "Correction {} refused: account {} is locked or would go below zero",
                         record.tx,
-                        StoredTransaction {
-                            client: record.client,
-                            amount,
-                            tx_type: TransactionType::Withdrawal,
-                            disputed: false,
-                        },
+                        record.client
                     );
                 }
+
+                store(
+                    &mut self.transactions,
+                    &mut self.client_index,
+                    record.tx,
+                    StoredTransaction::new(record.client, amount, TransactionType::Correction)
+                        .at(sequence)
+                        .with_timestamp(record.timestamp),
+                );
             }
 
             TransactionType::Dispute => {
                 if let Some(tx) = self.transactions.get_mut(&record.tx) {
-                    // Only dispute if client matches and not already disputed
-                    if tx.client == record.client && !tx.disputed {
+                    if tx.client != record.client
+                        && self.config.cross_client_disputes == CrossClientDisputePolicy::Reject
+                    {
+                        account.suspicious_disputes += 1;
+                        self.summary.cross_client_disputes += 1;
+                        anyhow::bail!(
+                            "Client {} disputed tx {} of client {}",
+                            record.client,
+                            record.tx,
+                            tx.client
+                        );
+                    }
+
+                    if tx.client == record.client && tx.disputed {
+                        self.summary.duplicate_disputes += 1;
+                    }
+
+                    // Only dispute if client matches and not already disputed. A locked account
+                    // takes no new holds, rather than recording a dispute that holds nothing.
+                    // Disputes opened before the lock still resolve and charge back as usual.
+                    if tx.client == record.client && !tx.disputed && !account.locked {
+                        if self
+                            .config
+                            .dispute_window
+                            .is_some_and(|window| window.closed(tx, sequence, record.timestamp))
+                        {
+                            self.summary.out_of_window_disputes += 1;
+                            anyhow::bail!("Tx {} is past the dispute window", record.tx);
+                        }
+
+                        let partial = self.config.partial_disputes;
+                        if self.config.dispute_amounts == DisputeAmountPolicy::CrossCheck
+                            && record.amount.is_some_and(|amount| {
+                                amount > tx.amount || (!partial && amount != tx.amount)
+                            })
+                        {
+                            self.summary.dispute_amount_mismatches += 1;
+                        }
+
+                        // A partial dispute only holds the portion it names
+                        let portion = match record.amount {
+                            Some(amount) if partial => amount.min(tx.amount),
+                            _ => tx.amount,
+                        };
+                        // A negative correction took money away, there's nothing of it to hold
+                        let portion = portion.max(Decimal::ZERO);
+
+                        // Guards against a flood of disputes freezing an entire account
+                        if self
+                            .config
+                            .max_held
+                            .is_some_and(|cap| account.held + portion > cap)
+                        {
+                            self.summary.held_cap_rejections += 1;
+                            anyhow::bail!(
+                                "Dispute of tx {} would exceed the held funds cap",
+                                record.tx
+                            );
+                        }
+
                         tx.disputed = true;
-                        account.hold_funds(tx.amount);
+                        tx.had_dispute = true;
+                        tx.resolved = false;
+                        tx.sequence = sequence;
+                        account.had_dispute = true;
+                        account.disputes += 1;
+                        tx.held = match tx.tx_type {
+                            TransactionType::Withdrawal => account.hold_reversal(portion),
+                            _ => account.hold_funds(portion),
+                        };
+
+                        if let Some(guard) = self.config.dispute_ratio
+                            && !account.dispute_ratio_breached
+                            && guard.breached(account.disputes, account.deposits)
+                        {
+                            account.dispute_ratio_breached = true;
+                            self.summary.dispute_ratio_breaches += 1;
+                            if guard.action == DisputeRatioAction::Freeze {
+                                account.freeze();
+                            }
+                        }
                     }
                 }
             }
 
             TransactionType::Resolve => {
+                if self.config.dispute_sequencing == DisputeSequencing::Strict {
+                    check_sequence(&self.transactions, &record, "resolve")?;
+                }
                 if let Some(tx) = self.transactions.get_mut(&record.tx) {
+                    if tx.client == record.client && !tx.disputed && tx.resolved {
+                        self.summary.duplicate_resolves += 1;
+                    }
+
                     // Only resolve if client matches and is disputed
                     if tx.client == record.client && tx.disputed {
-                        tx.disputed = false;
-                        account.release_funds(tx.amount);
+                        let portion = settled_portion(&self.config, &record, tx.held);
+                        tx.held -= match tx.tx_type {
+                            TransactionType::Withdrawal => account.drop_reversal(portion),
+                            _ => account.release_funds(portion),
+                        };
+                        // A partial resolve leaves the rest of the tx under dispute
+                        tx.disputed = !tx.held.is_zero();
+                        tx.resolved = !tx.disputed;
                     }
                 }
             }
 
             TransactionType::Chargeback => {
+                if self.config.dispute_sequencing == DisputeSequencing::Strict {
+                    check_sequence(&self.transactions, &record, "chargeback")?;
+                }
                 if let Some(tx) = self.transactions.get_mut(&record.tx) {
                     // Only chargeback if client matches and is disputed
                     if tx.client == record.client && tx.disputed {
-                        account.chargeback(tx.amount);
-                        tx.disputed = false; // Transaction is finalized
+                        // Only remove what this tx put on hold, never its original amount
+                        let portion = settled_portion(&self.config, &record, tx.held);
+                        let charged_back = match tx.tx_type {
+                            TransactionType::Withdrawal => account.chargeback_withdrawal(portion),
+                            _ => account.chargeback(portion),
+                        };
+                        if tx.charged_back.is_zero() && !charged_back.is_zero() {
+                            account.chargebacks += 1;
+                        }
+                        tx.held -= charged_back;
+                        tx.charged_back += charged_back;
+                        tx.finalized = true;
+                        // Finalized once nothing of it is held any more
+                        tx.disputed = !tx.held.is_zero();
+                    }
+                }
+            }
+
+            TransactionType::ChargebackReversal => {
+                if let Some(tx) = self.transactions.get_mut(&record.tx) {
+                    // Only reverse if client matches and something was charged back
+                    if tx.client == record.client && !tx.charged_back.is_zero() {
+                        match tx.tx_type {
+                            TransactionType::Withdrawal => {
+                                if !account.reverse_withdrawal_chargeback(tx.charged_back) {
+                                    anyhow::bail!(
+                                        "Account {} can't cover the reinstated withdrawal {}",
+                                        record.client,
+                                        record.tx
+                                    );
+                                }
+                            }
+                            _ => account.reverse_chargeback(tx.charged_back),
+                        }
+                        tx.charged_back = Decimal::ZERO;
+                        tx.finalized = false;
+                    }
+                }
+            }
+
+            TransactionType::Freeze => account.freeze(),
+
+            TransactionType::Unfreeze => {
+                if !account.unfreeze() {
+                    anyhow::bail!(
+                        "Account {} was locked by a chargeback and can't be unfrozen",
+                        record.client
+                    );
+                }
+            }
+
+            TransactionType::Custom(name) => {
+                // Handlers get the map itself, so reindex whatever they did to the record's tx
+                let before = self.transactions.get(&record.tx).map(|tx| tx.client);
+                self.handlers[name].apply(account, &record, &mut self.transactions)?;
+                let after = self.transactions.get(&record.tx).map(|tx| tx.client);
+                if before != after {
+                    unindex(&mut self.client_index, before, record.tx);
+                    if let Some(client) = after {
+                        self.client_index.entry(client).or_default().push(record.tx);
                     }
                 }
             }
         }
 
+        if let Some(context) = self.config.decimal_context
+            && let Some((saved_account, saved_tx, saved_summary)) = saved
+            && !(context.fits(account.available) && context.fits(account.held))
+        {
+            *account = saved_account;
+            self.summary = saved_summary;
+            unstore(&mut self.transactions, &mut self.client_index, record.tx);
+            // Back where it was in its client's index, which is kept oldest first
+            if let Some((tx, position)) = saved_tx {
+                let txs = self.client_index.entry(tx.client).or_default();
+                txs.insert(position.min(txs.len()), record.tx);
+                self.transactions.insert(record.tx, tx);
+            }
+            anyhow::bail!(
+                "Tx {} would leave a balance beyond {} significant digits",
+                record.tx,
+                context.max_digits
+            );
+        }
+
+        if let Some(scale) = self.config.working_scale {
+            account.rescale(scale);
+        }
+
+        let balances_after = (account.available, account.held);
+        if balances_after != balances_before {
+            account.funded = true;
+            if let Some(events) = &mut self.events {
+                events.push(LedgerEvent::new(
+                    Some(record.tx),
+                    record.client,
+                    LedgerEventKind::Record(record.tx_type.clone()),
+                    balances_before,
+                    balances_after,
+                ));
+            }
+        }
+
+        if applied && record.memo.is_some() {
+            account.memo = record.memo;
+        }
+
+        // Out of order timestamps never move the last activity back
+        if let Some(timestamp) = record.timestamp {
+            account.last_activity = account.last_activity.max(Some(timestamp));
+        }
+
+        if !fee_charged.is_zero() {
+            self.summary.fees_collected += fee_charged;
+            if let Some(fee_account_id) = self.config.fee_account {
+                let fee_account =
+                    open_account(&mut self.accounts, &self.account_factory, fee_account_id);
+                let available_before = fee_account.available;
+                // Not `deposit`: a locked fee account still takes the fee, which has already
+                // left the client's account and would otherwise vanish from the books
+                fee_account.available += fee_charged;
+                fee_account.funded = true;
+                if let Some(scale) = self.config.working_scale {
+                    fee_account.rescale(scale);
+                }
+                // There's no record of the fee account's own to reject, so it's always rounded
+                if let Some(context) = self.config.decimal_context {
+                    fee_account.available = context.round(fee_account.available);
+                }
+                if let Some(events) = &mut self.events {
+                    events.push(LedgerEvent::new(
+                        Some(record.tx),
+                        fee_account_id,
+                        LedgerEventKind::Record(record.tx_type.clone()),
+                        (available_before, fee_account.held),
+                        (fee_account.available, fee_account.held),
+                    ));
+                }
+            }
+        }
+
+        self.summary.count_type(record.tx_type.as_str(), moved);
+        if self.config.amount_histogram
+            && moved.is_some()
+            && let Some(amount) = record.amount
+        {
+            self.summary.bucket_amount(record.tx_type.as_str(), amount);
+        }
+        Ok(())
+    }
+
+    /// Merges client `from` into client `into`, e.g. after finding two accounts belong to the
+    /// same customer. See `Account::merge` for how the accounts combine; `into` is opened if it
+    /// doesn't exist yet. Every stored transaction of `from` moves over as it is, so open
+    /// disputes keep their held funds and can be resolved or charged back through `into`.
+    pub fn merge_clients(&mut self, from: ClientId, into: ClientId) -> Result<()> {
+        if from == into {
+            anyhow::bail!("Can't merge client {from} into itself");
+        }
+        let source = self
+            .accounts
+            .get(&from)
+            .with_context(|| format!("Client {from} has no account to merge"))?;
+
+        let target = self.accounts.get(&into).cloned();
+        let mut merged = target.unwrap_or_else(|| match &self.account_factory {
+            Some(factory) => factory(into),
+            None => Account::new(),
+        });
+        let into_before = (merged.available, merged.held);
+        if !merged.merge(source) {
+            anyhow::bail!("Merging client {from} into {into} would overflow the balances");
+        }
+
+        if let Some(events) = &mut self.events {
+            let moved = [
+                (
+                    from,
+                    (source.available, source.held),
+                    (Decimal::ZERO, Decimal::ZERO),
+                ),
+                (into, into_before, (merged.available, merged.held)),
+            ];
+            for (client, before, after) in moved
+                .into_iter()
+                .filter(|(_, before, after)| before != after)
+            {
+                events.push(LedgerEvent::new(
+                    None,
+                    client,
+                    LedgerEventKind::Merge,
+                    before,
+                    after,
+                ));
+            }
+        }
+        self.accounts.insert(into, merged);
+        self.accounts.remove(&from);
+
+        let moved = self.client_index.remove(&from).unwrap_or_default();
+        for tx in &moved {
+            if let Some(stored) = self.transactions.get_mut(tx) {
+                stored.client = into;
+            }
+        }
+        self.client_index.entry(into).or_default().extend(moved);
+
         Ok(())
     }
 
+    /// Reverses a previously applied deposit or withdrawal, restoring the balance from before it
+    /// and forgetting the stored transaction, for correcting a run after the fact. The record
+    /// identifies the transaction; its amount is ignored in favour of the stored one.
+    ///
+    /// Refused for a transaction under dispute, since the dispute's held funds depend on it, and
+    /// on a locked account, which covers anything charged back. A withdrawal's fee isn't refunded.
+    pub fn unapply(&mut self, record: &TransactionRecord) -> Result<()> {
+        let tx = self
+            .transactions
+            .get(&record.tx)
+            .filter(|tx| tx.client == record.client && tx.tx_type == record.tx_type)
+            .with_context(|| {
+                format!(
+                    "No {} {} stored for client {}",
+                    record.tx_type.as_str(),
+                    record.tx,
+                    record.client
+                )
+            })?;
+
+        if tx.disputed {
+            anyhow::bail!("Transaction {} is under dispute", record.tx);
+        }
+
+        let account = self
+            .accounts
+            .get_mut(&record.client)
+            .with_context(|| format!("No account for client {}", record.client))?;
+        let balances_before = (account.available, account.held);
+
+        match tx.tx_type {
+            TransactionType::Deposit => {
+                account
+                    .try_withdraw(tx.amount)
+                    .with_context(|| format!("Can't unapply deposit {}", record.tx))?;
+                account.deposited -= tx.amount;
+            }
+            TransactionType::Withdrawal if account.locked => {
+                anyhow::bail!("Can't unapply withdrawal {}: account is locked", record.tx)
+            }
+            TransactionType::Withdrawal => {
+                account.deposit(tx.amount);
+                account.withdrawn -= tx.amount;
+            }
+            _ => anyhow::bail!("Only deposits and withdrawals can be unapplied"),
+        }
+
+        let balances_after = (account.available, account.held);
+        if let Some(events) = &mut self.events
+            && balances_after != balances_before
+        {
+            events.push(LedgerEvent::new(
+                Some(record.tx),
+                record.client,
+                LedgerEventKind::Unapply(tx.tx_type.clone()),
+                balances_before,
+                balances_after,
+            ));
+        }
+        unstore(&mut self.transactions, &mut self.client_index, record.tx);
+        Ok(())
+    }
+
+    /// Applies each record in turn and yields it together with its account's state right after,
+    /// so callers can build a time series of balances. Records that fail to apply are skipped.
+    pub fn process_with_trace<I>(
+        &mut self,
+        records: I,
+    ) -> impl Iterator<Item = (TransactionRecord, AccountOutput)>
+    where
+        I: IntoIterator<Item = TransactionRecord>,
+    {
+        records.into_iter().filter_map(move |record| {
+            self.process_transaction(record.clone()).ok()?;
+
+            let account = self.accounts.get(&record.client)?;
+            let output =
+                AccountOutput::from_account_with(record.client, account, &self.config.output);
+            Some((record, output))
+        })
+    }
+
+    /// The client's stored transactions, oldest first, looked up through the client index
+    /// rather than by scanning every transaction.
+    pub fn client_transactions(
+        &self,
+        client: ClientId,
+    ) -> impl Iterator<Item = (TxId, &StoredTransaction)> + '_ {
+        self.client_index
+            .get(&client)
+            .into_iter()
+            .flatten()
+            .filter_map(|tx| Some((*tx, self.transactions.get(tx)?)))
+    }
+
+    /// What each of the client's open disputes holds, sorted by tx id, to see which transactions
+    /// are freezing their funds. Adds up to the account's held funds.
+    pub fn held_breakdown(&self, client: ClientId) -> Vec<(TxId, Decimal)> {
+        let mut breakdown: Vec<_> = self
+            .client_transactions(client)
+            .filter(|(_, stored)| stored.disputed)
+            .map(|(tx, stored)| (tx, stored.held))
+            .collect();
+        breakdown.sort_by_key(|(tx, _)| *tx);
+        breakdown
+    }
+
+    /// Lists only the accounts whose balances or locked state changed since `baseline`, sorted by
+    /// client. Accounts that didn't exist in the baseline are compared against an empty account.
+    pub fn diff_against(&self, baseline: &Snapshot) -> Vec<AccountDiff> {
+        let empty = Account::default();
+        let mut diffs: Vec<_> = self
+            .accounts
+            .iter()
+            .filter_map(|(&client, account)| {
+                let before = baseline.accounts.get(&client).unwrap_or(&empty);
+                AccountDiff::between(client, before, account)
+            })
+            .collect();
+
+        diffs.sort_by_key(|diff| diff.client);
+        diffs
+    }
+
+    /// A structured dump of the current state for debugging or serving over HTTP: every account
+    /// with its exact balances and locked flag, plus the disputes still open, both sorted by id.
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut accounts: Vec<_> = self.accounts.iter().collect();
+        accounts.sort_by_key(|(client, _)| **client);
+        let mut disputes: Vec<_> = self
+            .transactions
+            .iter()
+            .filter(|(_, tx)| tx.disputed)
+            .collect();
+        disputes.sort_by_key(|(tx, _)| **tx);
+
+        json!({
+            "accounts": accounts
+                .into_iter()
+                .map(|(client, account)| json!({
+                    "client": client,
+                    "available": account.available,
+                    "held": account.held,
+                    "total": account.total(),
+                    "locked": account.locked,
+                }))
+                .collect::<Vec<_>>(),
+            "open_disputes": disputes
+                .into_iter()
+                .map(|(id, tx)| json!({
+                    "tx": id,
+                    "client": tx.client,
+                    "amount": tx.amount,
+                    "held": tx.held,
+                }))
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    /// Every transaction that was ever disputed, sorted by tx id: open disputes, resolved ones
+    /// and charged back ones alike, for a compliance audit trail.
+    pub fn disputes_ledger(&self) -> Vec<DisputeEntry> {
+        let mut ledger: Vec<_> = self
+            .transactions
+            .iter()
+            .filter(|(_, stored)| stored.had_dispute)
+            .map(|(&tx, stored)| DisputeEntry {
+                tx,
+                client: stored.client,
+                amount: stored.amount,
+                disputed: stored.disputed,
+                finalized: stored.finalized,
+            })
+            .collect();
+        ledger.sort_by_key(|entry| entry.tx);
+        ledger
+    }
+
+    /// Accounts whose last activity is more than `dormant_after` seconds before the latest
+    /// timestamp seen on any account, sorted by client. Measuring against the input rather than
+    /// the clock keeps the report reproducible. Accounts without timestamps are left out.
+    pub fn dormant_accounts(&self, dormant_after: u64) -> Vec<DormantAccount> {
+        let Some(as_of) = self.accounts.values().filter_map(|a| a.last_activity).max() else {
+            return Vec::new();
+        };
+
+        let mut dormant: Vec<_> = self
+            .accounts
+            .iter()
+            .filter_map(|(&client, account)| {
+                let last_activity = account.last_activity?;
+                let idle_secs = as_of - last_activity;
+                (idle_secs > dormant_after).then_some(DormantAccount {
+                    client,
+                    last_activity,
+                    idle_secs,
+                })
+            })
+            .collect();
+        dormant.sort_by_key(|account| account.client);
+        dormant
+    }
+
+    /// Every locked account with its available and held funds, sorted by client. Kept apart from
+    /// the account table for compliance exports, which only care about frozen money.
+    pub fn locked_accounts(&self) -> Vec<(ClientId, Decimal, Decimal)> {
+        let mut locked: Vec<_> = self
+            .accounts
+            .iter()
+            .filter(|(_, account)| account.locked)
+            .map(|(&client, account)| (client, account.available, account.held))
+            .collect();
+        locked.sort_by_key(|(client, ..)| *client);
+        locked
+    }
+
+    /// Resolves every open dispute that has seen more than `max_age` further records since it was
+    /// opened, releasing whatever it still holds. Meant to run as a finalizer, modelling
+    /// a dispute timeout with the record count as a rough clock.
+    pub fn auto_resolve_disputes(&mut self, max_age: u64) {
+        let logged = self.events().len();
+        for (&id, tx) in &mut self.transactions {
+            if !tx.disputed || self.sequence.saturating_sub(tx.sequence) <= max_age {
+                continue;
+            }
+
+            if let Some(account) = self.accounts.get_mut(&tx.client) {
+                let balances_before = (account.available, account.held);
+                tx.held -= match tx.tx_type {
+                    TransactionType::Withdrawal => account.drop_reversal(tx.held),
+                    _ => account.release_funds(tx.held),
+                };
+                let balances_after = (account.available, account.held);
+                if let Some(events) = &mut self.events
+                    && balances_after != balances_before
+                {
+                    events.push(LedgerEvent::new(
+                        Some(id),
+                        tx.client,
+                        LedgerEventKind::AutoResolve,
+                        balances_before,
+                        balances_after,
+                    ));
+                }
+            }
+            tx.disputed = false;
+            self.summary.disputes_auto_resolved += 1;
+        }
+        self.sort_events_since(logged);
+    }
+
+    /// The counterpart of `auto_resolve_disputes` for policies that side with the disputing
+    /// party: disputes open for more than `max_age` further records are charged back, locking
+    /// their accounts like any chargeback.
+    pub fn auto_chargeback_disputes(&mut self, max_age: u64) {
+        let logged = self.events().len();
+        for (&id, tx) in &mut self.transactions {
+            if !tx.disputed || self.sequence.saturating_sub(tx.sequence) <= max_age {
+                continue;
+            }
+
+            if let Some(account) = self.accounts.get_mut(&tx.client) {
+                let balances_before = (account.available, account.held);
+                let charged_back = match tx.tx_type {
+                    TransactionType::Withdrawal => account.chargeback_withdrawal(tx.held),
+                    _ => account.chargeback(tx.held),
+                };
+                if tx.charged_back.is_zero() && !charged_back.is_zero() {
+                    account.chargebacks += 1;
+                }
+                tx.held -= charged_back;
+                tx.charged_back += charged_back;
+                tx.finalized = true;
+                let balances_after = (account.available, account.held);
+                if let Some(events) = &mut self.events
+                    && balances_after != balances_before
+                {
+                    events.push(LedgerEvent::new(
+                        Some(id),
+                        tx.client,
+                        LedgerEventKind::AutoChargeback,
+                        balances_before,
+                        balances_after,
+                    ));
+                }
+            }
+            tx.disputed = false;
+            self.summary.disputes_auto_charged_back += 1;
+        }
+        self.sort_events_since(logged);
+    }
+
+    /// Puts the events logged from `start` on in tx id order. Closing disputes walks the
+    /// transaction map, whose order changes from run to run, and the log shouldn't.
+    fn sort_events_since(&mut self, start: usize) {
+        if let Some(events) = &mut self.events {
+            events[start..].sort_by_key(|event| event.tx);
+        }
+    }
+
+    /// Checks that every account's held funds are exactly what its disputed transactions hold.
+    /// Any other value means the dispute bookkeeping went wrong somewhere. Returns the clients
+    /// that don't add up, sorted.
+    pub fn verify_held_consistency(&self) -> Result<(), Vec<ClientId>> {
+        let mut held: HashMap<ClientId, Decimal> = HashMap::new();
+        for tx in self.transactions.values().filter(|tx| tx.disputed) {
+            *held.entry(tx.client).or_default() += tx.held;
+        }
+
+        let mut mismatched: Vec<_> = self
+            .accounts
+            .iter()
+            .filter(|(client, account)| {
+                account.held != held.get(client).copied().unwrap_or_default()
+            })
+            .map(|(&client, _)| client)
+            .collect();
+
+        if mismatched.is_empty() {
+            return Ok(());
+        }
+        mismatched.sort();
+        Err(mismatched)
+    }
+
+    /// The run counters plus a description of the current state (account counts, totals,
+    /// disputes still open).
+    pub fn summary(&self) -> ProcessSummary {
+        let mut summary = self.summary.clone();
+
+        summary.accounts = self.accounts.len() as u64;
+        summary.locked_accounts = self.accounts.values().filter(|a| a.locked).count() as u64;
+        summary.open_disputes = self.transactions.values().filter(|t| t.disputed).count() as u64;
+        summary.total_overflows = self
+            .accounts
+            .values()
+            .filter(|a| a.total().is_none())
+            .count() as u64;
+        // Normalized so the scale doesn't depend on the order the accounts are summed in
+        let sum = |balance: fn(&Account) -> Decimal| {
+            self.accounts
+                .values()
+                .map(balance)
+                .fold(Decimal::ZERO, Decimal::saturating_add)
+        };
+        let available = sum(|a| a.available);
+        let held = sum(|a| a.held);
+        summary.total_available = available.normalize();
+        summary.total_held = held.normalize();
+        summary.total = available.saturating_add(held).normalize();
+
+        summary
+    }
+
+    /// The I/O layer records its own counters (malformed rows, precision overflows, ...) here
+    /// so a run has a single summary.
+    pub fn summary_mut(&mut self) -> &mut ProcessSummary {
+        &mut self.summary
+    }
+
     pub fn get_accounts(&self) -> Vec<AccountOutput> {
-        self.accounts
+        self.accounts().collect()
+    }
+
+    /// Lazily yields the output rows sorted by client, so large account sets can be streamed
+    /// out without building the whole table first, and the output is byte-identical from run
+    /// to run. Only the ids are sorted up front, which is cheap next to building the rows.
+    pub fn accounts(&self) -> impl Iterator<Item = AccountOutput> + '_ {
+        let output = &self.config.output;
+        let mut accounts: Vec<_> = self
+            .accounts
             .iter()
-            .map(|(&client, account)| AccountOutput::from_account(client, account))
-            .collect()
+            .filter(|(_, account)| account.funded || !output.hide_unfunded)
+            .collect();
+        accounts.sort_unstable_by_key(|(client, _)| **client);
+        accounts
+            .into_iter()
+            .map(|(&client, account)| AccountOutput::from_account_with(client, account, output))
     }
 }
 
@@ -113,352 +1078,3077 @@ impl Default for PaymentEngine {
     }
 }
 
+/// One change to a client's balances, see `PaymentEngine::with_event_log`. A withdrawal with a
+/// fee logs a second event crediting the fee account, a merge one event per account.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LedgerEvent {
+    /// The transaction behind the change, none for a merge.
+    pub tx: Option<TxId>,
+    pub client: ClientId,
+    pub kind: LedgerEventKind,
+    pub delta_available: Decimal,
+    pub delta_held: Decimal,
+}
+
+impl LedgerEvent {
+    fn new(
+        tx: Option<TxId>,
+        client: ClientId,
+        kind: LedgerEventKind,
+        (available_before, held_before): (Decimal, Decimal),
+        (available_after, held_after): (Decimal, Decimal),
+    ) -> Self {
+        Self {
+            tx,
+            client,
+            kind,
+            delta_available: available_after - available_before,
+            delta_held: held_after - held_before,
+        }
+    }
+}
+
+/// What made a `LedgerEvent`'s change.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LedgerEventKind {
+    /// A record applied through `process_transaction`.
+    Record(TransactionType),
+    /// A dispute closed by `PaymentEngine::auto_resolve_disputes`.
+    AutoResolve,
+    /// A dispute closed by `PaymentEngine::auto_chargeback_disputes`.
+    AutoChargeback,
+    /// A deposit or withdrawal reversed by `PaymentEngine::unapply`.
+    Unapply(TransactionType),
+    /// Balances moved by `PaymentEngine::merge_clients`.
+    Merge,
+}
+
+/// A resolve or chargeback that doesn't follow an open dispute, rejected under strict
+/// dispute sequencing.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum SequenceError {
+    /// No stored transaction of the client's has this id.
+    #[error("{0} of unknown tx {1}")]
+    UnknownTx(&'static str, TxId),
+    #[error("{0} of tx {1}, which is not under dispute")]
+    NotDisputed(&'static str, TxId),
+}
+
+fn check_sequence(
+    transactions: &HashMap<TxId, StoredTransaction>,
+    record: &TransactionRecord,
+    action: &'static str,
+) -> Result<(), SequenceError> {
+    match transactions.get(&record.tx) {
+        Some(tx) if tx.client == record.client && tx.disputed => Ok(()),
+        Some(tx) if tx.client == record.client => {
+            Err(SequenceError::NotDisputed(action, record.tx))
+        }
+        _ => Err(SequenceError::UnknownTx(action, record.tx)),
+    }
+}
+
+/// A row of an expected-output CSV, in the default column layout.
+#[derive(Debug, Deserialize)]
+struct ExpectedAccount {
+    client: ClientId,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+}
+
+/// A client whose replayed account doesn't match the expected output.
+#[derive(Debug, PartialEq)]
+pub struct Discrepancy {
+    pub client: ClientId,
+    /// Each differing column as `column: expected X, got Y`, or why the account can't be
+    /// compared at all.
+    pub details: Vec<String>,
+}
+
+/// Consistency check for migrated state: loads `snapshot`, replays the `transactions` file on
+/// top of it and compares the resulting accounts with the `expected` account CSV. Returns the
+/// clients that differ, sorted by id, so an empty list means the snapshot checks out.
+///
+/// Rows that fail to parse or are rejected by the engine are skipped, as in a normal run.
+/// Balances are compared as output, rounded to four places, and regardless of scale.
+pub fn replay_from_snapshot_and_verify(
+    snapshot: impl AsRef<Path>,
+    transactions: impl AsRef<Path>,
+    expected: impl AsRef<Path>,
+    config: EngineConfig,
+) -> Result<Vec<Discrepancy>> {
+    let mut engine = PaymentEngine::from_snapshot(Snapshot::load(snapshot)?, config);
+
+    let transactions = transactions.as_ref();
+    let file = File::open(transactions)
+        .with_context(|| format!("Failed to open input file: {}", transactions.display()))?;
+    for record in TransactionReader::new(file, &InputConfig::default())?.flatten() {
+        let _ = engine.process_transaction(record);
+    }
+
+    let expected = expected.as_ref();
+    let mut expected_accounts = BTreeMap::new();
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(expected)
+        .with_context(|| format!("Failed to open expected output: {}", expected.display()))?;
+    for row in reader.deserialize() {
+        let row: ExpectedAccount =
+            row.with_context(|| format!("Invalid expected output: {}", expected.display()))?;
+        expected_accounts.insert(row.client, row);
+    }
+
+    let mut actual: BTreeMap<_, _> = engine.accounts().map(|a| (a.client, a)).collect();
+    let mut discrepancies = Vec::new();
+    for (client, expected) in expected_accounts {
+        let details = match actual.remove(&client) {
+            None => vec!["missing from the replayed accounts".to_string()],
+            Some(actual) => [
+                ("available", expected.available, actual.available),
+                ("held", expected.held, actual.held),
+                ("total", expected.total, actual.total),
+            ]
+            .into_iter()
+            .filter(|(_, expected, actual)| expected != actual)
+            .map(|(column, expected, actual)| {
+                format!("{column}: expected {expected}, got {actual}")
+            })
+            .chain((expected.locked != actual.locked).then(|| {
+                format!(
+                    "locked: expected {}, got {}",
+                    expected.locked, actual.locked
+                )
+            }))
+            .collect(),
+        };
+        if !details.is_empty() {
+            discrepancies.push(Discrepancy { client, details });
+        }
+    }
+
+    // Whatever is left wasn't expected at all
+    discrepancies.extend(actual.into_keys().map(|client| Discrepancy {
+        client,
+        details: vec!["not in the expected output".to_string()],
+    }));
+    discrepancies.sort_by_key(|d| d.client);
+    Ok(discrepancies)
+}
+
+/// Runs `records` through an engine with each config and lists the accounts that come out
+/// differently under `candidate`, as changes relative to `baseline`, sorted by client. Meant
+/// for checking that a new policy only changes what it's supposed to. Rejected records are
+/// skipped, as in a normal run.
+///
+/// Built on `diff_against`, so only balances and locked states count as differences. An account
+/// only `baseline` opened is compared against an empty one.
+pub fn cross_check(
+    records: &[TransactionRecord],
+    baseline: EngineConfig,
+    candidate: EngineConfig,
+) -> Vec<AccountDiff> {
+    let run = |config| {
+        let mut engine = PaymentEngine::with_config(config);
+        for record in records {
+            let _ = engine.process_transaction(record.clone());
+        }
+        engine
+    };
+    let baseline = run(baseline).snapshot();
+    let candidate = run(candidate);
+
+    let empty = Account::default();
+    let mut diffs = candidate.diff_against(&baseline);
+    diffs.extend(
+        baseline
+            .accounts
+            .iter()
+            .filter(|(client, _)| !candidate.accounts.contains_key(client))
+            .filter_map(|(&client, account)| AccountDiff::between(client, account, &empty)),
+    );
+    diffs.sort_by_key(|diff| diff.client);
+    diffs
+}
+
+/// How much of a disputed tx's remaining hold a resolve or chargeback settles: all of it,
+/// or with partial disputes enabled, the amount carried on the record, capped at what's left.
+fn settled_portion(config: &EngineConfig, record: &TransactionRecord, held: Decimal) -> Decimal {
+    match record.amount {
+        Some(amount) if config.partial_disputes => amount.min(held),
+        _ => held,
+    }
+}
+
+/// Stores a transaction and indexes it under its client. A reused tx id replaces the stored
+/// transaction, which may have belonged to another client.
+fn store(
+    transactions: &mut HashMap<TxId, StoredTransaction>,
+    client_index: &mut HashMap<ClientId, Vec<TxId>>,
+    tx: TxId,
+    stored: StoredTransaction,
+) {
+    let client = stored.client;
+    let replaced = transactions.insert(tx, stored).map(|old| old.client);
+    unindex(client_index, replaced, tx);
+    client_index.entry(client).or_default().push(tx);
+}
+
+/// Forgets a stored transaction, index entry included.
+fn unstore(
+    transactions: &mut HashMap<TxId, StoredTransaction>,
+    client_index: &mut HashMap<ClientId, Vec<TxId>>,
+    tx: TxId,
+) {
+    let removed = transactions.remove(&tx).map(|old| old.client);
+    unindex(client_index, removed, tx);
+}
+
+fn unindex(client_index: &mut HashMap<ClientId, Vec<TxId>>, client: Option<ClientId>, tx: TxId) {
+    let Some(client) = client else {
+        return;
+    };
+    if let Some(txs) = client_index.get_mut(&client) {
+        txs.retain(|&t| t != tx);
+        if txs.is_empty() {
+            client_index.remove(&client);
+        }
+    }
+}
+
+/// The client's account, opened through the factory if it doesn't exist yet.
+fn open_account<'a>(
+    accounts: &'a mut HashMap<ClientId, Account>,
+    factory: &Option<AccountFactory>,
+    client: ClientId,
+) -> &'a mut Account {
+    accounts
+        .entry(client)
+        .or_insert_with(|| factory.as_ref().map_or_else(Account::new, |f| f(client)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::account::LockCause;
+    use crate::account::WithdrawErrorPriority;
+    use crate::account::{BoolFormat, OutputConfig};
+    use crate::config::{DisputeRatioGuard, DisputeWindow, WithdrawalFee};
+    use crate::output::{self, OutputColumn, SelectedColumns};
     use crate::transaction::TransactionRecord;
-    use rust_decimal::Decimal;
     use std::str::FromStr;
+    use std::time::Instant;
 
     fn create_deposit(client: u16, tx: u32, amount: &str) -> TransactionRecord {
         TransactionRecord {
             tx_type: TransactionType::Deposit,
-            client,
-            tx,
+            client: client.into(),
+            tx: tx.into(),
             amount: Some(Decimal::from_str(amount).unwrap()),
+            memo: None,
+            timestamp: None,
         }
     }
 
     fn create_withdrawal(client: u16, tx: u32, amount: &str) -> TransactionRecord {
         TransactionRecord {
             tx_type: TransactionType::Withdrawal,
-            client,
-            tx,
+            client: client.into(),
+            tx: tx.into(),
             amount: Some(Decimal::from_str(amount).unwrap()),
+            memo: None,
+            timestamp: None,
         }
     }
 
     fn create_dispute(client: u16, tx: u32) -> TransactionRecord {
         TransactionRecord {
             tx_type: TransactionType::Dispute,
-            client,
-            tx,
+            client: client.into(),
+            tx: tx.into(),
             amount: None,
+            memo: None,
+            timestamp: None,
         }
     }
 
     fn create_resolve(client: u16, tx: u32) -> TransactionRecord {
         TransactionRecord {
             tx_type: TransactionType::Resolve,
-            client,
-            tx,
+            client: client.into(),
+            tx: tx.into(),
             amount: None,
+            memo: None,
+            timestamp: None,
         }
     }
 
     fn create_chargeback(client: u16, tx: u32) -> TransactionRecord {
         TransactionRecord {
             tx_type: TransactionType::Chargeback,
-            client,
-            tx,
+            client: client.into(),
+            tx: tx.into(),
             amount: None,
+            memo: None,
+            timestamp: None,
+        }
+    }
+
+    /// Deposits, percentage-fee withdrawals and dispute/resolve pairs over high-scale amounts.
+    fn dispute_heavy_workload(rounds: u32) -> Vec<TransactionRecord> {
+        let mut records = Vec::new();
+        for round in 0..rounds {
+            let client = (round % 100) as u16;
+            let tx = round * 3;
+            let amount = format!("{}.{:018}", round % 1000 + 10, round as u64 * 7919);
+            records.push(create_deposit(client, tx, &amount));
+            records.push(create_withdrawal(client, tx + 1, "0.333333333333333333"));
+            records.push(create_dispute(client, tx));
+            records.push(create_resolve(client, tx));
+        }
+        records
+    }
+
+    #[test]
+    fn working_scale_bounds_balances() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            withdrawal_fee: Some(WithdrawalFee::Percent(Decimal::from_str("1.75").unwrap())),
+            working_scale: Some(8),
+            ..EngineConfig::default()
+        });
+        for record in dispute_heavy_workload(500) {
+            engine.process_transaction(record).unwrap();
+        }
+
+        for account in engine.accounts.values() {
+            assert_eq!(account.available.scale(), 8);
+            assert_eq!(account.held.scale(), 8);
+        }
+        // Amounts are rounded on the way in, so the held funds still add up
+        assert!(engine.verify_held_consistency().is_ok());
+    }
+
+    #[test]
+    fn working_scale_rejects_amounts_rounding_to_zero() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            working_scale: Some(8),
+            ..EngineConfig::default()
+        });
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+
+        let error = engine
+            .process_transaction(create_deposit(1, 2, "0.000000001"))
+            .unwrap_err();
+        assert!(error.to_string().contains("rounds to zero"), "{error}");
+        // Negative, and not let through as zero either
+        let correction = TransactionRecord {
+            tx_type: TransactionType::Correction,
+            ..create_deposit(1, 3, "-0.000000001")
+        };
+        assert!(engine.process_transaction(correction).is_err());
+        assert!(!engine.transactions.contains_key(&TxId(2)));
+        assert_eq!(engine.summary().records_rejected, 2);
+    }
+
+    /// Exact vs working scale arithmetic:
+    /// `cargo test --release bench_working_scale -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_working_scale() {
+        let records = dispute_heavy_workload(1_000_000);
+
+        for working_scale in [None, Some(8)] {
+            let mut engine = PaymentEngine::with_config(EngineConfig {
+                withdrawal_fee: Some(WithdrawalFee::Percent(Decimal::from_str("1.75").unwrap())),
+                working_scale,
+                ..EngineConfig::default()
+            });
+            let start = Instant::now();
+            for record in records.iter().cloned() {
+                let _ = engine.process_transaction(record);
+            }
+            println!(
+                "{working_scale:?}: {} records in {:?}",
+                records.len(),
+                start.elapsed()
+            );
+        }
+    }
+
+    /// Account map lookups with every client id in use:
+    /// `cargo test --release bench_account_lookups -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_account_lookups() {
+        // Scatter the clients so consecutive records rarely hit the same account
+        let records: Vec<_> = (0..2_000_000u32)
+            .map(|tx| create_deposit((tx.wrapping_mul(40_503) % 65_536) as u16, tx, "1.5"))
+            .collect();
+
+        let mut engine = PaymentEngine::new();
+        let start = Instant::now();
+        for record in records {
+            let _ = engine.process_transaction(record);
+        }
+        println!(
+            "{} accounts: 2000000 deposits in {:?}",
+            engine.accounts.len(),
+            start.elapsed()
+        );
+
+        let start = Instant::now();
+        let rows = engine.accounts().count();
+        println!("{rows} output rows in {:?}", start.elapsed());
+    }
+
+    #[test]
+    fn decimal_context_caps_significant_digits() {
+        use crate::config::DecimalContext;
+
+        let records = [
+            create_deposit(1, 1, "1000000.5"),
+            create_deposit(1, 2, "0.000001"),
+            create_deposit(1, 3, "0.25"),
+        ];
+        let run = |policy| {
+            let mut engine = PaymentEngine::with_config(EngineConfig {
+                decimal_context: Some(DecimalContext {
+                    max_digits: 12,
+                    policy,
+                }),
+                ..EngineConfig::default()
+            });
+            let results: Vec<_> = records
+                .iter()
+                .map(|record| engine.process_transaction(record.clone()).is_ok())
+                .collect();
+            (results, engine)
+        };
+
+        // 1000000.500001 needs 13 digits: rejected outright, or the amount is rounded away
+        let (results, engine) = run(PrecisionPolicy::Reject);
+        assert_eq!(results, [true, false, true]);
+        assert_eq!(
+            engine.accounts[&ClientId(1)].available,
+            Decimal::from_str("1000000.75").unwrap()
+        );
+        // The rejected deposit left nothing behind to dispute
+        assert!(!engine.transactions.contains_key(&TxId(2)));
+
+        let (results, mut engine) = run(PrecisionPolicy::Round);
+        assert_eq!(results, [true, true, true]);
+        assert_eq!(
+            engine.accounts[&ClientId(1)].available,
+            Decimal::from_str("1000000.75").unwrap()
+        );
+        // The stored deposit is what was credited, so disputing it holds as much
+        assert_eq!(engine.transactions[&TxId(2)].amount, Decimal::ZERO);
+        engine.process_transaction(create_dispute(1, 3)).unwrap();
+        assert!(engine.verify_held_consistency().is_ok());
+        engine.process_transaction(create_resolve(1, 3)).unwrap();
+        assert_eq!(engine.accounts[&ClientId(1)].held, Decimal::ZERO);
+
+        // Without a context every digit is kept
+        let mut engine = PaymentEngine::new();
+        for record in records {
+            engine.process_transaction(record).unwrap();
+        }
+        assert_eq!(
+            engine.accounts[&ClientId(1)].available,
+            Decimal::from_str("1000000.750001").unwrap()
+        );
+    }
+
+    #[test]
+    fn decimal_context_rejection_rolls_back_counters() {
+        use crate::config::{DecimalContext, DisputeRatioGuard};
+
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            decimal_context: Some(DecimalContext {
+                max_digits: 12,
+                policy: PrecisionPolicy::Reject,
+            }),
+            partial_disputes: true,
+            dispute_ratio: Some(DisputeRatioGuard {
+                max_ratio: Decimal::ZERO,
+                action: DisputeRatioAction::Warn,
+            }),
+            ..EngineConfig::default()
+        });
+        engine
+            .process_transaction(create_deposit(1, 1, "1000000.5"))
+            .unwrap();
+        engine
+            .process_transaction(create_deposit(1, 2, "1"))
+            .unwrap();
+
+        // Would leave 1000001.499999 available, after already counting a ratio breach
+        let dispute = TransactionRecord {
+            amount: Some(Decimal::from_str("0.000001").unwrap()),
+            ..create_dispute(1, 1)
+        };
+        assert!(engine.process_transaction(dispute).is_err());
+
+        let summary = engine.summary();
+        assert_eq!(summary.dispute_ratio_breaches, 0);
+        assert_eq!(summary.records_rejected, 1);
+        assert!(!engine.accounts[&ClientId(1)].dispute_ratio_breached);
+        assert!(!engine.transactions[&TxId(1)].disputed);
+        // The restored deposit keeps its place ahead of the later one
+        assert_eq!(engine.client_index[&ClientId(1)], [TxId(1), TxId(2)]);
+    }
+
+    #[test]
+    fn chargeback_reversal_unlocks_after_last() {
+        let mut engine = PaymentEngine::new();
+        for record in [
+            create_deposit(1, 1, "10.0"),
+            create_deposit(1, 2, "4.0"),
+            create_deposit(1, 3, "1.0"),
+            create_dispute(1, 1),
+            create_dispute(1, 2),
+            create_chargeback(1, 1),
+            create_chargeback(1, 2),
+            admin(TransactionType::ChargebackReversal, 1, 2),
+        ] {
+            engine.process_transaction(record).unwrap();
+        }
+
+        // One of two chargebacks reversed: the funds are back, the lock stays
+        let account = &engine.get_accounts()[0];
+        assert_eq!(account.available, Decimal::from(5));
+        assert!(account.locked);
+
+        // Reversing the same tx again does nothing
+        engine
+            .process_transaction(admin(TransactionType::ChargebackReversal, 1, 2))
+            .unwrap();
+        assert_eq!(engine.get_accounts()[0].available, Decimal::from(5));
+
+        engine
+            .process_transaction(admin(TransactionType::ChargebackReversal, 1, 1))
+            .unwrap();
+        let account = &engine.get_accounts()[0];
+        assert_eq!(account.available, Decimal::from(15));
+        assert!(!account.locked);
+        assert!(
+            engine
+                .disputes_ledger()
+                .iter()
+                .all(|entry| !entry.finalized)
+        );
+    }
+
+    #[test]
+    fn chargeback_reversal_of_single_chargeback() {
+        let mut engine = PaymentEngine::new();
+        for record in [
+            create_deposit(1, 1, "10.0"),
+            create_withdrawal(1, 2, "4.0"),
+            create_dispute(1, 2),
+            create_chargeback(1, 2),
+        ] {
+            engine.process_transaction(record).unwrap();
+        }
+        assert_eq!(engine.get_accounts()[0].available, Decimal::from(10));
+
+        // The withdrawal stands after all and the only lock is lifted
+        engine
+            .process_transaction(admin(TransactionType::ChargebackReversal, 1, 2))
+            .unwrap();
+        let account = &engine.get_accounts()[0];
+        assert_eq!(account.available, Decimal::from(6));
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn disputes_ledger_tracks_outcomes() {
+        let mut engine = PaymentEngine::new();
+        for record in [
+            create_deposit(1, 1, "10.0"),
+            create_deposit(1, 2, "4.0"),
+            create_deposit(2, 3, "7.0"),
+            create_deposit(2, 4, "1.0"),
+            create_dispute(1, 1),
+            create_resolve(1, 1),
+            create_dispute(2, 3),
+            create_dispute(1, 2),
+            create_chargeback(1, 2),
+        ] {
+            engine.process_transaction(record).unwrap();
+        }
+
+        let entry = |tx: u32, client: u16, amount: i64, disputed, finalized| DisputeEntry {
+            tx: tx.into(),
+            client: client.into(),
+            amount: Decimal::from(amount),
+            disputed,
+            finalized,
+        };
+        // Tx 4 was never disputed and isn't part of the ledger
+        assert_eq!(
+            engine.disputes_ledger(),
+            [
+                entry(1, 1, 10, false, false),
+                entry(2, 1, 4, false, true),
+                entry(3, 2, 7, true, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn locked_accounts_after_chargeback() {
+        let mut engine = PaymentEngine::new();
+        for record in [
+            create_deposit(1, 1, "10.0"),
+            create_deposit(1, 2, "4.0"),
+            create_deposit(2, 3, "7.0"),
+            create_dispute(1, 1),
+            create_dispute(1, 2),
+            create_chargeback(1, 1),
+            create_dispute(2, 3),
+        ] {
+            engine.process_transaction(record).unwrap();
+        }
+
+        // Client 2 only has funds held, client 1 is locked with tx 2 still held
+        assert_eq!(
+            engine.locked_accounts(),
+            [(ClientId(1), Decimal::ZERO, Decimal::from(4))]
+        );
+    }
+
+    #[test]
+    fn unapply_clean_deposit() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+        engine
+            .process_transaction(create_deposit(1, 2, "2.5"))
+            .unwrap();
+        engine
+            .process_transaction(create_withdrawal(1, 3, "1.0"))
+            .unwrap();
+
+        engine.unapply(&create_deposit(1, 2, "2.5")).unwrap();
+        engine.unapply(&create_withdrawal(1, 3, "1.0")).unwrap();
+        assert_eq!(engine.get_accounts()[0].available, Decimal::from(10));
+
+        // Gone for good: neither disputable nor unappliable a second time
+        engine.process_transaction(create_dispute(1, 2)).unwrap();
+        assert_eq!(engine.get_accounts()[0].held, Decimal::ZERO);
+        assert!(engine.unapply(&create_deposit(1, 2, "2.5")).is_err());
+    }
+
+    #[test]
+    fn unapply_refused_for_disputed_tx() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+        engine.process_transaction(create_dispute(1, 1)).unwrap();
+
+        assert!(engine.unapply(&create_deposit(1, 1, "10.0")).is_err());
+
+        // Charged back, the account is locked and the deposit stays put too
+        engine.process_transaction(create_chargeback(1, 1)).unwrap();
+        assert!(engine.unapply(&create_deposit(1, 1, "10.0")).is_err());
+        assert!(engine.get_accounts()[0].locked);
+    }
+
+    #[test]
+    fn deposit_withdraw_test() {
+        let mut engine = PaymentEngine::new();
+
+        // Deposit 10.0 to client 1
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+
+        // Withdraw 5.0 from client 1
+        engine
+            .process_transaction(create_withdrawal(1, 2, "5.0"))
+            .unwrap();
+
+        let accounts = engine.get_accounts();
+        assert_eq!(accounts.len(), 1);
+
+        let account = &accounts[0];
+        assert_eq!(account.client, ClientId(1));
+        assert_eq!(account.available, Decimal::from_str("5.0").unwrap());
+        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
+        assert_eq!(account.total, Decimal::from_str("5.0").unwrap());
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn fail_withdraw_no_funds() {
+        let mut engine = PaymentEngine::new();
+
+        // Deposit 5.0 to client 1
+        engine
+            .process_transaction(create_deposit(1, 1, "5.0"))
+            .unwrap();
+
+        // Try to withdraw 10.0 from client 1 (should fail)
+        engine
+            .process_transaction(create_withdrawal(1, 2, "10.0"))
+            .unwrap();
+
+        let accounts = engine.get_accounts();
+        assert_eq!(accounts.len(), 1);
+
+        let account = &accounts[0];
+        assert_eq!(account.client, ClientId(1));
+        assert_eq!(account.available, Decimal::from_str("5.0").unwrap());
+        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
+        assert_eq!(account.total, Decimal::from_str("5.0").unwrap());
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn overdraft_from_account_factory() {
+        let mut engine = PaymentEngine::new();
+        // Only client 1 gets a credit line
+        engine.set_account_factory(|client| match client {
+            ClientId(1) => Account::with_overdraft(Decimal::from_str("20.0").unwrap()),
+            _ => Account::new(),
+        });
+
+        for client in [1, 2] {
+            engine
+                .process_transaction(create_deposit(client, u32::from(client), "5.0"))
+                .unwrap();
+        }
+        // Within client 1's limit, but client 2 has none
+        engine
+            .process_transaction(create_withdrawal(1, 3, "15.0"))
+            .unwrap();
+        engine
+            .process_transaction(create_withdrawal(2, 4, "15.0"))
+            .unwrap();
+        // Beyond client 1's limit: -10 - 10.5 < -20
+        engine
+            .process_transaction(create_withdrawal(1, 5, "10.5"))
+            .unwrap();
+
+        let mut accounts = engine.get_accounts();
+        accounts.sort_by_key(|a| a.client);
+        assert_eq!(accounts[0].available, Decimal::from_str("-10.0").unwrap());
+        assert_eq!(accounts[0].total, Decimal::from_str("-10.0").unwrap());
+        assert_eq!(accounts[1].available, Decimal::from_str("5.0").unwrap());
+        assert_eq!(engine.summary().withdrawals_failed_insufficient, 2);
+    }
+
+    #[test]
+    fn output_scale_from_account_factory() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            output: OutputConfig {
+                fixed_decimals: true,
+                ..OutputConfig::default()
+            },
+            ..EngineConfig::default()
+        });
+        // Client 1 reports in cents, client 2 keeps the default four places
+        engine.set_account_factory(|client| match client {
+            ClientId(1) => Account::with_output_scale(2),
+            _ => Account::new(),
+        });
+
+        for client in [1, 2] {
+            engine
+                .process_transaction(create_deposit(client, u32::from(client), "1.23456"))
+                .unwrap();
         }
+
+        let accounts = engine.get_accounts();
+        assert_eq!(accounts[0].available.to_string(), "1.23");
+        assert_eq!(accounts[0].total.to_string(), "1.23");
+        assert_eq!(accounts[1].available.to_string(), "1.2346");
+        assert_eq!(accounts[1].held.to_string(), "0.0000");
+        // Only the output is rounded, the balance stays exact
+        assert_eq!(
+            engine.accounts[&ClientId(1)].available,
+            Decimal::from_str("1.23456").unwrap()
+        );
+    }
+
+    #[test]
+    fn success_successive_no_transactions_after_failure() {
+        let mut engine = PaymentEngine::new();
+
+        // Deposit 5.0 to client 1
+        engine
+            .process_transaction(create_deposit(1, 1, "5.0"))
+            .unwrap();
+
+        // Try to withdraw 10.0 from client 1 (should fail)
+        engine
+            .process_transaction(create_withdrawal(1, 2, "10.0"))
+            .unwrap();
+
+        // Withdraw 3.0 from client 1 (should succeed)
+        engine
+            .process_transaction(create_withdrawal(1, 3, "3.0"))
+            .unwrap();
+
+        let accounts = engine.get_accounts();
+        assert_eq!(accounts.len(), 1);
+
+        let account = &accounts[0];
+        assert_eq!(account.client, ClientId(1));
+        assert_eq!(account.available, Decimal::from_str("2.0").unwrap());
+        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
+        assert_eq!(account.total, Decimal::from_str("2.0").unwrap());
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn manage_disputes() {
+        let mut engine = PaymentEngine::new();
+
+        // Deposit 10.0 to client 1
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+
+        // Dispute the deposit
+        engine.process_transaction(create_dispute(1, 1)).unwrap();
+
+        let accounts = engine.get_accounts();
+        assert_eq!(accounts.len(), 1);
+
+        let account = &accounts[0];
+        assert_eq!(account.client, ClientId(1));
+        assert_eq!(account.available, Decimal::from_str("0.0").unwrap());
+        assert_eq!(account.held, Decimal::from_str("10.0").unwrap());
+        assert_eq!(account.total, Decimal::from_str("10.0").unwrap());
+        assert!(!account.locked);
+
+        // Resolve the dispute
+        engine.process_transaction(create_resolve(1, 1)).unwrap();
+
+        let accounts = engine.get_accounts();
+        let account = &accounts[0];
+        assert_eq!(account.available, Decimal::from_str("10.0").unwrap());
+        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
+        assert_eq!(account.total, Decimal::from_str("10.0").unwrap());
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn no_dispute_bad_tx() {
+        let mut engine = PaymentEngine::new();
+
+        // Deposit 10.0 to client 1
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+
+        // Try to dispute a non-existent transaction
+        engine.process_transaction(create_dispute(1, 999)).unwrap();
+
+        let accounts = engine.get_accounts();
+        assert_eq!(accounts.len(), 1);
+
+        let account = &accounts[0];
+        assert_eq!(account.client, ClientId(1));
+        assert_eq!(account.available, Decimal::from_str("10.0").unwrap());
+        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
+        assert_eq!(account.total, Decimal::from_str("10.0").unwrap());
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn manage_multiple_disputes() {
+        let mut engine = PaymentEngine::new();
+
+        // Deposit 10.0 to client 1
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+
+        // Deposit 5.0 to client 1
+        engine
+            .process_transaction(create_deposit(1, 2, "5.0"))
+            .unwrap();
+
+        // Dispute both deposits
+        engine.process_transaction(create_dispute(1, 1)).unwrap();
+        engine.process_transaction(create_dispute(1, 2)).unwrap();
+
+        let accounts = engine.get_accounts();
+        assert_eq!(accounts.len(), 1);
+
+        let account = &accounts[0];
+        assert_eq!(account.client, ClientId(1));
+        assert_eq!(account.available, Decimal::from_str("0.0").unwrap());
+        assert_eq!(account.held, Decimal::from_str("15.0").unwrap());
+        assert_eq!(account.total, Decimal::from_str("15.0").unwrap());
+        assert!(!account.locked);
+
+        // Resolve one dispute
+        engine.process_transaction(create_resolve(1, 1)).unwrap();
+
+        let accounts = engine.get_accounts();
+        let account = &accounts[0];
+        assert_eq!(account.available, Decimal::from_str("10.0").unwrap());
+        assert_eq!(account.held, Decimal::from_str("5.0").unwrap());
+        assert_eq!(account.total, Decimal::from_str("15.0").unwrap());
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn no_dispute_no_chargeback() {
+        let mut engine = PaymentEngine::new();
+
+        // Deposit 10.0 to client 1
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+
+        // Try to chargeback without dispute
+        engine.process_transaction(create_chargeback(1, 1)).unwrap();
+
+        let accounts = engine.get_accounts();
+        assert_eq!(accounts.len(), 1);
+
+        let account = &accounts[0];
+        assert_eq!(account.client, ClientId(1));
+        assert_eq!(account.available, Decimal::from_str("10.0").unwrap());
+        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
+        assert_eq!(account.total, Decimal::from_str("10.0").unwrap());
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn chargeback() {
+        let mut engine = PaymentEngine::new();
+
+        // Deposit 10.0 to client 1
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+
+        // Dispute the deposit
+        engine.process_transaction(create_dispute(1, 1)).unwrap();
+
+        // Chargeback the disputed transaction
+        engine.process_transaction(create_chargeback(1, 1)).unwrap();
+
+        let accounts = engine.get_accounts();
+        assert_eq!(accounts.len(), 1);
+
+        let account = &accounts[0];
+        assert_eq!(account.client, ClientId(1));
+        assert_eq!(account.available, Decimal::from_str("0.0").unwrap());
+        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
+        assert_eq!(account.total, Decimal::from_str("0.0").unwrap());
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn no_further_withdraw_after_chargeback() {
+        let mut engine = PaymentEngine::new();
+
+        // Deposit 15.0 to client 1
+        engine
+            .process_transaction(create_deposit(1, 1, "15.0"))
+            .unwrap();
+
+        // Deposit 5.0 to client 1
+        engine
+            .process_transaction(create_deposit(1, 2, "5.0"))
+            .unwrap();
+
+        // Dispute the first deposit
+        engine.process_transaction(create_dispute(1, 1)).unwrap();
+
+        // Chargeback the disputed transaction (locks account)
+        engine.process_transaction(create_chargeback(1, 1)).unwrap();
+
+        // Try to withdraw from the locked account (should fail)
+        engine
+            .process_transaction(create_withdrawal(1, 3, "2.0"))
+            .unwrap();
+
+        let accounts = engine.get_accounts();
+        assert_eq!(accounts.len(), 1);
+
+        let account = &accounts[0];
+        assert_eq!(account.client, ClientId(1));
+        assert_eq!(account.available, Decimal::from_str("5.0").unwrap());
+        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
+        assert_eq!(account.total, Decimal::from_str("5.0").unwrap());
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn disputes_possible_after_chargeback() {
+        let mut engine = PaymentEngine::new();
+
+        // Deposit 10.0 to client 1
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+
+        // Deposit 5.0 to client 1
+        engine
+            .process_transaction(create_deposit(1, 2, "5.0"))
+            .unwrap();
+
+        // Dispute the first deposit
+        engine.process_transaction(create_dispute(1, 1)).unwrap();
+
+        // Dispute the second deposit
+        engine.process_transaction(create_dispute(1, 2)).unwrap();
+
+        // Chargeback the first disputed transaction (locks account)
+        engine.process_transaction(create_chargeback(1, 1)).unwrap();
+
+        // Chargeback the second disputed transaction (should still work)
+        engine.process_transaction(create_chargeback(1, 2)).unwrap();
+
+        let accounts = engine.get_accounts();
+        assert_eq!(accounts.len(), 1);
+
+        let account = &accounts[0];
+        assert_eq!(account.client, ClientId(1));
+        assert_eq!(account.available, Decimal::from_str("0.0").unwrap());
+        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
+        assert_eq!(account.total, Decimal::from_str("0.0").unwrap());
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn resolve_pre_lock_dispute_after_chargeback() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+        engine
+            .process_transaction(create_deposit(1, 2, "5.0"))
+            .unwrap();
+        engine.process_transaction(create_dispute(1, 1)).unwrap();
+        engine.process_transaction(create_dispute(1, 2)).unwrap();
+
+        // Locks the account, with tx 2 still under dispute
+        engine.process_transaction(create_chargeback(1, 1)).unwrap();
+        engine.process_transaction(create_resolve(1, 2)).unwrap();
+
+        let account = &engine.get_accounts()[0];
+        assert_eq!(account.available, Decimal::from_str("5.0").unwrap());
+        assert_eq!(account.held, Decimal::ZERO);
+        assert_eq!(account.total, Decimal::from_str("5.0").unwrap());
+        assert!(account.locked);
+        assert_eq!(engine.summary().open_disputes, 0);
+    }
+
+    #[test]
+    fn pre_lock_disputes_settle_but_no_new_ones_open() {
+        let mut engine = PaymentEngine::new();
+        for record in [
+            create_deposit(1, 1, "10.0"),
+            create_deposit(1, 2, "5.0"),
+            create_withdrawal(1, 3, "2.0"),
+            create_deposit(1, 4, "1.0"),
+            create_dispute(1, 2),
+            create_dispute(1, 3),
+            admin(TransactionType::Freeze, 1, 5),
+            // After the lock: tx 1 and 4 can't be disputed any more
+            create_dispute(1, 1),
+            create_dispute(1, 4),
+            create_resolve(1, 2),
+            create_resolve(1, 3),
+        ] {
+            engine.process_transaction(record).unwrap();
+        }
+
+        let account = &engine.get_accounts()[0];
+        // Tx 2's funds were released, tx 3's provisional credit dropped
+        assert_eq!(account.available, Decimal::from_str("14.0").unwrap());
+        assert_eq!(account.held, Decimal::ZERO);
+        assert!(account.locked);
+        assert!(!engine.transactions[&TxId(1)].disputed);
+        assert!(!engine.transactions[&TxId(4)].had_dispute);
+        assert_eq!(engine.summary().open_disputes, 0);
+        assert_eq!(engine.accounts[&ClientId(1)].disputes, 2);
+        assert_eq!(engine.verify_held_consistency(), Ok(()));
+
+        // Nothing to charge back either, so the freeze stays the only lock
+        engine.process_transaction(create_chargeback(1, 1)).unwrap();
+        assert_eq!(
+            engine.accounts[&ClientId(1)].lock_cause,
+            Some(LockCause::Freeze)
+        );
+    }
+
+    fn admin(tx_type: TransactionType, client: u16, tx: u32) -> TransactionRecord {
+        TransactionRecord {
+            tx_type,
+            client: client.into(),
+            tx: tx.into(),
+            amount: None,
+            memo: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn freeze_and_unfreeze() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+
+        engine
+            .process_transaction(admin(TransactionType::Freeze, 1, 2))
+            .unwrap();
+        let account = &engine.get_accounts()[0];
+        assert!(account.locked);
+        assert_eq!(account.total, Decimal::from_str("10.0").unwrap());
+
+        engine
+            .process_transaction(create_withdrawal(1, 3, "1.0"))
+            .unwrap();
+        assert_eq!(engine.summary().withdrawals_failed_locked, 1);
+
+        engine
+            .process_transaction(admin(TransactionType::Unfreeze, 1, 4))
+            .unwrap();
+        engine
+            .process_transaction(create_withdrawal(1, 5, "1.0"))
+            .unwrap();
+
+        let account = &engine.get_accounts()[0];
+        assert!(!account.locked);
+        assert_eq!(account.total, Decimal::from_str("9.0").unwrap());
+    }
+
+    #[test]
+    fn unfreeze_refused_after_chargeback() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+        engine
+            .process_transaction(create_deposit(1, 2, "5.0"))
+            .unwrap();
+        engine.process_transaction(create_dispute(1, 1)).unwrap();
+
+        // Disputes opened before a freeze can still be charged back, turning it into a final lock
+        engine
+            .process_transaction(admin(TransactionType::Freeze, 1, 3))
+            .unwrap();
+        engine.process_transaction(create_chargeback(1, 1)).unwrap();
+
+        // A freeze on top of the chargeback lock doesn't make it liftable
+        engine
+            .process_transaction(admin(TransactionType::Freeze, 1, 5))
+            .unwrap();
+        assert!(
+            engine
+                .process_transaction(admin(TransactionType::Unfreeze, 1, 6))
+                .is_err()
+        );
+
+        let account = &engine.get_accounts()[0];
+        assert!(account.locked);
+        assert_eq!(account.total, Decimal::from_str("5.0").unwrap());
+        assert_eq!(engine.summary().records_rejected, 1);
+    }
+
+    fn create_correction(client: u16, tx: u32, amount: &str) -> TransactionRecord {
+        TransactionRecord {
+            tx_type: TransactionType::Correction,
+            client: client.into(),
+            tx: tx.into(),
+            amount: Some(Decimal::from_str(amount).unwrap()),
+            memo: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn corrections_adjust_available() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+        engine
+            .process_transaction(create_correction(1, 2, "2.5"))
+            .unwrap();
+        engine
+            .process_transaction(create_correction(1, 3, "-4.0"))
+            .unwrap();
+
+        let account = &engine.get_accounts()[0];
+        assert_eq!(account.available, Decimal::from_str("8.5").unwrap());
+
+        // Corrections are stored and can be disputed like deposits
+        engine.process_transaction(create_dispute(1, 2)).unwrap();
+        let account = &engine.get_accounts()[0];
+        assert_eq!(account.held, Decimal::from_str("2.5").unwrap());
+        assert_eq!(account.total, Decimal::from_str("8.5").unwrap());
+
+        // Disputing a negative one holds nothing
+        engine.process_transaction(create_dispute(1, 3)).unwrap();
+        assert_eq!(
+            engine.get_accounts()[0].held,
+            Decimal::from_str("2.5").unwrap()
+        );
+    }
+
+    #[test]
+    fn correction_cannot_take_total_below_zero() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process_transaction(create_deposit(1, 1, "5.0"))
+            .unwrap();
+
+        assert!(
+            engine
+                .process_transaction(create_correction(1, 2, "-5.0001"))
+                .is_err()
+        );
+        engine
+            .process_transaction(create_correction(1, 3, "-5.0"))
+            .unwrap();
+        assert_eq!(engine.get_accounts()[0].total, Decimal::ZERO);
+        assert!(!engine.transactions.contains_key(&TxId(2)));
+
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            corrections: CorrectionPolicy::Unbounded,
+            ..EngineConfig::default()
+        });
+        engine
+            .process_transaction(create_correction(1, 1, "-1.0"))
+            .unwrap();
+        assert_eq!(
+            engine.get_accounts()[0].total,
+            Decimal::from_str("-1.0").unwrap()
+        );
+    }
+
+    #[test]
+    fn held_consistency() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            partial_disputes: true,
+            ..EngineConfig::default()
+        });
+        for record in [
+            create_deposit(1, 1, "10.0"),
+            create_deposit(1, 2, "5.0"),
+            create_deposit(2, 3, "1.0"),
+            create_deposit(3, 4, "1.0"),
+            create_dispute(1, 1),
+            create_dispute(1, 2),
+            create_resolve(1, 2),
+            create_dispute(2, 3),
+            create_chargeback(2, 3),
+        ] {
+            engine.process_transaction(record).unwrap();
+        }
+        assert_eq!(engine.verify_held_consistency(), Ok(()));
+
+        engine.account_mut(ClientId(3)).unwrap().held += Decimal::ONE;
+        engine.account_mut(ClientId(1)).unwrap().held -= Decimal::ONE;
+        assert_eq!(
+            engine.verify_held_consistency(),
+            Err(vec![ClientId(1), ClientId(3)])
+        );
+    }
+
+    #[test]
+    fn stale_disputes_auto_resolved() {
+        let mut engine = PaymentEngine::new();
+        for record in [
+            create_deposit(1, 1, "10.0"),
+            create_deposit(2, 2, "5.0"),
+            create_dispute(1, 1),
+            create_deposit(3, 3, "1.0"),
+            create_deposit(3, 4, "1.0"),
+            create_dispute(2, 2),
+            create_deposit(3, 5, "1.0"),
+        ] {
+            engine.process_transaction(record).unwrap();
+        }
+
+        // Client 1's dispute has seen 4 further records, client 2's only 1
+        engine.set_finalizer(|engine| engine.auto_resolve_disputes(2));
+        engine.finalize();
+
+        let mut accounts = engine.get_accounts();
+        accounts.sort_by_key(|a| a.client);
+        assert_eq!(accounts[0].available, Decimal::from_str("10.0").unwrap());
+        assert_eq!(accounts[0].held, Decimal::ZERO);
+        assert_eq!(accounts[1].available, Decimal::ZERO);
+        assert_eq!(accounts[1].held, Decimal::from_str("5.0").unwrap());
+
+        let summary = engine.summary();
+        assert_eq!(summary.disputes_auto_resolved, 1);
+        assert_eq!(summary.open_disputes, 1);
+        assert_eq!(engine.verify_held_consistency(), Ok(()));
+    }
+
+    #[test]
+    fn stale_disputes_auto_charged_back() {
+        let mut engine = PaymentEngine::new();
+        for record in [
+            create_deposit(1, 1, "10.0"),
+            create_deposit(2, 2, "5.0"),
+            create_dispute(1, 1),
+            create_deposit(3, 3, "1.0"),
+            create_deposit(3, 4, "1.0"),
+            create_dispute(2, 2),
+            create_deposit(3, 5, "1.0"),
+        ] {
+            engine.process_transaction(record).unwrap();
+        }
+
+        // Client 1's dispute has seen 4 further records, client 2's only 1
+        engine.set_finalizer(|engine| engine.auto_chargeback_disputes(2));
+        engine.finalize();
+
+        let mut accounts = engine.get_accounts();
+        accounts.sort_by_key(|a| a.client);
+        assert_eq!(accounts[0].available, Decimal::ZERO);
+        assert_eq!(accounts[0].held, Decimal::ZERO);
+        assert!(accounts[0].locked);
+        assert_eq!(accounts[1].held, Decimal::from_str("5.0").unwrap());
+        assert!(!accounts[1].locked);
+
+        let summary = engine.summary();
+        assert_eq!(summary.disputes_auto_charged_back, 1);
+        assert_eq!(summary.open_disputes, 1);
+        assert_eq!(engine.verify_held_consistency(), Ok(()));
+
+        // Charged back like any other, so it can be reversed after review
+        engine
+            .process_transaction(admin(TransactionType::ChargebackReversal, 1, 1))
+            .unwrap();
+        assert!(!engine.get_accounts().iter().any(|a| a.locked));
+    }
+
+    #[test]
+    fn latest_memo_in_verbose_output() {
+        use crate::output::{OutputColumn, column_names, write_accounts};
+        use crate::reader::{InputConfig, ParseMode, TransactionReader};
+
+        let input = "type,client,tx,amount,memo\n\
+                     deposit,1,1,10.0,opening balance\n\
+                     deposit,1,2,5.0,\"refund, see \"\"case 7\"\"\"\n\
+                     withdrawal,1,3,1.0,\n\
+                     withdrawal,1,4,100.0,refused\n";
+
+        // The refused withdrawal leaves the memo of the last applied record
+        for parse_mode in [ParseMode::Serde, ParseMode::ByteRecord] {
+            let config = InputConfig {
+                parse_mode,
+                ..InputConfig::default()
+            };
+            let mut engine = PaymentEngine::with_config(EngineConfig {
+                output: OutputConfig {
+                    verbose: true,
+                    ..OutputConfig::default()
+                },
+                ..EngineConfig::default()
+            });
+            for record in TransactionReader::new(input.as_bytes(), &config).unwrap() {
+                engine.process_transaction(record.unwrap()).unwrap();
+            }
+
+            let mut output = Vec::new();
+            let header = column_names(&OutputColumn::standard(&engine.config.output));
+            write_accounts(engine.accounts(), &header, &mut output, None).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                "client,available,held,total,locked,had_dispute,memo\n\
+                 1,14.0,0,14.0,false,false,\"refund, see \"\"case 7\"\"\"\n"
+            );
+        }
+    }
+
+    #[test]
+    fn account_limit_rejects_new_clients() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            max_accounts: Some(2),
+            ..EngineConfig::default()
+        });
+        engine
+            .process_transaction(create_deposit(1, 1, "1.0"))
+            .unwrap();
+        engine
+            .process_transaction(create_deposit(2, 2, "1.0"))
+            .unwrap();
+        assert!(
+            engine
+                .process_transaction(create_deposit(3, 3, "1.0"))
+                .is_err()
+        );
+        engine
+            .process_transaction(create_deposit(1, 4, "1.0"))
+            .unwrap();
+
+        let summary = engine.summary();
+        assert_eq!(summary.accounts, 2);
+        assert_eq!(summary.account_limit_rejections, 1);
+        assert_eq!(summary.records_rejected, 1);
+        assert_eq!(summary.total, Decimal::from(3));
+    }
+
+    #[test]
+    fn state_as_json() {
+        let mut engine = PaymentEngine::new();
+        for record in [
+            create_deposit(2, 1, "10.0"),
+            create_deposit(1, 2, "5.5"),
+            create_deposit(2, 3, "1.0"),
+            create_dispute(2, 3),
+        ] {
+            engine.process_transaction(record).unwrap();
+        }
+
+        assert_eq!(
+            engine.to_json(),
+            json!({
+                "accounts": [
+                    {"client": 1, "available": "5.5", "held": "0", "total": "5.5", "locked": false},
+                    {"client": 2, "available": "10.0", "held": "1.0", "total": "11.0", "locked": false},
+                ],
+                "open_disputes": [
+                    {"tx": 3, "client": 2, "amount": "1.0", "held": "1.0"},
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn duplicate_disputes_and_resolves_counted() {
+        let mut engine = PaymentEngine::new();
+        for record in [
+            create_deposit(1, 1, "10.0"),
+            create_dispute(1, 1),
+            create_dispute(1, 1),
+            create_resolve(1, 1),
+            create_resolve(1, 1),
+            // Wrong client, not a duplicate of anything
+            create_dispute(2, 1),
+            // Never disputed, or charged back rather than resolved
+            create_deposit(1, 2, "1.0"),
+            create_resolve(1, 2),
+            create_dispute(1, 2),
+            create_chargeback(1, 2),
+            create_resolve(1, 2),
+        ] {
+            engine.process_transaction(record).unwrap();
+        }
+
+        let summary = engine.summary();
+        assert_eq!(summary.duplicate_disputes, 1);
+        assert_eq!(summary.duplicate_resolves, 1);
+
+        // The duplicate dispute didn't hold twice
+        let account = engine.account_mut(ClientId(1)).unwrap();
+        assert_eq!(account.available, Decimal::from_str("10.0").unwrap());
+        assert_eq!(account.held, Decimal::ZERO);
+    }
+
+    #[test]
+    fn dormant_accounts_by_last_activity() {
+        let at = |mut record: TransactionRecord, timestamp| {
+            record.timestamp = Some(timestamp);
+            record
+        };
+
+        let mut engine = PaymentEngine::new();
+        for record in [
+            at(create_deposit(1, 1, "1.0"), 1_000),
+            at(create_deposit(2, 2, "1.0"), 1_000),
+            at(create_deposit(3, 3, "1.0"), 5_000),
+            // Client 2 became active again, arriving out of order doesn't undo that
+            at(create_withdrawal(2, 4, "0.5"), 9_000),
+            at(create_dispute(2, 2), 8_000),
+            at(create_deposit(4, 5, "1.0"), 10_000),
+            // No timestamp, not classified either way
+            create_deposit(5, 6, "1.0"),
+        ] {
+            engine.process_transaction(record).unwrap();
+        }
+
+        let dormant = engine.dormant_accounts(5_000);
+        assert_eq!(
+            dormant,
+            [DormantAccount {
+                client: ClientId(1),
+                last_activity: 1_000,
+                idle_secs: 9_000,
+            }]
+        );
+
+        // Exactly at the threshold is still active
+        let clients: Vec<_> = engine
+            .dormant_accounts(4_999)
+            .iter()
+            .map(|d| d.client)
+            .collect();
+        assert_eq!(clients, [ClientId(1), ClientId(3)]);
+
+        assert!(PaymentEngine::new().dormant_accounts(0).is_empty());
+    }
+
+    #[test]
+    fn dispute_ratio_guard() {
+        for action in [DisputeRatioAction::Warn, DisputeRatioAction::Freeze] {
+            let mut engine = PaymentEngine::with_config(EngineConfig {
+                dispute_ratio: Some(DisputeRatioGuard {
+                    max_ratio: Decimal::from_str("0.5").unwrap(),
+                    action,
+                }),
+                ..EngineConfig::default()
+            });
+            for tx in 1..=4 {
+                engine
+                    .process_transaction(create_deposit(1, tx, "1.0"))
+                    .unwrap();
+                engine
+                    .process_transaction(create_deposit(2, tx + 10, "1.0"))
+                    .unwrap();
+            }
+
+            // Two disputes out of four deposits is still within the ratio
+            for tx in [1, 2] {
+                engine.process_transaction(create_dispute(1, tx)).unwrap();
+                engine
+                    .process_transaction(create_dispute(2, tx + 10))
+                    .unwrap();
+            }
+            // The third tips client 1 over, further disputes don't count it again
+            engine.process_transaction(create_dispute(1, 3)).unwrap();
+            engine.process_transaction(create_resolve(1, 3)).unwrap();
+            let _ = engine.process_transaction(create_dispute(1, 4));
+
+            assert_eq!(engine.summary().dispute_ratio_breaches, 1);
+            assert!(
+                !engine
+                    .account_mut(ClientId(2))
+                    .unwrap()
+                    .dispute_ratio_breached
+            );
+
+            let account = engine.account_mut(ClientId(1)).unwrap();
+            assert!(account.dispute_ratio_breached);
+            match action {
+                DisputeRatioAction::Warn => {
+                    assert!(!account.locked);
+                    assert_eq!(account.disputes, 4);
+                }
+                DisputeRatioAction::Freeze => {
+                    assert!(account.locked);
+                    assert_eq!(account.lock_cause, Some(LockCause::Freeze));
+                    // Frozen, so no new hold was placed
+                    assert_eq!(account.held, Decimal::from_str("2.0").unwrap());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dispute_window_by_records() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            dispute_window: Some(DisputeWindow::Records(3)),
+            ..EngineConfig::default()
+        });
+        engine
+            .process_transaction(create_deposit(1, 1, "1.0"))
+            .unwrap();
+        engine
+            .process_transaction(create_deposit(1, 2, "2.0"))
+            .unwrap();
+        engine
+            .process_transaction(create_deposit(1, 3, "4.0"))
+            .unwrap();
+
+        // Three records after tx 2, still inside
+        engine.process_transaction(create_dispute(1, 2)).unwrap();
+        // Four records after tx 1, just outside
+        assert!(engine.process_transaction(create_dispute(1, 1)).is_err());
+
+        // Re-disputing after a resolve still counts from when the tx was stored
+        engine.process_transaction(create_resolve(1, 2)).unwrap();
+        assert!(engine.process_transaction(create_dispute(1, 2)).is_err());
+
+        assert_eq!(engine.summary().out_of_window_disputes, 2);
+        let account = engine.account_mut(ClientId(1)).unwrap();
+        assert_eq!(account.held, Decimal::ZERO);
+        assert_eq!(account.available, Decimal::from_str("7.0").unwrap());
+    }
+
+    #[test]
+    fn dispute_window_by_seconds() {
+        let at = |mut record: TransactionRecord, timestamp| {
+            record.timestamp = Some(timestamp);
+            record
+        };
+
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            dispute_window: Some(DisputeWindow::Seconds(100)),
+            ..EngineConfig::default()
+        });
+        engine
+            .process_transaction(at(create_deposit(1, 1, "1.0"), 1_000))
+            .unwrap();
+        engine
+            .process_transaction(at(create_deposit(1, 2, "2.0"), 1_001))
+            .unwrap();
+        engine
+            .process_transaction(create_deposit(1, 3, "4.0"))
+            .unwrap();
+
+        assert!(
+            engine
+                .process_transaction(at(create_dispute(1, 1), 1_101))
+                .is_err()
+        );
+        engine
+            .process_transaction(at(create_dispute(1, 2), 1_101))
+            .unwrap();
+        // Without timestamps on both sides there's nothing to go by
+        engine
+            .process_transaction(at(create_dispute(1, 3), 9_999))
+            .unwrap();
+
+        assert_eq!(engine.summary().out_of_window_disputes, 1);
+        let account = engine.account_mut(ClientId(1)).unwrap();
+        assert_eq!(account.held, Decimal::from_str("6.0").unwrap());
+    }
+
+    #[test]
+    fn cross_client_disputes_flag_the_disputer() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            cross_client_disputes: CrossClientDisputePolicy::Reject,
+            ..EngineConfig::default()
+        });
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+        engine
+            .process_transaction(create_deposit(2, 2, "1.0"))
+            .unwrap();
+
+        assert!(engine.process_transaction(create_dispute(2, 1)).is_err());
+        assert!(engine.process_transaction(create_dispute(2, 1)).is_err());
+        // Disputes of unknown transactions are still ignored
+        engine.process_transaction(create_dispute(2, 99)).unwrap();
+        // The owner's own dispute goes ahead
+        engine.process_transaction(create_dispute(1, 1)).unwrap();
+
+        assert_eq!(engine.summary().cross_client_disputes, 2);
+        let disputer = engine.account_mut(ClientId(2)).unwrap();
+        assert_eq!(disputer.suspicious_disputes, 2);
+        assert_eq!(disputer.available, Decimal::ONE);
+        let owner = engine.account_mut(ClientId(1)).unwrap();
+        assert_eq!(owner.suspicious_disputes, 0);
+        assert_eq!(owner.held, Decimal::from_str("10.0").unwrap());
+    }
+
+    #[test]
+    fn cross_client_disputes_ignored_by_default() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+        engine.process_transaction(create_dispute(2, 1)).unwrap();
+
+        assert_eq!(engine.summary().cross_client_disputes, 0);
+        assert_eq!(
+            engine.account_mut(ClientId(2)).unwrap().suspicious_disputes,
+            0
+        );
+        assert_eq!(engine.account_mut(ClientId(1)).unwrap().held, Decimal::ZERO);
+    }
+
+    #[test]
+    fn deposit_first_account_creation() {
+        let records = || {
+            [
+                create_withdrawal(1, 1, "1.0"),
+                create_dispute(2, 1),
+                create_deposit(3, 2, "1.0"),
+                create_withdrawal(3, 3, "1.0"),
+            ]
+        };
+
+        // By default any record opens an account, leaving empty ones behind
+        let mut engine = PaymentEngine::new();
+        for record in records() {
+            engine.process_transaction(record).unwrap();
+        }
+        assert_eq!(engine.summary().accounts, 3);
+        assert_eq!(engine.summary().withdrawals_failed_insufficient, 1);
+
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            account_creation: AccountCreation::DepositFirst,
+            ..EngineConfig::default()
+        });
+        let results: Vec<_> = records()
+            .into_iter()
+            .map(|record| engine.process_transaction(record).is_ok())
+            .collect();
+        assert_eq!(results, [false, false, true, true]);
+
+        let summary = engine.summary();
+        assert_eq!(summary.accounts, 1);
+        assert_eq!(summary.unknown_client_rejections, 2);
+        assert_eq!(summary.withdrawals_failed_insufficient, 0);
+    }
+
+    #[test]
+    fn locked_and_insufficient_withdrawal_reason() {
+        for (priority, locked, insufficient) in [
+            (WithdrawErrorPriority::Locked, 1, 0),
+            (WithdrawErrorPriority::InsufficientFunds, 0, 1),
+        ] {
+            let mut engine = PaymentEngine::with_config(EngineConfig {
+                withdraw_error_priority: priority,
+                ..EngineConfig::default()
+            });
+            engine
+                .process_transaction(create_deposit(1, 1, "5.0"))
+                .unwrap();
+            engine.account_mut(ClientId(1)).unwrap().freeze();
+            engine
+                .process_transaction(create_withdrawal(1, 2, "10.0"))
+                .unwrap();
+
+            let summary = engine.summary();
+            assert_eq!(summary.withdrawals_failed_locked, locked, "{priority:?}");
+            assert_eq!(summary.withdrawals_failed_insufficient, insufficient);
+        }
+    }
+
+    #[test]
+    fn strict_sequencing_rejects_settling_undisputed_txs() {
+        let strict = EngineConfig {
+            dispute_sequencing: DisputeSequencing::Strict,
+            ..EngineConfig::default()
+        };
+        let mut engine = PaymentEngine::with_config(strict);
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+
+        let error = |result: Result<()>| result.unwrap_err().downcast::<SequenceError>().unwrap();
+        assert_eq!(
+            error(engine.process_transaction(create_resolve(1, 1))),
+            SequenceError::NotDisputed("resolve", TxId(1))
+        );
+        assert_eq!(
+            error(engine.process_transaction(create_chargeback(1, 2))),
+            SequenceError::UnknownTx("chargeback", TxId(2))
+        );
+        // Another client's tx is as good as unknown
+        assert_eq!(
+            error(engine.process_transaction(create_resolve(2, 1))),
+            SequenceError::UnknownTx("resolve", TxId(1))
+        );
+
+        engine.process_transaction(create_dispute(1, 1)).unwrap();
+        engine.process_transaction(create_resolve(1, 1)).unwrap();
+        assert_eq!(engine.summary().records_rejected, 3);
+
+        // Lenient by default: the same resolve is a no-op, and not a duplicate of anything
+        let mut engine = PaymentEngine::new();
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+        engine.process_transaction(create_resolve(1, 1)).unwrap();
+        assert_eq!(engine.summary().records_processed, 2);
+        assert_eq!(engine.summary().duplicate_resolves, 0);
+    }
+
+    #[test]
+    fn merge_clients_preserves_disputes_and_locks() {
+        let mut engine = PaymentEngine::new();
+        for record in [
+            create_deposit(1, 1, "10.0"),
+            create_dispute(1, 1),
+            admin(TransactionType::Freeze, 1, 4),
+            create_deposit(2, 2, "5.0"),
+            create_deposit(2, 3, "3.0"),
+            create_dispute(2, 2),
+            create_dispute(2, 3),
+            // Locks client 2 with tx 2 still under dispute
+            create_chargeback(2, 3),
+        ] {
+            engine.process_transaction(record).unwrap();
+        }
+
+        assert!(engine.merge_clients(ClientId(2), ClientId(2)).is_err());
+        assert!(engine.merge_clients(ClientId(9), ClientId(1)).is_err());
+        engine.merge_clients(ClientId(2), ClientId(1)).unwrap();
+
+        let accounts = engine.get_accounts();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].client, ClientId(1));
+        assert_eq!(accounts[0].available, Decimal::ZERO);
+        assert_eq!(accounts[0].held, Decimal::from_str("15.0").unwrap());
+        assert!(accounts[0].locked);
+        // The chargeback lock wins over the freeze, so unfreezing can't lift it
+        let account = &engine.accounts[&ClientId(1)];
+        assert_eq!(account.lock_cause, Some(LockCause::Chargeback));
+        assert_eq!(account.chargebacks, 1);
+
+        for tx in [1, 2] {
+            let stored = &engine.transactions[&TxId(tx)];
+            assert!(stored.disputed);
+            assert_eq!(stored.client, ClientId(1));
+        }
+        assert_eq!(engine.client_transactions(ClientId(2)).count(), 0);
+        assert_eq!(engine.client_transactions(ClientId(1)).count(), 3);
+        assert_eq!(engine.summary().open_disputes, 2);
+        assert_eq!(engine.verify_held_consistency(), Ok(()));
+
+        // Client 2's dispute now settles through client 1
+        engine.process_transaction(create_resolve(1, 2)).unwrap();
+        let account = &engine.get_accounts()[0];
+        assert_eq!(account.available, Decimal::from_str("5.0").unwrap());
+        assert_eq!(account.held, Decimal::from_str("10.0").unwrap());
+    }
+
+    #[test]
+    fn per_type_counts_and_volumes() {
+        let mut engine = PaymentEngine::new();
+        for record in [
+            create_deposit(1, 1, "10.0"),
+            create_deposit(2, 2, "5.25"),
+            create_withdrawal(1, 3, "2.5"),
+            // Refused, counted but moves nothing
+            create_withdrawal(2, 4, "100.0"),
+            create_dispute(2, 2),
+            create_chargeback(2, 2),
+            // Locked by the chargeback, so nothing is deposited
+            create_deposit(2, 5, "1.0"),
+            create_withdrawal(1, 6, "0.5"),
+        ] {
+            engine.process_transaction(record).unwrap();
+        }
+        // Rejected records aren't counted at all
+        assert!(
+            engine
+                .process_transaction(create_deposit(3, 7, "-1.0"))
+                .is_err()
+        );
+
+        let d = |s: &str| Decimal::from_str(s).unwrap();
+        let types = engine.summary().transaction_types;
+        let stats = |name: &str| {
+            let stats = &types[name];
+            (stats.count, stats.volume)
+        };
+        assert_eq!(stats("deposit"), (3, Some(d("15.25"))));
+        assert_eq!(stats("withdrawal"), (3, Some(d("3.0"))));
+        assert_eq!(stats("dispute"), (1, None));
+        assert_eq!(stats("chargeback"), (1, None));
+        assert_eq!(types.len(), 4);
+    }
+
+    #[test]
+    fn amount_histogram_buckets() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            amount_histogram: true,
+            ..EngineConfig::default()
+        });
+        let amounts = [
+            "0.5", "0.99", "1", "9.99", "50", "100", "2500", "100000", "1000000",
+        ];
+        for (tx, amount) in (1..).zip(amounts) {
+            engine
+                .process_transaction(create_deposit(1, tx, amount))
+                .unwrap();
+        }
+        for record in [
+            create_withdrawal(1, 20, "5.0"),
+            // Refused, but still bucketed like it's counted
+            create_withdrawal(1, 21, "5000000"),
+            create_dispute(1, 1),
+        ] {
+            engine.process_transaction(record).unwrap();
+        }
+
+        let types = engine.summary().transaction_types;
+        let buckets = |name: &str| types[name].histogram.as_ref().map(|h| h.buckets);
+        assert_eq!(buckets("deposit"), Some([2, 2, 1, 1, 1, 0, 2]));
+        assert_eq!(buckets("withdrawal"), Some([0, 1, 0, 0, 0, 0, 1]));
+        assert_eq!(buckets("dispute"), None);
+
+        let json = serde_json::to_value(&types["withdrawal"]).unwrap();
+        assert_eq!(json["histogram"]["under_10"], 1);
+        assert_eq!(json["histogram"]["100000_and_over"], 1);
+
+        // Off by default
+        let mut plain = PaymentEngine::new();
+        plain
+            .process_transaction(create_deposit(1, 1, "1.0"))
+            .unwrap();
+        assert_eq!(plain.summary().transaction_types["deposit"].histogram, None);
+    }
+
+    #[test]
+    fn withdrawal_fees() {
+        let d = |s: &str| Decimal::from_str(s).unwrap();
+
+        // Flat fee, credited to a fee account
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            withdrawal_fee: Some(WithdrawalFee::Flat(d("0.5"))),
+            fee_account: Some(ClientId(99)),
+            ..EngineConfig::default()
+        });
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+        engine
+            .process_transaction(create_withdrawal(1, 2, "4.0"))
+            .unwrap();
+        // 5.5 left, can't cover 5.5 + 0.5
+        engine
+            .process_transaction(create_withdrawal(1, 3, "5.5"))
+            .unwrap();
+
+        assert_eq!(engine.accounts[&ClientId(1)].available, d("5.5"));
+        assert_eq!(engine.accounts[&ClientId(99)].available, d("0.5"));
+        // The stored withdrawal is the amount alone, the fee isn't disputable
+        assert_eq!(engine.transactions[&TxId(2)].amount, d("4.0"));
+        let summary = engine.summary();
+        assert_eq!(summary.fees_collected, d("0.5"));
+        assert_eq!(summary.withdrawals_failed_insufficient, 1);
+
+        // Percentage fee, only counted in the summary
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            withdrawal_fee: Some("1.5%".parse().unwrap()),
+            ..EngineConfig::default()
+        });
+        engine
+            .process_transaction(create_deposit(1, 1, "100.0"))
+            .unwrap();
+        engine
+            .process_transaction(create_withdrawal(1, 2, "50.0"))
+            .unwrap();
+        engine
+            .process_transaction(create_withdrawal(1, 3, "49.0"))
+            .unwrap();
+
+        assert_eq!(engine.accounts[&ClientId(1)].available, d("49.25"));
+        assert_eq!(engine.summary().fees_collected, d("0.75"));
+        assert_eq!(engine.summary().accounts, 1);
+        assert_eq!(engine.summary().withdrawals_failed_insufficient, 1);
+    }
+
+    #[test]
+    fn withdrawal_fees_reach_a_locked_fee_account() {
+        let d = |s: &str| Decimal::from_str(s).unwrap();
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            withdrawal_fee: Some(WithdrawalFee::Flat(d("0.5"))),
+            fee_account: Some(ClientId(99)),
+            ..EngineConfig::default()
+        });
+        for record in [
+            create_deposit(99, 1, "1.0"),
+            admin(TransactionType::Freeze, 99, 2),
+            create_deposit(1, 3, "10.0"),
+            create_withdrawal(1, 4, "4.0"),
+        ] {
+            engine.process_transaction(record).unwrap();
+        }
+
+        // Every fee taken from a client shows up in the fee account
+        let fee_account = &engine.accounts[&ClientId(99)];
+        assert!(fee_account.locked);
+        assert_eq!(fee_account.available, d("1.5"));
+        assert_eq!(engine.accounts[&ClientId(1)].available, d("5.5"));
+        assert_eq!(engine.summary().fees_collected, d("0.5"));
+    }
+
+    #[test]
+    fn withdrawal_dispute_then_resolve() {
+        let d = |s: &str| Decimal::from_str(s).unwrap();
+        let mut engine = PaymentEngine::new();
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+        engine
+            .process_transaction(create_withdrawal(1, 2, "4.0"))
+            .unwrap();
+
+        // The withdrawn amount is provisionally credited back as held
+        engine.process_transaction(create_dispute(1, 2)).unwrap();
+        let account = &engine.get_accounts()[0];
+        assert_eq!(account.available, d("6.0"));
+        assert_eq!(account.held, d("4.0"));
+        assert_eq!(account.total, d("10.0"));
+        assert_eq!(engine.verify_held_consistency(), Ok(()));
+
+        // The withdrawal stands, the credit goes away
+        engine.process_transaction(create_resolve(1, 2)).unwrap();
+        let account = &engine.get_accounts()[0];
+        assert_eq!(account.available, d("6.0"));
+        assert_eq!(account.held, Decimal::ZERO);
+        assert_eq!(account.total, d("6.0"));
+        assert!(!account.locked);
+        assert_eq!(engine.verify_held_consistency(), Ok(()));
+    }
+
+    #[test]
+    fn withdrawal_dispute_then_chargeback() {
+        let d = |s: &str| Decimal::from_str(s).unwrap();
+        let mut engine = PaymentEngine::new();
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+        // Withdraw everything, the dispute must not need any available funds
+        engine
+            .process_transaction(create_withdrawal(1, 2, "10.0"))
+            .unwrap();
+        engine.process_transaction(create_dispute(1, 2)).unwrap();
+        assert_eq!(engine.get_accounts()[0].held, d("10.0"));
+
+        // The withdrawal is reversed, the money is back in available
+        engine.process_transaction(create_chargeback(1, 2)).unwrap();
+        let account = &engine.get_accounts()[0];
+        assert_eq!(account.available, d("10.0"));
+        assert_eq!(account.held, Decimal::ZERO);
+        assert_eq!(account.total, d("10.0"));
+        assert!(account.locked);
+        assert_eq!(engine.verify_held_consistency(), Ok(()));
+
+        // And it can't be charged back a second time
+        engine.process_transaction(create_chargeback(1, 2)).unwrap();
+        assert_eq!(engine.get_accounts()[0].total, d("10.0"));
+    }
+
+    #[test]
+    fn net_flow_columns() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            output: OutputConfig {
+                net_flow: true,
+                ..OutputConfig::default()
+            },
+            ..EngineConfig::default()
+        });
+        for record in [
+            create_deposit(1, 1, "10.0"),
+            create_deposit(1, 2, "5.5"),
+            create_withdrawal(1, 3, "3.0"),
+            create_withdrawal(1, 4, "100.0"),
+            create_dispute(1, 2),
+            create_withdrawal(1, 5, "2.25"),
+        ] {
+            engine.process_transaction(record).unwrap();
+        }
+
+        // The refused withdrawal doesn't count, and a dispute isn't a flow
+        let account = &engine.get_accounts()[0];
+        assert_eq!(account.deposited, Some(Decimal::from_str("15.5").unwrap()));
+        assert_eq!(account.withdrawn, Some(Decimal::from_str("5.25").unwrap()));
+        assert_eq!(account.total, Decimal::from_str("10.25").unwrap());
+
+        let mut plain = PaymentEngine::new();
+        plain
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+        assert_eq!(plain.get_accounts()[0].deposited, None);
+    }
+
+    #[test]
+    fn closed_status_column() {
+        let records = [
+            create_deposit(1, 1, "10.0"),
+            create_dispute(1, 1),
+            create_chargeback(1, 1),
+            create_deposit(2, 2, "10.0"),
+            create_deposit(2, 3, "5.0"),
+            create_dispute(2, 3),
+            create_chargeback(2, 3),
+            create_deposit(3, 4, "1.0"),
+        ];
+
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            output: OutputConfig {
+                closed_status: true,
+                ..OutputConfig::default()
+            },
+            ..EngineConfig::default()
+        });
+        for record in records.clone() {
+            engine.process_transaction(record).unwrap();
+        }
+
+        // Client 2 is locked too, but still has money on the account
+        let closed: Vec<_> = engine.get_accounts().iter().map(|a| a.closed).collect();
+        assert_eq!(closed, [Some(true), Some(false), Some(false)]);
+
+        // Written in the same format as `locked`, with or without selected columns
+        let mut yes_no = PaymentEngine::with_config(EngineConfig {
+            output: OutputConfig {
+                closed_status: true,
+                bool_format: BoolFormat::YesNo,
+                ..OutputConfig::default()
+            },
+            ..EngineConfig::default()
+        });
+        for record in records[..3].iter().cloned() {
+            yes_no.process_transaction(record).unwrap();
+        }
+        let mut buffer = Vec::new();
+        output::write_accounts(yes_no.get_accounts(), &[], &mut buffer, None).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "client,available,held,total,locked,closed\n1,0.0,0.0,0.0,yes,yes\n"
+        );
+        let columns = [OutputColumn::Locked, OutputColumn::Closed];
+        let rows = yes_no
+            .get_accounts()
+            .into_iter()
+            .map(|account| SelectedColumns {
+                account,
+                columns: &columns,
+            });
+        let mut buffer = Vec::new();
+        output::write_accounts(rows, &[], &mut buffer, None).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "locked,closed\nyes,yes\n"
+        );
+
+        // Kept in the output either way, just without the column
+        let mut plain = PaymentEngine::new();
+        for record in records {
+            plain.process_transaction(record).unwrap();
+        }
+        let accounts = plain.get_accounts();
+        assert_eq!(accounts.len(), 3);
+        assert!(accounts[0].locked);
+        assert_eq!(accounts[0].closed, None);
+    }
+
+    #[test]
+    fn unfunded_accounts_hidden_on_request() {
+        let records = [
+            create_deposit(1, 1, "10.0"),
+            create_withdrawal(2, 2, "5.0"),
+            create_withdrawal(1, 3, "10.0"),
+        ];
+
+        let mut engine = PaymentEngine::new();
+        for record in records.clone() {
+            engine.process_transaction(record).unwrap();
+        }
+        assert_eq!(engine.get_accounts().len(), 2);
+
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            output: OutputConfig {
+                hide_unfunded: true,
+                ..OutputConfig::default()
+            },
+            ..EngineConfig::default()
+        });
+        for record in records {
+            engine.process_transaction(record).unwrap();
+        }
+
+        // Client 1 is empty too, but it was funded at some point
+        let accounts = engine.get_accounts();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].client, ClientId(1));
+        assert_eq!(accounts[0].total, Decimal::ZERO);
+    }
+
+    #[test]
+    fn fee_account_funded_by_fees_alone() {
+        let d = |s: &str| Decimal::from_str(s).unwrap();
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            withdrawal_fee: Some(WithdrawalFee::Flat(d("0.5"))),
+            fee_account: Some(ClientId(99)),
+            output: OutputConfig {
+                hide_unfunded: true,
+                ..OutputConfig::default()
+            },
+            ..EngineConfig::default()
+        });
+        for record in [create_deposit(1, 1, "10.0"), create_withdrawal(1, 2, "5.0")] {
+            engine.process_transaction(record).unwrap();
+        }
+
+        // The fee account never sees a record of its own, only the collected fee
+        let accounts = engine.get_accounts();
+        let fee_account = accounts
+            .iter()
+            .find(|account| account.client == ClientId(99))
+            .unwrap();
+        assert_eq!(fee_account.available, d("0.5"));
+    }
+
+    #[test]
+    fn trace_running_balances() {
+        let mut engine = PaymentEngine::new();
+
+        let records = vec![
+            create_deposit(1, 1, "10.0"),
+            create_deposit(1, 2, "5.0"),
+            create_withdrawal(1, 3, "3.0"),
+            create_withdrawal(1, 4, "50.0"), // insufficient funds, balance unchanged
+            create_dispute(1, 2),
+            create_resolve(1, 2),
+        ];
+
+        let trace: Vec<_> = engine
+            .process_with_trace(records)
+            .map(|(record, account)| (record.tx.0, account.available, account.held, account.total))
+            .collect();
+
+        let d = |s: &str| Decimal::from_str(s).unwrap();
+        assert_eq!(
+            trace,
+            vec![
+                (1, d("10.0"), d("0"), d("10.0")),
+                (2, d("15.0"), d("0"), d("15.0")),
+                (3, d("12.0"), d("0"), d("12.0")),
+                (4, d("12.0"), d("0"), d("12.0")),
+                (2, d("7.0"), d("5.0"), d("12.0")),
+                (2, d("12.0"), d("0"), d("12.0")),
+            ]
+        );
+    }
+
+    #[test]
+    fn dispute_rejected_over_held_cap() {
+        let config = EngineConfig {
+            max_held: Some(Decimal::from_str("5.0").unwrap()),
+            ..EngineConfig::default()
+        };
+        let mut engine = PaymentEngine::with_config(config);
+
+        engine
+            .process_transaction(create_deposit(1, 1, "3.0"))
+            .unwrap();
+        engine
+            .process_transaction(create_deposit(1, 2, "3.0"))
+            .unwrap();
+
+        engine.process_transaction(create_dispute(1, 1)).unwrap();
+        assert!(engine.process_transaction(create_dispute(1, 2)).is_err());
+
+        let accounts = engine.get_accounts();
+        let account = &accounts[0];
+        assert_eq!(account.available, Decimal::from_str("3.0").unwrap());
+        assert_eq!(account.held, Decimal::from_str("3.0").unwrap());
+        assert_eq!(engine.summary().held_cap_rejections, 1);
+
+        // The rejected dispute left tx 2 undisputed, so it can't be charged back
+        engine.process_transaction(create_chargeback(1, 2)).unwrap();
+        assert!(!engine.get_accounts()[0].locked);
+    }
+
+    #[test]
+    fn account_lookup() {
+        let mut engine = PaymentEngine::new();
+        for record in [
+            create_deposit(1, 1, "10.0"),
+            create_deposit(1, 2, "2.12345"),
+            create_dispute(1, 2),
+        ] {
+            engine.process_transaction(record).unwrap();
+        }
+
+        // Exact balances, not rounded like the output
+        assert_eq!(
+            engine.account(ClientId(1)),
+            Some(AccountSnapshot {
+                available: Decimal::from_str("10.0").unwrap(),
+                held: Decimal::from_str("2.12345").unwrap(),
+                total: Decimal::from_str("12.12345").unwrap(),
+                locked: false,
+            })
+        );
+        assert_eq!(engine.account(ClientId(2)), None);
+    }
+
+    #[test]
+    fn cross_check_reports_policy_differences() {
+        let records = [
+            create_deposit(1, 1, "10.0"),
+            create_deposit(2, 2, "5.0"),
+            create_withdrawal(1, 3, "4.0"),
+            create_dispute(2, 2),
+            create_deposit(3, 4, "2.0"),
+        ];
+
+        // Cross-checking dispute amounts only counts mismatches, and these disputes carry none
+        let no_op = EngineConfig {
+            dispute_amounts: DisputeAmountPolicy::CrossCheck,
+            ..EngineConfig::default()
+        };
+        assert!(cross_check(&records, EngineConfig::default(), no_op).is_empty());
+
+        let d = |s: &str| Decimal::from_str(s).unwrap();
+        let fees_and_cap = EngineConfig {
+            withdrawal_fee: Some(WithdrawalFee::Flat(d("0.5"))),
+            max_accounts: Some(2),
+            ..EngineConfig::default()
+        };
+        let diffs = cross_check(&records, EngineConfig::default(), fees_and_cap);
+        assert_eq!(
+            diffs,
+            [
+                AccountDiff {
+                    client: ClientId(1),
+                    available_delta: d("-0.5"),
+                    held_delta: Decimal::ZERO,
+                    locked: false,
+                },
+                // Never opened under the cap
+                AccountDiff {
+                    client: ClientId(3),
+                    available_delta: d("-2.0"),
+                    held_delta: Decimal::ZERO,
+                    locked: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn replay_from_snapshot_and_verify_reports_discrepancies() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+        engine
+            .process_transaction(create_deposit(2, 2, "5.0"))
+            .unwrap();
+
+        let dir = std::env::temp_dir();
+        let id = std::process::id();
+        let snapshot = dir.join(format!("replay-snapshot-{id}.json"));
+        let transactions = dir.join(format!("replay-transactions-{id}.csv"));
+        let expected = dir.join(format!("replay-expected-{id}.csv"));
+        engine.snapshot().save(&snapshot).unwrap();
+        std::fs::write(
+            &transactions,
+            "type,client,tx,amount\n\
+             dispute,1,1,\n\
+             withdrawal,2,3,1.5\n\
+             deposit,3,4,2.0\n",
+        )
+        .unwrap();
+
+        let verify = |expected_output: &str| {
+            std::fs::write(&expected, expected_output).unwrap();
+            replay_from_snapshot_and_verify(
+                &snapshot,
+                &transactions,
+                &expected,
+                EngineConfig::default(),
+            )
+            .unwrap()
+        };
+
+        // Matches regardless of scale and column padding
+        assert_eq!(
+            verify(
+                "client,available,held,total,locked\n\
+                 1, 0, 10.0000, 10, false\n\
+                 2, 3.5, 0, 3.5, false\n\
+                 3, 2, 0, 2, false\n"
+            ),
+            vec![]
+        );
+
+        let discrepancies = verify(
+            "client,available,held,total,locked\n\
+             1,0,10,10,true\n\
+             2,5,0,5,false\n\
+             4,1,0,1,false\n",
+        );
+        for path in [&snapshot, &transactions, &expected] {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(
+            discrepancies,
+            vec![
+                Discrepancy {
+                    client: ClientId(1),
+                    details: vec!["locked: expected true, got false".to_string()],
+                },
+                Discrepancy {
+                    client: ClientId(2),
+                    details: vec![
+                        "available: expected 5, got 3.5".to_string(),
+                        "total: expected 5, got 3.5".to_string(),
+                    ],
+                },
+                Discrepancy {
+                    client: ClientId(3),
+                    details: vec!["not in the expected output".to_string()],
+                },
+                Discrepancy {
+                    client: ClientId(4),
+                    details: vec!["missing from the replayed accounts".to_string()],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_skips_accounts_back_where_they_were() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+        let baseline = engine.snapshot();
+
+        // A settled dispute and a refused withdrawal move counters, not balances
+        engine.process_transaction(create_dispute(1, 1)).unwrap();
+        engine.process_transaction(create_resolve(1, 1)).unwrap();
+        let _ = engine.process_transaction(create_withdrawal(1, 2, "20.0"));
+        assert_eq!(engine.summary().withdrawals_failed_insufficient, 1);
+
+        assert_ne!(
+            engine.accounts[&ClientId(1)],
+            baseline.accounts[&ClientId(1)]
+        );
+        assert!(engine.diff_against(&baseline).is_empty());
+    }
+
+    #[test]
+    fn diff_only_lists_touched_accounts() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+        engine
+            .process_transaction(create_deposit(2, 2, "5.0"))
+            .unwrap();
+        engine
+            .process_transaction(create_deposit(3, 3, "1.0"))
+            .unwrap();
+
+        let baseline = engine.snapshot();
+
+        engine.process_transaction(create_dispute(1, 1)).unwrap();
+        engine
+            .process_transaction(create_withdrawal(3, 4, "0.5"))
+            .unwrap();
+        engine
+            .process_transaction(create_deposit(4, 5, "2.0"))
+            .unwrap();
+
+        let d = |s: &str| Decimal::from_str(s).unwrap();
+        assert_eq!(
+            engine.diff_against(&baseline),
+            vec![
+                AccountDiff {
+                    client: ClientId(1),
+                    available_delta: d("-10.0"),
+                    held_delta: d("10.0"),
+                    locked: false,
+                },
+                AccountDiff {
+                    client: ClientId(3),
+                    available_delta: d("-0.5"),
+                    held_delta: d("0"),
+                    locked: false,
+                },
+                AccountDiff {
+                    client: ClientId(4),
+                    available_delta: d("2.0"),
+                    held_delta: d("0"),
+                    locked: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn summary_describes_end_state() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+        engine
+            .process_transaction(create_deposit(2, 2, "4.0"))
+            .unwrap();
+        engine
+            .process_transaction(create_deposit(2, 3, "1.0"))
+            .unwrap();
+        engine.process_transaction(create_dispute(1, 1)).unwrap();
+        engine.process_transaction(create_chargeback(1, 1)).unwrap();
+        engine.process_transaction(create_dispute(2, 3)).unwrap();
+        assert!(
+            engine
+                .process_transaction(TransactionRecord {
+                    amount: None,
+                    ..create_deposit(3, 4, "1.0")
+                })
+                .is_err()
+        );
+
+        let summary = engine.summary();
+        assert_eq!(summary.records_processed, 6);
+        assert_eq!(summary.records_rejected, 1);
+        assert_eq!(summary.accounts, 2);
+        assert_eq!(summary.locked_accounts, 1);
+        assert_eq!(summary.open_disputes, 1);
+        assert_eq!(summary.total_available, Decimal::from_str("4.0").unwrap());
+        assert_eq!(summary.total_held, Decimal::from_str("1.0").unwrap());
+        assert_eq!(summary.total, Decimal::from_str("5.0").unwrap());
     }
 
     #[test]
-    fn deposit_withdraw_test() {
-        let mut engine = PaymentEngine::new();
+    fn dispute_amount_cross_check() {
+        let config = EngineConfig {
+            dispute_amounts: DisputeAmountPolicy::CrossCheck,
+            ..EngineConfig::default()
+        };
+        let mut engine = PaymentEngine::with_config(config);
 
-        // Deposit 10.0 to client 1
         engine
             .process_transaction(create_deposit(1, 1, "10.0"))
             .unwrap();
-
-        // Withdraw 5.0 from client 1
         engine
-            .process_transaction(create_withdrawal(1, 2, "5.0"))
+            .process_transaction(create_deposit(1, 2, "5.0"))
             .unwrap();
 
-        let accounts = engine.get_accounts();
-        assert_eq!(accounts.len(), 1);
+        let with_amount = |tx: u32, amount: &str| TransactionRecord {
+            amount: Some(Decimal::from_str(amount).unwrap()),
+            ..create_dispute(1, tx)
+        };
 
-        let account = &accounts[0];
-        assert_eq!(account.client, 1);
-        assert_eq!(account.available, Decimal::from_str("5.0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("5.0").unwrap());
-        assert!(!account.locked);
+        engine.process_transaction(with_amount(1, "10.00")).unwrap();
+        assert_eq!(engine.summary().dispute_amount_mismatches, 0);
+
+        // Mismatch is counted, but the stored amount is what gets held
+        engine.process_transaction(with_amount(2, "7.0")).unwrap();
+        assert_eq!(engine.summary().dispute_amount_mismatches, 1);
+
+        let account = &engine.get_accounts()[0];
+        assert_eq!(account.available, Decimal::from_str("0.0").unwrap());
+        assert_eq!(account.held, Decimal::from_str("15.0").unwrap());
     }
 
     #[test]
-    fn fail_withdraw_no_funds() {
+    fn had_dispute_survives_resolve() {
         let mut engine = PaymentEngine::new();
-
-        // Deposit 5.0 to client 1
-        engine
-            .process_transaction(create_deposit(1, 1, "5.0"))
-            .unwrap();
-
-        // Try to withdraw 10.0 from client 1 (should fail)
         engine
-            .process_transaction(create_withdrawal(1, 2, "10.0"))
+            .process_transaction(create_deposit(1, 1, "10.0"))
             .unwrap();
+        assert!(!engine.accounts[&ClientId(1)].had_dispute);
 
-        let accounts = engine.get_accounts();
-        assert_eq!(accounts.len(), 1);
+        engine.process_transaction(create_dispute(1, 1)).unwrap();
+        assert!(engine.accounts[&ClientId(1)].had_dispute);
 
-        let account = &accounts[0];
-        assert_eq!(account.client, 1);
-        assert_eq!(account.available, Decimal::from_str("5.0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("5.0").unwrap());
-        assert!(!account.locked);
+        engine.process_transaction(create_resolve(1, 1)).unwrap();
+        assert!(engine.accounts[&ClientId(1)].had_dispute);
+        assert_eq!(engine.accounts[&ClientId(1)].held, Decimal::ZERO);
     }
 
     #[test]
-    fn success_successive_no_transactions_after_failure() {
+    fn failed_withdrawals_counted_by_reason() {
         let mut engine = PaymentEngine::new();
-
-        // Deposit 5.0 to client 1
         engine
-            .process_transaction(create_deposit(1, 1, "5.0"))
+            .process_transaction(create_deposit(1, 1, "10.0"))
+            .unwrap();
+        engine
+            .process_transaction(create_deposit(1, 2, "1.0"))
             .unwrap();
 
-        // Try to withdraw 10.0 from client 1 (should fail)
         engine
-            .process_transaction(create_withdrawal(1, 2, "10.0"))
+            .process_transaction(create_withdrawal(1, 3, "20.0"))
             .unwrap();
 
-        // Withdraw 3.0 from client 1 (should succeed)
+        engine.process_transaction(create_dispute(1, 2)).unwrap();
+        engine.process_transaction(create_chargeback(1, 2)).unwrap();
         engine
-            .process_transaction(create_withdrawal(1, 3, "3.0"))
+            .process_transaction(create_withdrawal(1, 4, "1.0"))
+            .unwrap();
+        engine
+            .process_transaction(create_withdrawal(1, 5, "2.0"))
             .unwrap();
 
-        let accounts = engine.get_accounts();
-        assert_eq!(accounts.len(), 1);
+        let summary = engine.summary();
+        assert_eq!(summary.withdrawals_failed_insufficient, 1);
+        assert_eq!(summary.withdrawals_failed_locked, 2);
+    }
 
-        let account = &accounts[0];
-        assert_eq!(account.client, 1);
-        assert_eq!(account.available, Decimal::from_str("2.0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("2.0").unwrap());
-        assert!(!account.locked);
+    struct Bonus;
+
+    impl CustomHandler for Bonus {
+        fn type_name(&self) -> &str {
+            "bonus"
+        }
+
+        fn apply(
+            &self,
+            account: &mut Account,
+            record: &TransactionRecord,
+            store: &mut HashMap<TxId, StoredTransaction>,
+        ) -> Result<()> {
+            let amount = record.amount.context("Bonus missing amount")?;
+            account.deposit(amount);
+            store.insert(
+                record.tx,
+                StoredTransaction::new(record.client, amount, record.tx_type.clone()),
+            );
+            Ok(())
+        }
+    }
+
+    /// The client index rebuilt the slow way, by scanning every stored transaction.
+    fn brute_force_index(engine: &PaymentEngine) -> HashMap<ClientId, Vec<TxId>> {
+        let mut index: HashMap<_, Vec<_>> = HashMap::new();
+        for (&tx, stored) in &engine.transactions {
+            index.entry(stored.client).or_default().push(tx);
+        }
+        index.values_mut().for_each(|txs| txs.sort());
+        index
+    }
+
+    fn sorted_index(engine: &PaymentEngine) -> HashMap<ClientId, Vec<TxId>> {
+        let mut index = engine.client_index.clone();
+        index.values_mut().for_each(|txs| txs.sort());
+        index
     }
 
     #[test]
-    fn manage_disputes() {
+    fn client_index_matches_scan() {
         let mut engine = PaymentEngine::new();
+        engine.register_handler(Bonus);
+        let bonus = |client: u16, tx: u32, amount: &str| TransactionRecord {
+            tx_type: TransactionType::Custom("bonus".to_string()),
+            ..create_deposit(client, tx, amount)
+        };
 
-        // Deposit 10.0 to client 1
+        for tx in 1..=30 {
+            let client = (tx % 4) as u16 + 1;
+            let record = match tx % 3 {
+                0 => create_withdrawal(client, tx, "1.0"),
+                1 => create_deposit(client, tx, "5.0"),
+                _ => bonus(client, tx, "0.5"),
+            };
+            let _ = engine.process_transaction(record);
+        }
+        // Client 4's first withdrawal, tx 3, had nothing to draw on and was never stored.
+        // A tx id reused by another client moves to that client
         engine
-            .process_transaction(create_deposit(1, 1, "10.0"))
+            .process_transaction(create_deposit(4, 1, "1.0"))
             .unwrap();
+        // Unapplying forgets the transaction
+        engine.unapply(&create_deposit(3, 10, "5.0")).unwrap();
+        engine.process_transaction(create_dispute(4, 13)).unwrap();
 
-        // Dispute the deposit
-        engine.process_transaction(create_dispute(1, 1)).unwrap();
+        assert_eq!(sorted_index(&engine), brute_force_index(&engine));
+        assert!(!engine.client_index[&ClientId(2)].contains(&TxId(1)));
+        assert!(!engine.client_index[&ClientId(3)].contains(&TxId(10)));
 
-        let accounts = engine.get_accounts();
-        assert_eq!(accounts.len(), 1);
+        let txs: Vec<_> = engine
+            .client_transactions(ClientId(4))
+            .map(|(tx, _)| tx)
+            .collect();
+        assert_eq!(
+            txs,
+            [7, 11, 15, 19, 23, 27, 1].map(TxId),
+            "in the order they were stored"
+        );
+        assert_eq!(engine.client_transactions(ClientId(9)).count(), 0);
 
-        let account = &accounts[0];
-        assert_eq!(account.client, 1);
-        assert_eq!(account.available, Decimal::from_str("0.0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("10.0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("10.0").unwrap());
-        assert!(!account.locked);
+        let restored = PaymentEngine::from_snapshot(engine.snapshot(), EngineConfig::default());
+        assert_eq!(sorted_index(&restored), brute_force_index(&engine));
+    }
 
-        // Resolve the dispute
-        engine.process_transaction(create_resolve(1, 1)).unwrap();
+    #[test]
+    fn held_breakdown_sums_to_held() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            partial_disputes: true,
+            ..EngineConfig::default()
+        });
+        let with_amount = |record: TransactionRecord, amount: &str| TransactionRecord {
+            amount: Some(Decimal::from_str(amount).unwrap()),
+            ..record
+        };
 
-        let accounts = engine.get_accounts();
-        let account = &accounts[0];
-        assert_eq!(account.available, Decimal::from_str("10.0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("10.0").unwrap());
-        assert!(!account.locked);
+        for record in [
+            create_deposit(1, 4, "10.0"),
+            create_deposit(1, 2, "5.0"),
+            create_deposit(1, 3, "2.5"),
+            create_deposit(2, 1, "7.0"),
+            create_dispute(1, 4),
+            with_amount(create_dispute(1, 2), "1.5"),
+            create_dispute(1, 3),
+            create_resolve(1, 3),
+            with_amount(create_chargeback(1, 4), "4.0"),
+            create_dispute(2, 1),
+        ] {
+            engine.process_transaction(record).unwrap();
+        }
+
+        let breakdown = engine.held_breakdown(ClientId(1));
+        assert_eq!(
+            breakdown,
+            [
+                (TxId(2), Decimal::from_str("1.5").unwrap()),
+                (TxId(4), Decimal::from_str("6.0").unwrap()),
+            ]
+        );
+        let held = engine.accounts[&ClientId(1)].held;
+        assert_eq!(
+            breakdown.iter().map(|(_, held)| held).sum::<Decimal>(),
+            held
+        );
+
+        assert!(engine.held_breakdown(ClientId(9)).is_empty());
     }
 
     #[test]
-    fn no_dispute_bad_tx() {
+    fn custom_handler_applies_unknown_type() {
+        let bonus = |client: u16, tx: u32, amount: &str| TransactionRecord {
+            tx_type: TransactionType::Custom("bonus".to_string()),
+            ..create_deposit(client, tx, amount)
+        };
+
         let mut engine = PaymentEngine::new();
+        assert!(engine.process_transaction(bonus(1, 1, "2.0")).is_err());
+        assert!(engine.get_accounts().is_empty());
 
-        // Deposit 10.0 to client 1
+        engine.register_handler(Bonus);
         engine
-            .process_transaction(create_deposit(1, 1, "10.0"))
+            .process_transaction(create_deposit(1, 2, "10.0"))
             .unwrap();
+        engine.process_transaction(bonus(1, 3, "2.5")).unwrap();
 
-        // Try to dispute a non-existent transaction
-        engine.process_transaction(create_dispute(1, 999)).unwrap();
-
-        let accounts = engine.get_accounts();
-        assert_eq!(accounts.len(), 1);
+        // Stored by the handler, so it can be disputed like any other transaction
+        engine.process_transaction(create_dispute(1, 3)).unwrap();
 
-        let account = &accounts[0];
-        assert_eq!(account.client, 1);
+        let account = &engine.get_accounts()[0];
         assert_eq!(account.available, Decimal::from_str("10.0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("10.0").unwrap());
-        assert!(!account.locked);
+        assert_eq!(account.held, Decimal::from_str("2.5").unwrap());
+        assert_eq!(account.total, Decimal::from_str("12.5").unwrap());
     }
 
     #[test]
-    fn manage_multiple_disputes() {
-        let mut engine = PaymentEngine::new();
+    fn partial_chargeback_and_resolve_interleave() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            partial_disputes: true,
+            ..EngineConfig::default()
+        });
+        let with_amount = |record: TransactionRecord, amount: &str| TransactionRecord {
+            amount: Some(Decimal::from_str(amount).unwrap()),
+            ..record
+        };
 
-        // Deposit 10.0 to client 1
         engine
             .process_transaction(create_deposit(1, 1, "10.0"))
             .unwrap();
+        engine.process_transaction(create_dispute(1, 1)).unwrap();
 
-        // Deposit 5.0 to client 1
         engine
-            .process_transaction(create_deposit(1, 2, "5.0"))
+            .process_transaction(with_amount(create_chargeback(1, 1), "3.0"))
+            .unwrap();
+        engine
+            .process_transaction(with_amount(create_resolve(1, 1), "2.0"))
             .unwrap();
+        assert!(engine.transactions[&TxId(1)].disputed);
+        assert_eq!(
+            engine.transactions[&TxId(1)].held,
+            Decimal::from_str("5.0").unwrap()
+        );
 
-        // Dispute both deposits
-        engine.process_transaction(create_dispute(1, 1)).unwrap();
-        engine.process_transaction(create_dispute(1, 2)).unwrap();
+        // More than what's left only releases what's left
+        engine
+            .process_transaction(with_amount(create_resolve(1, 1), "100.0"))
+            .unwrap();
+        assert!(!engine.transactions[&TxId(1)].disputed);
 
-        let accounts = engine.get_accounts();
-        assert_eq!(accounts.len(), 1);
+        // The tx is settled, nothing further moves
+        engine
+            .process_transaction(with_amount(create_chargeback(1, 1), "1.0"))
+            .unwrap();
 
-        let account = &accounts[0];
-        assert_eq!(account.client, 1);
-        assert_eq!(account.available, Decimal::from_str("0.0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("15.0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("15.0").unwrap());
-        assert!(!account.locked);
+        let account = &engine.get_accounts()[0];
+        assert_eq!(account.available, Decimal::from_str("7.0").unwrap());
+        assert_eq!(account.held, Decimal::ZERO);
+        assert_eq!(account.total, Decimal::from_str("7.0").unwrap());
+        assert!(account.locked);
+        assert_eq!(engine.verify_held_consistency(), Ok(()));
+    }
 
-        // Resolve one dispute
-        engine.process_transaction(create_resolve(1, 1)).unwrap();
+    #[test]
+    fn partial_disputes_with_cross_check() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            dispute_amounts: DisputeAmountPolicy::CrossCheck,
+            partial_disputes: true,
+            ..EngineConfig::default()
+        });
+        let dispute = |tx, amount: &str| TransactionRecord {
+            amount: Some(Decimal::from_str(amount).unwrap()),
+            ..create_dispute(1, tx)
+        };
+        for record in [
+            create_deposit(1, 1, "10.0"),
+            create_deposit(1, 2, "5.0"),
+            // A portion of the tx is no mismatch, more than all of it is
+            dispute(1, "4.0"),
+            dispute(2, "6.0"),
+        ] {
+            engine.process_transaction(record).unwrap();
+        }
 
-        let accounts = engine.get_accounts();
-        let account = &accounts[0];
-        assert_eq!(account.available, Decimal::from_str("10.0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("5.0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("15.0").unwrap());
-        assert!(!account.locked);
+        assert_eq!(engine.summary().dispute_amount_mismatches, 1);
+        assert_eq!(
+            engine.get_accounts()[0].held,
+            Decimal::from_str("9.0").unwrap()
+        );
     }
 
     #[test]
-    fn no_dispute_no_chargeback() {
-        let mut engine = PaymentEngine::new();
+    fn partial_dispute_then_chargeback() {
+        let config = EngineConfig {
+            partial_disputes: true,
+            ..EngineConfig::default()
+        };
+        let mut engine = PaymentEngine::with_config(config);
 
-        // Deposit 10.0 to client 1
         engine
             .process_transaction(create_deposit(1, 1, "10.0"))
             .unwrap();
+        engine
+            .process_transaction(create_deposit(1, 2, "5.0"))
+            .unwrap();
 
-        // Try to chargeback without dispute
+        // Only 4.0 of tx 1 is disputed, all of tx 2
+        engine
+            .process_transaction(TransactionRecord {
+                amount: Some(Decimal::from_str("4.0").unwrap()),
+                ..create_dispute(1, 1)
+            })
+            .unwrap();
+        engine.process_transaction(create_dispute(1, 2)).unwrap();
+        assert_eq!(
+            engine.transactions[&TxId(1)].held,
+            Decimal::from_str("4.0").unwrap()
+        );
+
+        let account = &engine.get_accounts()[0];
+        assert_eq!(account.available, Decimal::from_str("6.0").unwrap());
+        assert_eq!(account.held, Decimal::from_str("9.0").unwrap());
+
+        // The chargeback removes the 4.0 tx 1 held, not its original 10.0,
+        // leaving tx 2's hold intact
         engine.process_transaction(create_chargeback(1, 1)).unwrap();
 
-        let accounts = engine.get_accounts();
-        assert_eq!(accounts.len(), 1);
+        let account = &engine.get_accounts()[0];
+        assert_eq!(account.available, Decimal::from_str("6.0").unwrap());
+        assert_eq!(account.held, Decimal::from_str("5.0").unwrap());
+        assert_eq!(account.total, Decimal::from_str("11.0").unwrap());
+        assert!(account.locked);
+        assert_eq!(engine.transactions[&TxId(1)].held, Decimal::ZERO);
 
-        let account = &accounts[0];
-        assert_eq!(account.client, 1);
-        assert_eq!(account.available, Decimal::from_str("10.0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("10.0").unwrap());
-        assert!(!account.locked);
+        // tx 2 can still be charged back in full
+        engine.process_transaction(create_chargeback(1, 2)).unwrap();
+        let account = &engine.get_accounts()[0];
+        assert_eq!(account.held, Decimal::ZERO);
+        assert_eq!(account.total, Decimal::from_str("6.0").unwrap());
     }
 
     #[test]
-    fn chargeback() {
+    fn chargeback_after_refused_hold_removes_nothing() {
         let mut engine = PaymentEngine::new();
 
-        // Deposit 10.0 to client 1
         engine
             .process_transaction(create_deposit(1, 1, "10.0"))
             .unwrap();
+        engine
+            .process_transaction(create_deposit(1, 2, "5.0"))
+            .unwrap();
+        engine.process_transaction(create_dispute(1, 2)).unwrap();
+        engine
+            .process_transaction(create_withdrawal(1, 3, "8.0"))
+            .unwrap();
 
-        // Dispute the deposit
+        // Only 2.0 available, so tx 1's hold is refused
         engine.process_transaction(create_dispute(1, 1)).unwrap();
+        assert_eq!(engine.transactions[&TxId(1)].held, Decimal::ZERO);
 
-        // Chargeback the disputed transaction
+        // Previously this would take tx 1's 10.0 out of held... which belongs to tx 2
         engine.process_transaction(create_chargeback(1, 1)).unwrap();
 
-        let accounts = engine.get_accounts();
-        assert_eq!(accounts.len(), 1);
-
-        let account = &accounts[0];
-        assert_eq!(account.client, 1);
-        assert_eq!(account.available, Decimal::from_str("0.0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("0.0").unwrap());
-        assert!(account.locked);
+        let account = &engine.get_accounts()[0];
+        assert_eq!(account.available, Decimal::from_str("2.0").unwrap());
+        assert_eq!(account.held, Decimal::from_str("5.0").unwrap());
     }
 
     #[test]
-    fn no_further_withdraw_after_chargeback() {
+    fn finalizer_runs_once_before_output() {
         let mut engine = PaymentEngine::new();
-
-        // Deposit 15.0 to client 1
         engine
-            .process_transaction(create_deposit(1, 1, "15.0"))
+            .process_transaction(create_deposit(1, 1, "10.0"))
             .unwrap();
-
-        // Deposit 5.0 to client 1
         engine
-            .process_transaction(create_deposit(1, 2, "5.0"))
+            .process_transaction(create_deposit(2, 2, "5.0"))
             .unwrap();
 
-        // Dispute the first deposit
-        engine.process_transaction(create_dispute(1, 1)).unwrap();
-
-        // Chargeback the disputed transaction (locks account)
-        engine.process_transaction(create_chargeback(1, 1)).unwrap();
+        engine.set_finalizer(|engine| {
+            if let Some(account) = engine.account_mut(ClientId(2)) {
+                account.available = Decimal::ZERO;
+            }
+        });
+        engine.finalize();
 
-        // Try to withdraw from the locked account (should fail)
+        // Re-funding after the finalizer ran proves it doesn't run again
         engine
-            .process_transaction(create_withdrawal(1, 3, "2.0"))
+            .process_transaction(create_deposit(2, 3, "1.0"))
             .unwrap();
+        engine.finalize();
 
-        let accounts = engine.get_accounts();
-        assert_eq!(accounts.len(), 1);
-
-        let account = &accounts[0];
-        assert_eq!(account.client, 1);
-        assert_eq!(account.available, Decimal::from_str("5.0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("5.0").unwrap());
-        assert!(account.locked);
+        let mut accounts = engine.get_accounts();
+        accounts.sort_by_key(|a| a.client);
+        assert_eq!(accounts[0].total, Decimal::from_str("10.0").unwrap());
+        assert_eq!(accounts[1].total, Decimal::from_str("1.0").unwrap());
     }
 
     #[test]
-    fn disputes_possible_after_chargeback() {
+    fn disputes_only_against_snapshot() {
         let mut engine = PaymentEngine::new();
-
-        // Deposit 10.0 to client 1
         engine
             .process_transaction(create_deposit(1, 1, "10.0"))
             .unwrap();
-
-        // Deposit 5.0 to client 1
         engine
             .process_transaction(create_deposit(1, 2, "5.0"))
             .unwrap();
 
-        // Dispute the first deposit
-        engine.process_transaction(create_dispute(1, 1)).unwrap();
-
-        // Dispute the second deposit
-        engine.process_transaction(create_dispute(1, 2)).unwrap();
-
-        // Chargeback the first disputed transaction (locks account)
-        engine.process_transaction(create_chargeback(1, 1)).unwrap();
+        let config = EngineConfig {
+            ingestion: IngestionMode::DisputesOnly,
+            ..EngineConfig::default()
+        };
+        let mut replay = PaymentEngine::from_snapshot(engine.snapshot(), config);
 
-        // Chargeback the second disputed transaction (should still work)
-        engine.process_transaction(create_chargeback(1, 2)).unwrap();
+        // The deposit is filtered out, the dispute acts on the snapshotted tx 1
+        replay
+            .process_transaction(create_deposit(1, 3, "100.0"))
+            .unwrap();
+        replay.process_transaction(create_dispute(1, 1)).unwrap();
 
-        let accounts = engine.get_accounts();
+        let accounts = replay.get_accounts();
         assert_eq!(accounts.len(), 1);
 
         let account = &accounts[0];
-        assert_eq!(account.client, 1);
-        assert_eq!(account.available, Decimal::from_str("0.0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("0.0").unwrap());
-        assert!(account.locked);
+        assert_eq!(account.available, Decimal::from_str("5.0").unwrap());
+        assert_eq!(account.held, Decimal::from_str("10.0").unwrap());
+        assert_eq!(account.total, Decimal::from_str("15.0").unwrap());
+        assert_eq!(replay.summary().filtered, 1);
+    }
+
+    #[test]
+    fn event_log_records_balance_changes() {
+        let records = [
+            create_deposit(1, 1, "10.0"),
+            create_dispute(1, 1),
+            // Already disputed, so nothing moves and nothing is logged
+            create_dispute(1, 1),
+            create_chargeback(1, 1),
+        ];
+        let event = |kind, delta_available: &str, delta_held: &str| LedgerEvent {
+            tx: Some(TxId(1)),
+            client: ClientId(1),
+            kind: LedgerEventKind::Record(kind),
+            delta_available: Decimal::from_str(delta_available).unwrap(),
+            delta_held: Decimal::from_str(delta_held).unwrap(),
+        };
+
+        let mut engine = PaymentEngine::new().with_event_log();
+        for record in records.clone() {
+            let _ = engine.process_transaction(record);
+        }
+        assert_eq!(
+            engine.events(),
+            [
+                event(TransactionType::Deposit, "10.0", "0"),
+                event(TransactionType::Dispute, "-10.0", "10.0"),
+                event(TransactionType::Chargeback, "0", "-10.0"),
+            ]
+        );
+
+        let mut engine = PaymentEngine::new();
+        for record in records {
+            let _ = engine.process_transaction(record);
+        }
+        assert!(engine.events().is_empty());
+    }
+
+    #[test]
+    fn event_log_records_engine_operations() {
+        let event = |tx, client, kind, delta_available: &str, delta_held: &str| LedgerEvent {
+            tx,
+            client: ClientId(client),
+            kind,
+            delta_available: Decimal::from_str(delta_available).unwrap(),
+            delta_held: Decimal::from_str(delta_held).unwrap(),
+        };
+
+        let mut engine = PaymentEngine::new().with_event_log();
+        for record in [
+            create_deposit(1, 1, "10.0"),
+            create_deposit(1, 2, "4.0"),
+            create_dispute(1, 1),
+            create_dispute(1, 2),
+            create_deposit(2, 3, "3.0"),
+            create_deposit(3, 4, "1.0"),
+        ] {
+            engine.process_transaction(record).unwrap();
+        }
+        engine.unapply(&create_deposit(3, 4, "1.0")).unwrap();
+        engine.merge_clients(ClientId(2), ClientId(3)).unwrap();
+        let logged = engine.events().len();
+        engine.auto_chargeback_disputes(0);
+
+        assert_eq!(
+            engine.events()[logged - 3..logged],
+            [
+                event(
+                    Some(TxId(4)),
+                    3,
+                    LedgerEventKind::Unapply(TransactionType::Deposit),
+                    "-1.0",
+                    "0"
+                ),
+                event(None, 2, LedgerEventKind::Merge, "-3.0", "0"),
+                event(None, 3, LedgerEventKind::Merge, "3.0", "0"),
+            ]
+        );
+        // Both disputes are charged back, logged in tx id order
+        assert_eq!(
+            engine.events()[logged..],
+            [
+                event(
+                    Some(TxId(1)),
+                    1,
+                    LedgerEventKind::AutoChargeback,
+                    "0",
+                    "-10.0"
+                ),
+                event(
+                    Some(TxId(2)),
+                    1,
+                    LedgerEventKind::AutoChargeback,
+                    "0",
+                    "-4.0"
+                ),
+            ]
+        );
+        assert!(engine.accounts[&ClientId(1)].locked);
     }
 }
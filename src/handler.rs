@@ -0,0 +1,20 @@
+use crate::account::Account;
+use crate::transaction::{StoredTransaction, TransactionRecord, TxId};
+use anyhow::Result;
+use std::collections::HashMap;
+
+/// Extension point for domain-specific transaction types. Records whose type isn't one of the
+/// built-ins are dispatched to the handler registered under that type name.
+pub trait CustomHandler {
+    /// The transaction type this handler applies, as it appears in the input.
+    fn type_name(&self) -> &str;
+
+    /// Applies `record` to the client's account. `store` is the engine's transaction history,
+    /// so handlers can record transactions for later disputes or look existing ones up.
+    fn apply(
+        &self,
+        account: &mut Account,
+        record: &TransactionRecord,
+        store: &mut HashMap<TxId, StoredTransaction>,
+    ) -> Result<()>;
+}
@@ -1,57 +1,665 @@
-use anyhow::{Context, Result};
-use csv::Writer;
-use engine::PaymentEngine;
-use std::{env, fs::File, io::stdout};
+use account::AccountDiff;
+use anyhow::Result;
+use cli::Options;
+use engine::{PaymentEngine, SequenceError};
+use metadata::Metadata;
+use output::{CsvOutput, JsonOutput, OutputColumn, OutputFormat, OutputWriter, column_names};
+use parallel::ParallelReader;
+use progress::Progress;
+use reader::{InputFormat, RecordError, RecordSource, TransactionReader};
+use snapshot::Snapshot;
+use std::io::Write;
+use std::time::{Instant, SystemTime};
+use std::{env, io::stdout, process::ExitCode};
+use summary::ProcessSummary;
+use transaction::{AmountError, MAX_AMOUNT, MAX_SCALE, PrecisionPolicy, TransactionRecord};
 
 pub mod account;
+pub mod audit;
+#[cfg(feature = "binary")]
+pub mod binary;
+pub mod cli;
+pub mod config;
+pub mod dormancy;
 pub mod engine;
+pub mod handler;
+pub mod metadata;
+pub mod output;
+pub mod parallel;
+#[cfg(feature = "parquet")]
+pub mod parquet_output;
+pub mod progress;
+pub mod reader;
+#[cfg(feature = "report")]
+pub mod report;
+pub mod snapshot;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(feature = "async")]
+pub mod stream;
+pub mod summary;
 pub mod transaction;
 
-fn main() -> Result<()> {
+/// Exit code for `--fail-on-empty` when the input held no transactions.
+const NO_TRANSACTIONS: u8 = 2;
+
+/// Exit code for `audit` when a check failed, apart from 1 for a snapshot that can't be read.
+const AUDIT_FAILED: u8 = 3;
+
+fn main() -> Result<ExitCode> {
     let args: Vec<String> = env::args().collect();
+    if args.get(1).is_some_and(|arg| arg == "audit") {
+        let [_, _, snapshot] = args.as_slice() else {
+            anyhow::bail!(Options::usage(&args[0]));
+        };
+        return run_audit(snapshot);
+    }
 
-    if args.len() != 2 {
-        anyhow::bail!("Usage: {} transactions.csv", args[0]);
+    let options = Options::parse(args)?;
+    if options.validate_only {
+        return validate_input(&options);
     }
 
-    process_transactions(&args[1])?;
+    let summary = process_transactions(&options)?;
 
-    Ok(())
+    if options.fail_on_empty && summary.records_read == 0 {
+        eprintln!("No transactions in the input");
+        return Ok(ExitCode::from(NO_TRANSACTIONS));
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Runs every audit check over the snapshot and prints a pass/fail line per check.
+fn run_audit(snapshot: &str) -> Result<ExitCode> {
+    let checks = audit::audit(&Snapshot::load(snapshot)?);
+    audit::write_report(&checks, stdout().lock())?;
+
+    if checks.iter().all(audit::AuditCheck::passed) {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::from(AUDIT_FAILED))
+    }
+}
+
+/// Reads the whole input like a normal run, but only checks each record, printing a line per
+/// invalid one and a count at the end. Exits with a failure if any record was invalid.
+fn validate_input(options: &Options) -> Result<ExitCode> {
+    let mut engine = PaymentEngine::with_config(options.engine.clone());
+    ingest_input(&mut engine, options)?;
+
+    let summary = engine.summary();
+    println!(
+        "{} of {} record(s) invalid",
+        summary.records_rejected, summary.records_read
+    );
+    if summary.records_rejected == 0 {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+/// Surfaces counted problems that don't warrant a per-record warning.
+fn report_warnings(summary: &ProcessSummary, options: &Options) {
+    if summary.precision_overflows > 0 {
+        let action = match options.input_config.precision {
+            PrecisionPolicy::Reject => "dropped",
+            PrecisionPolicy::Round => "rounded",
+        };
+        eprintln!(
+            "Warning: {} amount(s) exceeded {MAX_SCALE} decimal places and were {action}",
+            summary.precision_overflows
+        );
+    }
+
+    if summary.out_of_range_amounts > 0 {
+        eprintln!(
+            "Warning: {} amount(s) larger than {MAX_AMOUNT} were skipped",
+            summary.out_of_range_amounts
+        );
+    }
+
+    if summary.oversized > 0 {
+        eprintln!(
+            "Warning: {} line(s) longer than --max-record-size were skipped",
+            summary.oversized
+        );
+    }
+
+    if summary.duplicate_disputes > 0 || summary.duplicate_resolves > 0 {
+        eprintln!(
+            "Warning: {} duplicate dispute(s) and {} duplicate resolve(s) were ignored",
+            summary.duplicate_disputes, summary.duplicate_resolves
+        );
+    }
+
+    if summary.total_overflows > 0 {
+        eprintln!(
+            "Warning: {} account total(s) overflowed and were capped",
+            summary.total_overflows
+        );
+    }
+
+    if summary.dispute_ratio_breaches > 0 {
+        eprintln!(
+            "Warning: {} account(s) disputed more than the allowed share of their deposits",
+            summary.dispute_ratio_breaches
+        );
+    }
+
+    if summary.out_of_window_disputes > 0 {
+        eprintln!(
+            "Warning: {} dispute(s) came after the dispute window and were rejected",
+            summary.out_of_window_disputes
+        );
+    }
+
+    if summary.cross_client_disputes > 0 {
+        eprintln!(
+            "Warning: {} dispute(s) named another client's transaction and were rejected",
+            summary.cross_client_disputes
+        );
+    }
+
+    if summary.dispute_amount_mismatches > 0 {
+        eprintln!(
+            "Warning: {} dispute(s) carried an amount different from the disputed transaction",
+            summary.dispute_amount_mismatches
+        );
+    }
 }
 
-fn process_transactions(input_path: &str) -> Result<()> {
-    let file =
-        File::open(input_path).with_context(|| format!("Failed to open file: {input_path}"))?;
+/// Feeds every readable record to the engine. Bad rows are counted and skipped, unless
+/// there are more of them than `--max-rejects` allows, in which case the file is assumed
+/// to be fundamentally broken and processing stops.
+///
+/// With `--time-budget`, reading stops once the budget is spent and the accounts are output as
+/// they stand, for batch jobs where a late result is worth less than a partial one.
+///
+/// With `--dedup`, a record equal to the one before it in every field is taken for a feed
+/// retry and skipped. This is about repeated rows, not repeated tx ids.
+///
+/// With `--validate-only`, records are checked with `PaymentEngine::check_record` instead of
+/// being applied, and every invalid one is printed with where it is in the input. Nothing
+/// touches the accounts, only the counters move.
+///
+/// With `--strict`, an amount that doesn't parse, or a resolve or chargeback without an open
+/// dispute, aborts the run.
+fn ingest(
+    records: &mut impl RecordSource,
+    engine: &mut PaymentEngine,
+    options: &Options,
+) -> Result<()> {
+    let mut rejects = 0;
+    let mut progress = options.progress.then(Progress::default);
+    let mut previous: Option<TransactionRecord> = None;
+    let deadline = options.time_budget.map(|budget| Instant::now() + budget);
+
+    while let Some(result) = records.next() {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            let summary = engine.summary_mut();
+            eprintln!(
+                "Warning: Time budget spent after {} records ({} processed), the output is partial",
+                summary.records_read, summary.records_processed
+            );
+            break;
+        }
 
-    let mut reader = csv::ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .from_reader(file);
+        engine.summary_mut().records_read += 1;
+        if let Some(progress) = &mut progress {
+            progress.tick(engine.summary_mut().records_read);
+        }
 
-    let mut engine = PaymentEngine::new();
+        if options.validate_only {
+            let error = match result {
+                Ok(record) => engine.check_record(&record).err(),
+                Err(e) => Some(e.into()),
+            };
+            if let Some(e) = error {
+                let summary = engine.summary_mut();
+                summary.records_rejected += 1;
+                println!("{}: {e:#}", location(records, summary.records_read));
+            }
+            continue;
+        }
 
-    for result in reader.deserialize() {
-        match result {
-            Ok(record) => {
-                if let Err(e) = engine.process_transaction(record) {
+        if options.dedup {
+            let record = result.as_ref().ok();
+            if record.is_some() && record == previous.as_ref() {
+                engine.summary_mut().deduped += 1;
+                continue;
+            }
+            previous = record.cloned();
+        }
+
+        let rejected = match result {
+            Ok(record) => match engine.process_transaction(record) {
+                Ok(()) => false,
+                Err(e) if options.strict && e.is::<SequenceError>() => {
+                    let location = location(records, engine.summary_mut().records_read);
+                    return Err(e.context(format!("Aborting at {location}")));
+                }
+                Err(e) => {
                     eprintln!("Warning: Failed to process transaction: {e}");
+                    true
                 }
+            },
+            // Already counted by the reader
+            Err(RecordError::Amount(AmountError::PrecisionOverflow(_))) => true,
+            Err(RecordError::Amount(e)) if options.strict => {
+                let location = location(records, engine.summary_mut().records_read);
+                return Err(anyhow::Error::new(e).context(format!("Aborting at {location}")));
+            }
+            Err(RecordError::Amount(AmountError::OutOfRange(_))) => {
+                engine.summary_mut().out_of_range_amounts += 1;
+                true
             }
             Err(_) => {
                 // Silently ignore invalid CSV records as per requirements
-                continue;
+                engine.summary_mut().malformed += 1;
+                true
             }
+        };
+
+        if rejected {
+            rejects += 1;
+            check_rejects(rejects, options)?;
         }
     }
 
-    let mut writer = Writer::from_writer(stdout());
+    Ok(())
+}
 
-    for account in engine.get_accounts() {
-        writer
-            .serialize(account)
-            .context("Failed to write output")?;
+/// Where the record just read sits in the input: its line where the input has lines, its
+/// index among the records otherwise.
+fn location(records: &impl RecordSource, records_read: u64) -> String {
+    match records.line() {
+        Some(line) => format!("line {line}"),
+        None => format!("record {records_read}"),
     }
+}
 
-    writer.flush().context("Failed to flush output")?;
+/// Runs `ingest` over the configured input source.
+fn ingest_input(engine: &mut PaymentEngine, options: &Options) -> Result<()> {
+    let precision_overflows = match &options.sqlite_table {
+        #[cfg(feature = "sqlite")]
+        Some(table) => {
+            let mut reader =
+                sqlite::SqliteReader::open(&options.input, table, options.input_config.precision)?;
+            ingest(&mut reader, engine, options)?;
+            reader.precision_overflows()
+        }
+        #[cfg(not(feature = "sqlite"))]
+        Some(_) => anyhow::bail!("--sqlite-table requires building with the sqlite feature"),
+        #[cfg(feature = "binary")]
+        None if options.input_format == InputFormat::Binary => {
+            let mut reader = binary::BinaryReader::new(reader::open(&options.input)?)?;
+            ingest(&mut reader, engine, options)?;
+            0
+        }
+        #[cfg(not(feature = "binary"))]
+        None if options.input_format == InputFormat::Binary => {
+            anyhow::bail!("--input-format binary requires building with the binary feature")
+        }
+        None => {
+            let input = reader::open(&options.input)?;
+            let reader = TransactionReader::new(input, &options.input_config)?;
+            match options.parse_threads {
+                Some(threads) => {
+                    let mut reader = ParallelReader::new(reader, threads);
+                    ingest(&mut reader, engine, options)?;
+                    engine.summary_mut().oversized += reader.oversized();
+                    reader.precision_overflows()
+                }
+                None => {
+                    let mut reader = reader;
+                    ingest(&mut reader, engine, options)?;
+                    engine.summary_mut().oversized += reader.oversized();
+                    reader.precision_overflows()
+                }
+            }
+        }
+    };
 
+    engine.summary_mut().precision_overflows += precision_overflows;
     Ok(())
 }
+
+fn check_rejects(rejects: u64, options: &Options) -> Result<()> {
+    match options.max_rejects {
+        Some(max) if rejects > max => {
+            anyhow::bail!("Aborting: {rejects} records rejected, more than --max-rejects {max}")
+        }
+        _ => Ok(()),
+    }
+}
+
+fn process_transactions(options: &Options) -> Result<ProcessSummary> {
+    // Checked up front rather than failing after all the work is done
+    #[cfg(not(feature = "report"))]
+    if options.report_to.is_some() {
+        anyhow::bail!("--report-to requires building with the report feature");
+    }
+
+    let baseline = options.snapshot.as_ref().map(Snapshot::load).transpose()?;
+    let mut engine = match &baseline {
+        Some(snapshot) => PaymentEngine::from_snapshot(snapshot.clone(), options.engine.clone()),
+        None => PaymentEngine::with_config(options.engine.clone()),
+    };
+
+    match (options.dispute_timeout, options.chargeback_timeout) {
+        (Some(max_age), _) => engine.set_finalizer(move |e| e.auto_resolve_disputes(max_age)),
+        (None, Some(max_age)) => engine.set_finalizer(move |e| e.auto_chargeback_disputes(max_age)),
+        (None, None) => {}
+    }
+
+    ingest_input(&mut engine, options)?;
+    engine.finalize();
+
+    if options.verify
+        && let Err(clients) = engine.verify_held_consistency()
+    {
+        let clients: Vec<_> = clients.iter().map(ToString::to_string).collect();
+        anyhow::bail!(
+            "Held funds don't match the open disputes for client(s) {}",
+            clients.join(", ")
+        );
+    }
+
+    report_warnings(&engine.summary(), options);
+
+    if let Some(path) = &options.save_snapshot {
+        engine.snapshot().save(path)?;
+    }
+
+    if let (Some(path), Some(dormant_after)) = (&options.dormancy_report, options.dormant_after) {
+        dormancy::save_report(path, &engine.dormant_accounts(dormant_after))?;
+    }
+
+    let unwritable = write_output(&engine, baseline.as_ref(), options, &mut stdout())?;
+    if unwritable > 0 {
+        eprintln!("Warning: {unwritable} account row(s) failed to serialize and were left out");
+    }
+
+    let summary = engine.summary();
+    if let Some(path) = &options.metadata {
+        Metadata::new(&options.input, &summary, SystemTime::now()).save(path)?;
+    }
+    if let Some(path) = &options.summary_json {
+        summary.save(path)?;
+    }
+    #[cfg(feature = "report")]
+    if let Some(target) = &options.report_to {
+        report::send_summary(&summary, target)?;
+    }
+
+    Ok(summary)
+}
+
+fn write_output(
+    engine: &PaymentEngine,
+    baseline: Option<&Snapshot>,
+    options: &Options,
+    out: &mut (dyn Write + Send),
+) -> Result<u64> {
+    if options.stats_only {
+        return output::write_summary(&engine.summary(), out).map(|()| 0);
+    }
+
+    // Both are CSV only, which `Options::parse` makes sure of
+    if let Some(baseline) = baseline.filter(|_| options.diff) {
+        let rows = engine.diff_against(baseline);
+        return csv_output(options).write_rows(rows, &AccountDiff::COLUMNS, out);
+    }
+    if let Some(path) = &options.resume {
+        let header = column_names(&OutputColumn::standard(&options.engine.output));
+        return output::resume_accounts(engine.accounts(), &header, path, options.flush_every)
+            .map(|()| 0);
+    }
+
+    output_writer(options)?.write_accounts(&mut engine.accounts(), out)
+}
+
+fn csv_output(options: &Options) -> CsvOutput {
+    CsvOutput {
+        flush_every: options.flush_every,
+        output: options.engine.output.clone(),
+        columns: options.output_columns.clone(),
+        skip_unwritable: options.skip_unwritable,
+    }
+}
+
+fn output_writer(options: &Options) -> Result<Box<dyn OutputWriter>> {
+    Ok(match options.output_format {
+        OutputFormat::Csv => Box::new(csv_output(options)),
+        OutputFormat::Json => Box::new(JsonOutput),
+        #[cfg(feature = "parquet")]
+        OutputFormat::Parquet => Box::new(parquet_output::ParquetOutput {
+            format: options.decimal_format,
+        }),
+        #[cfg(not(feature = "parquet"))]
+        OutputFormat::Parquet => {
+            anyhow::bail!("--format parquet requires building with the parquet feature")
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reader::ParseMode;
+    use rust_decimal::Decimal;
+    use std::num::NonZeroUsize;
+
+    const THREE_BAD_ROWS: &str = "type,client,tx,amount\n\
+                                  deposit,1,1,10.0\n\
+                                  deposit,1,2,abc\n\
+                                  withdrawal,1,3,\n\
+                                  bogus,1,4,1.0\n\
+                                  deposit,1,5,1.0\n";
+
+    fn run(input: &str, options: &Options) -> (Result<()>, PaymentEngine) {
+        let mut reader = TransactionReader::new(input.as_bytes(), &options.input_config).unwrap();
+        let mut engine = PaymentEngine::with_config(options.engine.clone());
+        let result = ingest(&mut reader, &mut engine, options);
+        (result, engine)
+    }
+
+    #[test]
+    fn test_rejects_tolerated_by_default() {
+        let (result, engine) = run(THREE_BAD_ROWS, &Options::default());
+        assert!(result.is_ok());
+        assert_eq!(engine.summary().records_processed, 2);
+    }
+
+    #[test]
+    fn test_max_rejects_aborts_early() {
+        let options = Options {
+            max_rejects: Some(2),
+            ..Options::default()
+        };
+        let (result, engine) = run(THREE_BAD_ROWS, &options);
+
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("3 records rejected"), "{error}");
+
+        // The deposit after the third bad row was never applied
+        assert_eq!(engine.summary().records_read, 4);
+        assert_eq!(engine.summary().records_processed, 1);
+    }
+
+    #[test]
+    fn test_strict_aborts_on_resolve_before_dispute() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     resolve,1,1,\n\
+                     dispute,1,1,\n";
+
+        let mut options =
+            Options::parse(["tx-processor", "--strict", "-"].map(String::from)).unwrap();
+        let (result, engine) = run(input, &options);
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(error.contains("Aborting at line 3"), "{error}");
+        assert!(
+            error.contains("resolve of tx 1, which is not under dispute"),
+            "{error}"
+        );
+        // Nothing after the resolve was applied
+        assert_eq!(engine.summary().records_read, 2);
+
+        options.strict = false;
+        options.engine = Default::default();
+        let (result, engine) = run(input, &options);
+        assert!(result.is_ok());
+        assert_eq!(engine.summary().duplicate_resolves, 0);
+        assert_eq!(engine.summary().open_disputes, 1);
+    }
+
+    #[test]
+    fn test_strict_aborts_on_invalid_amount() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     deposit,1,2,12.x\n\
+                     deposit,1,3,1.0\n";
+
+        let mut options =
+            Options::parse(["tx-processor", "--strict", "-"].map(String::from)).unwrap();
+        let (result, engine) = run(input, &options);
+        let error = format!("{:#}", result.unwrap_err());
+        assert_eq!(error, "Aborting at line 3: invalid amount \"12.x\"");
+        assert_eq!(engine.summary().records_processed, 1);
+
+        // Otherwise the row is skipped like any other malformed one
+        options.strict = false;
+        let (result, engine) = run(input, &options);
+        assert!(result.is_ok());
+        assert_eq!(engine.summary().malformed, 1);
+        assert_eq!(engine.summary().records_processed, 2);
+    }
+
+    #[test]
+    fn test_strict_reports_the_input_line() {
+        // The memo spans two lines and the long line is dropped, neither is one record per line
+        let input = format!(
+            "type,client,tx,amount,memo\n\
+             deposit,1,1,10.0,\"two\nlines\"\n\
+             deposit,1,2,1.0,{}\n\
+             deposit,1,3,12.x,\n",
+            "x".repeat(64)
+        );
+
+        let mut options = Options::parse(
+            ["tx-processor", "--strict", "--max-record-size", "40", "-"].map(String::from),
+        )
+        .unwrap();
+        for parse_mode in [ParseMode::Serde, ParseMode::ByteRecord] {
+            options.input_config.parse_mode = parse_mode;
+            let (result, _) = run(&input, &options);
+            let error = format!("{:#}", result.unwrap_err());
+            assert_eq!(error, "Aborting at line 5: invalid amount \"12.x\"");
+        }
+
+        let reader = TransactionReader::new(input.as_bytes(), &options.input_config).unwrap();
+        let mut reader = ParallelReader::new(reader, NonZeroUsize::new(2).unwrap());
+        let mut engine = PaymentEngine::with_config(options.engine.clone());
+        let error = format!(
+            "{:#}",
+            ingest(&mut reader, &mut engine, &options).unwrap_err()
+        );
+        assert_eq!(error, "Aborting at line 5: invalid amount \"12.x\"");
+    }
+
+    #[cfg(feature = "binary")]
+    #[test]
+    fn test_strict_reports_the_record_index_without_lines() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     resolve,1,1,\n";
+        let options = Options::parse(["tx-processor", "--strict", "-"].map(String::from)).unwrap();
+        let mut writer = binary::BinaryWriter::new(Vec::new()).unwrap();
+        for record in TransactionReader::new(input.as_bytes(), &options.input_config).unwrap() {
+            writer.write(&record.unwrap()).unwrap();
+        }
+        let bytes = writer.finish().unwrap();
+
+        let mut reader = binary::BinaryReader::new(bytes.as_slice()).unwrap();
+        let mut engine = PaymentEngine::with_config(options.engine.clone());
+        let error = format!(
+            "{:#}",
+            ingest(&mut reader, &mut engine, &options).unwrap_err()
+        );
+        assert!(error.starts_with("Aborting at record 2: "), "{error}");
+    }
+
+    #[test]
+    fn test_out_of_range_amounts_counted_apart() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     deposit,1,2,2000000000000000000\n\
+                     deposit,1,3,12.x\n";
+
+        let (result, engine) = run(input, &Options::default());
+        assert!(result.is_ok());
+        let summary = engine.summary();
+        assert_eq!(summary.out_of_range_amounts, 1);
+        assert_eq!(summary.malformed, 1);
+        assert_eq!(summary.records_processed, 1);
+    }
+
+    #[test]
+    fn test_non_ascii_types_rejected_and_counted() {
+        let input = "type,client,tx,amount\n\
+                     deposit\u{a0},1,1,10.0\n\
+                     with\u{a0}drawal,1,2,1.0\n\
+                     dépôt,1,3,1.0\n";
+
+        let (result, engine) = run(input, &Options::default());
+        assert!(result.is_ok());
+        let summary = engine.summary();
+        assert_eq!(summary.records_processed, 1);
+        assert_eq!(summary.records_rejected, 2);
+        assert_eq!(summary.malformed, 0);
+    }
+
+    #[test]
+    fn test_validate_only_applies_nothing() {
+        let options = Options {
+            validate_only: true,
+            ..Options::default()
+        };
+        let (result, engine) = run(THREE_BAD_ROWS, &options);
+        assert!(result.is_ok());
+
+        let summary = engine.summary();
+        assert_eq!(summary.records_read, 5);
+        assert_eq!(summary.records_rejected, 3);
+        assert_eq!(summary.records_processed, 0);
+        assert_eq!(summary.accounts, 0);
+    }
+
+    #[test]
+    fn test_dedup_skips_repeated_rows() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     deposit,1,1,10.0\n\
+                     withdrawal,1,2,1.0\n\
+                     deposit,1,1,10.0\n";
+
+        let options = Options {
+            dedup: true,
+            ..Options::default()
+        };
+        let (result, engine) = run(input, &options);
+        assert!(result.is_ok());
+
+        // Only the immediate repeat is skipped, the later one isn't consecutive
+        let summary = engine.summary();
+        assert_eq!(summary.deduped, 1);
+        assert_eq!(summary.records_processed, 3);
+        assert_eq!(summary.total_available, Decimal::from(19));
+
+        let (_, engine) = run(input, &Options::default());
+        assert_eq!(engine.summary().total_available, Decimal::from(29));
+    }
+}
@@ -0,0 +1,115 @@
+use crate::summary::ProcessSummary;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Provenance of a run's output: what was processed and when. Written as a JSON sidecar rather
+/// than a comment line in the CSV, which strict parsers would choke on.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Metadata {
+    /// The input path as given, `-` for stdin.
+    pub input: String,
+    /// UTC, RFC 3339 with second precision.
+    pub generated_at: String,
+    pub records_read: u64,
+    pub records_processed: u64,
+    pub records_rejected: u64,
+    pub malformed: u64,
+    pub accounts: u64,
+}
+
+impl Metadata {
+    pub fn new(input: &str, summary: &ProcessSummary, generated_at: SystemTime) -> Self {
+        Self {
+            input: input.to_string(),
+            generated_at: rfc3339(generated_at),
+            records_read: summary.records_read,
+            records_processed: summary.records_processed,
+            records_rejected: summary.records_rejected,
+            malformed: summary.malformed,
+            accounts: summary.accounts,
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create metadata file: {}", path.display()))?;
+
+        serde_json::to_writer_pretty(BufWriter::new(file), self).context("Failed to write metadata")
+    }
+}
+
+/// Formats a time as `YYYY-MM-DDTHH:MM:SSZ`, clamping anything before 1970 to the epoch.
+fn rfc3339(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // Civil date from days since the epoch, after Howard Hinnant's days_from_civil inverse
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_rfc3339() {
+        let at = |secs| rfc3339(UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(at(0), "1970-01-01T00:00:00Z");
+        assert_eq!(at(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(at(1_709_251_199), "2024-02-29T23:59:59Z");
+    }
+
+    #[test]
+    fn test_metadata_sidecar_contents() {
+        let summary = ProcessSummary {
+            records_read: 10,
+            records_processed: 7,
+            records_rejected: 2,
+            malformed: 1,
+            accounts: 3,
+            ..ProcessSummary::default()
+        };
+        let generated_at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let metadata = Metadata::new("transactions.csv", &summary, generated_at);
+
+        let path = std::env::temp_dir().join(format!("metadata-{}.json", std::process::id()));
+        metadata.save(&path).unwrap();
+        let saved: serde_json::Value = serde_json::from_reader(File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            saved,
+            serde_json::json!({
+                "input": "transactions.csv",
+                "generated_at": "2023-11-14T22:13:20Z",
+                "records_read": 10,
+                "records_processed": 7,
+                "records_rejected": 2,
+                "malformed": 1,
+                "accounts": 3,
+            })
+        );
+    }
+}
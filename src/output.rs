@@ -0,0 +1,786 @@
+use crate::account::{AccountOutput, OutputConfig};
+use crate::summary::ProcessSummary;
+use crate::transaction::ClientId;
+use anyhow::{Context, Result};
+use csv::{Writer, WriterBuilder};
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Read, Write};
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Streams the account rows (balances or diffs) out as CSV. With `flush_every` set, the writer
+/// is flushed every N rows so a consumer reading a pipe sees progress on very large account sets
+/// rather than waiting for the whole table.
+///
+/// The csv writer takes the header from the first row, so `header` is written instead when
+/// there are no rows at all. It must name the columns the rows would have had.
+pub fn write_accounts<W: Write>(
+    accounts: impl IntoIterator<Item = impl Serialize>,
+    header: &[&str],
+    output: W,
+    flush_every: Option<NonZeroUsize>,
+) -> Result<()> {
+    let mut writer = Writer::from_writer(output);
+    let mut rows = 0;
+
+    for account in accounts {
+        writer
+            .serialize(account)
+            .context("Failed to write output")?;
+        rows += 1;
+
+        if flush_every.is_some_and(|n| rows % n == 0) {
+            writer.flush().context("Failed to flush output")?;
+        }
+    }
+
+    if rows == 0 {
+        writer
+            .write_record(header)
+            .context("Failed to write output")?;
+    }
+
+    writer.flush().context("Failed to flush output")?;
+
+    Ok(())
+}
+
+/// Like [`write_accounts`], but a row that fails to serialize is left out instead of aborting,
+/// so one bad account doesn't cost the whole table. Returns how many rows were left out, for
+/// the caller to report. Failing to write to `output` itself still aborts.
+///
+/// Each row is serialized on its own first, so a failure can't leave half a row behind.
+/// The header always comes from `header`.
+pub fn write_accounts_skipping<W: Write>(
+    accounts: impl IntoIterator<Item = impl Serialize>,
+    header: &[&str],
+    output: W,
+    flush_every: Option<NonZeroUsize>,
+) -> Result<u64> {
+    let row_writer = |buffer| WriterBuilder::new().has_headers(false).from_writer(buffer);
+    let mut output = BufWriter::new(output);
+    let (mut rows, mut failed) = (0, 0);
+
+    let mut row = row_writer(Vec::new());
+    row.write_record(header).context("Failed to write output")?;
+    let mut buffer = row.into_inner().context("Failed to write output")?;
+    output
+        .write_all(&buffer)
+        .context("Failed to write output")?;
+
+    for account in accounts {
+        buffer.clear();
+        let mut row = row_writer(buffer);
+        // A failed row may leave partial fields behind, so it's dropped with its writer
+        if row.serialize(account).is_err() {
+            failed += 1;
+            buffer = Vec::new();
+            continue;
+        }
+
+        buffer = row.into_inner().context("Failed to write output")?;
+        output
+            .write_all(&buffer)
+            .context("Failed to write output")?;
+        rows += 1;
+
+        if flush_every.is_some_and(|n| rows % n == 0) {
+            output.flush().context("Failed to flush output")?;
+        }
+    }
+
+    output.flush().context("Failed to flush output")?;
+
+    Ok(failed)
+}
+
+/// Finishes an account CSV that an earlier run left partially written, appending only the
+/// accounts it doesn't hold yet. Resuming a complete file changes nothing, and a missing or
+/// empty one is written from scratch, header included.
+///
+/// A crash mid-row leaves an incomplete last line behind. It's dropped, so that account is
+/// written again in full. The file must have been written with the same `header`.
+pub fn resume_accounts(
+    accounts: impl IntoIterator<Item = AccountOutput>,
+    header: &[&str],
+    path: impl AsRef<Path>,
+    flush_every: Option<NonZeroUsize>,
+) -> Result<()> {
+    let path = path.as_ref();
+    let mut file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)
+        .with_context(|| format!("Failed to open output file: {}", path.display()))?;
+
+    let mut existing = Vec::new();
+    file.read_to_end(&mut existing)
+        .with_context(|| format!("Failed to read partial output: {}", path.display()))?;
+    let complete = existing
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |i| i + 1);
+    if complete < existing.len() {
+        file.set_len(complete as u64)
+            .with_context(|| format!("Failed to truncate partial output: {}", path.display()))?;
+    }
+
+    let mut written = HashSet::new();
+    if complete > 0 {
+        let mut reader = csv::Reader::from_reader(&existing[..complete]);
+        let found = reader.headers().context("Invalid partial output")?;
+        if !found.iter().eq(header.iter().copied()) {
+            anyhow::bail!("{} was written with other columns", path.display());
+        }
+        for row in reader.records() {
+            let row = row.context("Invalid partial output")?;
+            let client: ClientId = row[0].parse().context("Invalid client in partial output")?;
+            written.insert(client);
+        }
+    }
+
+    let mut writer = WriterBuilder::new().has_headers(false).from_writer(file);
+    if complete == 0 {
+        writer
+            .write_record(header)
+            .context("Failed to write output")?;
+    }
+
+    let remaining = accounts
+        .into_iter()
+        .filter(|account| !written.contains(&account.client));
+    for (rows, account) in (1..).zip(remaining) {
+        writer
+            .serialize(account)
+            .context("Failed to write output")?;
+
+        if flush_every.is_some_and(|n| rows % n == 0) {
+            writer.flush().context("Failed to flush output")?;
+        }
+    }
+
+    writer.flush().context("Failed to flush output")?;
+
+    Ok(())
+}
+
+/// Writes the account table in one particular file format.
+pub trait OutputWriter {
+    /// Returns how many rows were left out for failing to serialize, which only the CSV
+    /// writer does, and only with `skip_unwritable`.
+    fn write_accounts(
+        &self,
+        accounts: &mut dyn Iterator<Item = AccountOutput>,
+        output: &mut (dyn Write + Send),
+    ) -> Result<u64>;
+}
+
+/// The standard CSV table, see [`write_accounts`].
+#[derive(Debug, Clone, Default)]
+pub struct CsvOutput {
+    pub flush_every: Option<NonZeroUsize>,
+    /// What the rows were built with, for the header of an empty table.
+    pub output: OutputConfig,
+    /// Only these columns, in this order, rather than the standard ones.
+    pub columns: Option<Vec<OutputColumn>>,
+    /// Leave out rows that fail to serialize, see [`write_accounts_skipping`].
+    pub skip_unwritable: bool,
+}
+
+impl CsvOutput {
+    /// Writes any kind of rows, e.g. account diffs, with this writer's settings.
+    pub fn write_rows(
+        &self,
+        rows: impl IntoIterator<Item = impl Serialize>,
+        header: &[&str],
+        output: impl Write,
+    ) -> Result<u64> {
+        if self.skip_unwritable {
+            write_accounts_skipping(rows, header, output, self.flush_every)
+        } else {
+            write_accounts(rows, header, output, self.flush_every).map(|()| 0)
+        }
+    }
+}
+
+impl OutputWriter for CsvOutput {
+    fn write_accounts(
+        &self,
+        accounts: &mut dyn Iterator<Item = AccountOutput>,
+        output: &mut (dyn Write + Send),
+    ) -> Result<u64> {
+        match &self.columns {
+            Some(columns) => {
+                let rows = accounts.map(|account| SelectedColumns { account, columns });
+                self.write_rows(rows, &column_names(columns), output)
+            }
+            None => {
+                let header = column_names(&OutputColumn::standard(&self.output));
+                self.write_rows(accounts, &header, output)
+            }
+        }
+    }
+}
+
+/// A JSON array with one object per account. Balances are strings, so no precision is lost
+/// to a consumer parsing numbers as floats.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonOutput;
+
+impl OutputWriter for JsonOutput {
+    fn write_accounts(
+        &self,
+        accounts: &mut dyn Iterator<Item = AccountOutput>,
+        mut output: &mut (dyn Write + Send),
+    ) -> Result<u64> {
+        let mut serializer = serde_json::Serializer::new(&mut output);
+        serializer
+            .collect_seq(accounts)
+            .context("Failed to write output")?;
+        writeln!(output).context("Failed to write output")?;
+        output.flush().context("Failed to flush output")?;
+        Ok(0)
+    }
+}
+
+/// The file format of the account table.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    Json,
+    /// Requires the `parquet` feature.
+    Parquet,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "parquet" => Ok(OutputFormat::Parquet),
+            other => {
+                anyhow::bail!("Unknown output format {other:?}, expected csv, json or parquet")
+            }
+        }
+    }
+}
+
+/// Precision and scale of the decimal columns in columnar output. The default scale matches the
+/// four decimal places the CSV output is rounded to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecimalFormat {
+    pub precision: u8,
+    pub scale: i8,
+}
+
+impl Default for DecimalFormat {
+    fn default() -> Self {
+        Self {
+            precision: 38,
+            scale: 4,
+        }
+    }
+}
+
+impl FromStr for DecimalFormat {
+    type Err = anyhow::Error;
+
+    /// Parses `PRECISION,SCALE`, e.g. `18,4`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (precision, scale) = s
+            .split_once(',')
+            .with_context(|| format!("Expected PRECISION,SCALE, got {s:?}"))?;
+        let precision: u8 = precision
+            .trim()
+            .parse()
+            .context("Invalid decimal precision")?;
+        let scale: i8 = scale.trim().parse().context("Invalid decimal scale")?;
+
+        if !(1..=38).contains(&precision) || scale < 0 || scale as u8 > precision {
+            anyhow::bail!("Decimal precision must be 1-38 and scale 0-precision, got {s}");
+        }
+
+        Ok(Self { precision, scale })
+    }
+}
+
+/// A column of the account table, for emitting a subset of them in a chosen order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputColumn {
+    Client,
+    Available,
+    Held,
+    Total,
+    Locked,
+    HadDispute,
+    Memo,
+    Deposited,
+    Withdrawn,
+    Closed,
+}
+
+impl OutputColumn {
+    pub const ALL: [OutputColumn; 10] = [
+        OutputColumn::Client,
+        OutputColumn::Available,
+        OutputColumn::Held,
+        OutputColumn::Total,
+        OutputColumn::Locked,
+        OutputColumn::HadDispute,
+        OutputColumn::Memo,
+        OutputColumn::Deposited,
+        OutputColumn::Withdrawn,
+        OutputColumn::Closed,
+    ];
+
+    /// The columns of a full account row, including the optional ones `config` turns on.
+    pub fn standard(config: &OutputConfig) -> Vec<OutputColumn> {
+        let mut columns = Self::ALL[..5].to_vec();
+        if config.verbose {
+            columns.extend([OutputColumn::HadDispute, OutputColumn::Memo]);
+        }
+        if config.net_flow {
+            columns.extend([OutputColumn::Deposited, OutputColumn::Withdrawn]);
+        }
+        if config.closed_status {
+            columns.push(OutputColumn::Closed);
+        }
+        columns
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputColumn::Client => "client",
+            OutputColumn::Available => "available",
+            OutputColumn::Held => "held",
+            OutputColumn::Total => "total",
+            OutputColumn::Locked => "locked",
+            OutputColumn::HadDispute => "had_dispute",
+            OutputColumn::Memo => "memo",
+            OutputColumn::Deposited => "deposited",
+            OutputColumn::Withdrawn => "withdrawn",
+            OutputColumn::Closed => "closed",
+        }
+    }
+}
+
+pub fn column_names(columns: &[OutputColumn]) -> Vec<&'static str> {
+    columns.iter().map(|column| column.name()).collect()
+}
+
+/// Parses a column spec such as `client,total,locked`, rejecting unknown column names.
+pub fn parse_output_columns(spec: &str) -> Result<Vec<OutputColumn>> {
+    spec.split(',')
+        .map(str::trim)
+        .map(|name| {
+            OutputColumn::ALL
+                .into_iter()
+                .find(|column| column.name() == name)
+                .with_context(|| {
+                    let known: Vec<_> = OutputColumn::ALL.map(OutputColumn::name).into();
+                    format!(
+                        "Unknown output column {name:?}, expected one of {}",
+                        known.join(",")
+                    )
+                })
+        })
+        .collect()
+}
+
+/// Serializes only the selected columns of an account row, in the selected order.
+pub struct SelectedColumns<'a> {
+    pub account: AccountOutput,
+    pub columns: &'a [OutputColumn],
+}
+
+impl Serialize for SelectedColumns<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let account = &self.account;
+        let mut row = serializer.serialize_struct("AccountOutput", self.columns.len())?;
+
+        for &column in self.columns {
+            let name = column.name();
+            match column {
+                OutputColumn::Client => row.serialize_field(name, &account.client)?,
+                OutputColumn::Available => row.serialize_field(name, &account.available)?,
+                OutputColumn::Held => row.serialize_field(name, &account.held)?,
+                OutputColumn::Total => row.serialize_field(name, &account.total)?,
+                OutputColumn::Locked => {
+                    account
+                        .bool_format
+                        .serialize_field(&mut row, name, account.locked)?
+                }
+                OutputColumn::HadDispute => row.serialize_field(name, &account.had_dispute)?,
+                OutputColumn::Memo => row.serialize_field(name, &account.memo)?,
+                OutputColumn::Deposited => row.serialize_field(name, &account.deposited)?,
+                OutputColumn::Withdrawn => row.serialize_field(name, &account.withdrawn)?,
+                OutputColumn::Closed => match account.closed {
+                    Some(closed) => account
+                        .bool_format
+                        .serialize_field(&mut row, name, closed)?,
+                    None => row.serialize_field(name, &account.closed)?,
+                },
+            }
+        }
+
+        row.end()
+    }
+}
+
+/// Writes the summary as `metric,value` CSV rows, in field declaration order.
+pub fn write_summary<W: Write>(summary: &ProcessSummary, output: W) -> Result<()> {
+    let Value::Object(fields) = serde_json::to_value(summary)? else {
+        anyhow::bail!("Summary did not serialize to an object");
+    };
+
+    let mut writer = Writer::from_writer(output);
+    writer.write_record(["metric", "value"])?;
+
+    for (metric, value) in fields {
+        write_metric(&mut writer, metric, value)?;
+    }
+
+    writer.flush().context("Failed to flush summary")?;
+
+    Ok(())
+}
+
+/// Nested metrics such as the per-type counts get one row per leaf, named by their path,
+/// e.g. `transaction_types.deposit.count`.
+fn write_metric<W: Write>(writer: &mut Writer<W>, metric: String, value: Value) -> Result<()> {
+    let value = match value {
+        Value::Object(fields) => {
+            for (name, value) in fields {
+                write_metric(writer, format!("{metric}.{name}"), value)?;
+            }
+            return Ok(());
+        }
+        Value::String(s) => s,
+        other => other.to_string(),
+    };
+    writer
+        .write_record([metric, value])
+        .context("Failed to write summary")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::{Account, BoolFormat};
+    use crate::transaction::ClientId;
+    use rust_decimal::Decimal;
+
+    fn accounts() -> Vec<AccountOutput> {
+        (1..=10)
+            .map(|client| AccountOutput {
+                client: ClientId(client),
+                available: Decimal::from(client),
+                held: Decimal::ZERO,
+                total: Decimal::from(client),
+                locked: false,
+                bool_format: BoolFormat::default(),
+                total_overflow: false,
+                had_dispute: None,
+                memo: None,
+                deposited: None,
+                withdrawn: None,
+                closed: None,
+            })
+            .collect()
+    }
+
+    fn render(flush_every: Option<usize>) -> String {
+        let mut buffer = Vec::new();
+        let flush_every = flush_every.and_then(NonZeroUsize::new);
+        let header = column_names(&OutputColumn::standard(&OutputConfig::default()));
+        write_accounts(accounts(), &header, &mut buffer, flush_every).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn test_json_matches_csv_values() {
+        let mut csv = Vec::new();
+        CsvOutput::default()
+            .write_accounts(&mut accounts().into_iter(), &mut csv)
+            .unwrap();
+        let from_csv: Vec<(ClientId, Decimal, Decimal, Decimal, bool)> =
+            csv::Reader::from_reader(csv.as_slice())
+                .records()
+                .map(Result::unwrap)
+                .map(|row| {
+                    let d = |i: usize| row[i].parse::<Decimal>().unwrap();
+                    (
+                        row[0].parse().unwrap(),
+                        d(1),
+                        d(2),
+                        d(3),
+                        row[4].parse().unwrap(),
+                    )
+                })
+                .collect();
+
+        let mut json = Vec::new();
+        JsonOutput
+            .write_accounts(&mut accounts().into_iter(), &mut json)
+            .unwrap();
+        let Value::Array(rows) = serde_json::from_slice(&json).unwrap() else {
+            panic!("Expected a JSON array");
+        };
+        let from_json: Vec<_> = rows
+            .iter()
+            .map(|row| {
+                let d = |field: &str| row[field].as_str().unwrap().parse::<Decimal>().unwrap();
+                let client = row["client"].as_u64().unwrap() as u16;
+                let locked = row["locked"].as_bool().unwrap();
+                (
+                    ClientId(client),
+                    d("available"),
+                    d("held"),
+                    d("total"),
+                    locked,
+                )
+            })
+            .collect();
+
+        assert_eq!(from_json.len(), 10);
+        assert_eq!(from_json, from_csv);
+    }
+
+    /// An account row whose serialization fails partway through, after its first field.
+    struct Unwritable(AccountOutput);
+
+    impl Serialize for Unwritable {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut row = serializer.serialize_struct("AccountOutput", 5)?;
+            row.serialize_field("client", &self.0.client)?;
+            Err(serde::ser::Error::custom("simulated failure"))
+        }
+    }
+
+    #[derive(Serialize)]
+    #[serde(untagged)]
+    enum Row {
+        Good(AccountOutput),
+        Bad(Unwritable),
+    }
+
+    fn rows_with_failure() -> Vec<Row> {
+        accounts()
+            .into_iter()
+            .map(|account| match u16::from(account.client) {
+                4 => Row::Bad(Unwritable(account)),
+                _ => Row::Good(account),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_write_failure_skips_only_that_row() {
+        let header = column_names(&OutputColumn::standard(&OutputConfig::default()));
+
+        let mut buffer = Vec::new();
+        assert!(write_accounts(rows_with_failure(), &header, &mut buffer, None).is_err());
+
+        let mut buffer = Vec::new();
+        let failed =
+            write_accounts_skipping(rows_with_failure(), &header, &mut buffer, None).unwrap();
+        assert_eq!(failed, 1);
+
+        // Everything else comes out exactly as it would have without the bad row
+        let without: Vec<_> = accounts()
+            .into_iter()
+            .filter(|account| account.client != ClientId(4))
+            .collect();
+        let mut expected = Vec::new();
+        write_accounts(without, &header, &mut expected, None).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            String::from_utf8(expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_header_written_without_rows() {
+        let mut buffer = Vec::new();
+        CsvOutput::default()
+            .write_accounts(&mut std::iter::empty(), &mut buffer)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "client,available,held,total,locked\n"
+        );
+    }
+
+    #[test]
+    fn test_verbose_columns_in_header() {
+        let account = AccountOutput {
+            had_dispute: Some(true),
+            memo: Some(String::new()),
+            ..accounts().remove(0)
+        };
+
+        let mut buffer = Vec::new();
+        let header = column_names(&OutputColumn::standard(&OutputConfig {
+            verbose: true,
+            ..OutputConfig::default()
+        }));
+        write_accounts([account], &header, &mut buffer, None).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "client,available,held,total,locked,had_dispute,memo\n1,1,0,1,false,true,\n"
+        );
+    }
+
+    #[test]
+    fn test_selected_columns_round_trip() {
+        let columns = parse_output_columns("total, client,locked").unwrap();
+        let rows = accounts().into_iter().map(|account| SelectedColumns {
+            account,
+            columns: &columns,
+        });
+
+        let mut buffer = Vec::new();
+        write_accounts(rows, &column_names(&columns), &mut buffer, None).unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+        assert!(text.starts_with("total,client,locked\n1,1,false\n"));
+
+        let mut reader = csv::Reader::from_reader(text.as_bytes());
+        let parsed: Vec<(Decimal, ClientId, bool)> =
+            reader.deserialize().map(Result::unwrap).collect();
+        let expected: Vec<_> = accounts()
+            .into_iter()
+            .map(|a| (a.total, a.client, a.locked))
+            .collect();
+        assert_eq!(parsed, expected);
+    }
+
+    #[test]
+    fn test_bool_formats_round_trip() {
+        for (format, locked, unlocked) in [
+            (BoolFormat::TrueFalse, "true", "false"),
+            (BoolFormat::OneZero, "1", "0"),
+            (BoolFormat::YesNo, "yes", "no"),
+        ] {
+            let rows = [true, false].map(|locked| AccountOutput {
+                locked,
+                bool_format: format,
+                ..accounts().remove(0)
+            });
+
+            let mut buffer = Vec::new();
+            write_accounts(rows, &[], &mut buffer, None).unwrap();
+            let text = String::from_utf8(buffer).unwrap();
+            assert_eq!(
+                text,
+                format!(
+                    "client,available,held,total,locked\n1,1,0,1,{locked}\n1,1,0,1,{unlocked}\n"
+                )
+            );
+
+            let mut reader = csv::Reader::from_reader(text.as_bytes());
+            let parsed: Vec<_> = reader
+                .records()
+                .map(|row| format.parse(&row.unwrap()[4]))
+                .collect();
+            assert_eq!(parsed, [Some(true), Some(false)]);
+
+            // Selecting columns keeps the format
+            let columns = [OutputColumn::Locked];
+            let rows = [true].map(|locked| SelectedColumns {
+                account: AccountOutput {
+                    locked,
+                    bool_format: format,
+                    ..accounts().remove(0)
+                },
+                columns: &columns,
+            });
+            let mut buffer = Vec::new();
+            write_accounts(rows, &[], &mut buffer, None).unwrap();
+            assert_eq!(
+                String::from_utf8(buffer).unwrap(),
+                format!("locked\n{locked}\n")
+            );
+        }
+
+        assert_eq!("1/0".parse::<BoolFormat>().unwrap(), BoolFormat::OneZero);
+        assert_eq!(BoolFormat::YesNo.parse("true"), None);
+        assert!("on/off".parse::<BoolFormat>().is_err());
+    }
+
+    #[test]
+    fn test_fixed_decimals() {
+        let account = Account {
+            available: Decimal::from(5),
+            held: Decimal::new(5, 1),
+            ..Account::default()
+        };
+        let render = |fixed_decimals| {
+            let config = OutputConfig {
+                fixed_decimals,
+                net_flow: true,
+                ..OutputConfig::default()
+            };
+            let row = AccountOutput::from_account_with(ClientId(1), &account, &config);
+            let mut csv = Vec::new();
+            CsvOutput::default()
+                .write_accounts(&mut [row].into_iter(), &mut csv)
+                .unwrap();
+            String::from_utf8(csv).unwrap()
+        };
+
+        assert!(render(false).ends_with("\n1,5,0.5,5.5,false,0,0\n"));
+        assert!(render(true).ends_with("\n1,5.0000,0.5000,5.5000,false,0.0000,0.0000\n"));
+    }
+
+    #[test]
+    fn test_resume_partial_output() {
+        let path = std::env::temp_dir().join(format!("resume-{}.csv", std::process::id()));
+        let header = column_names(&OutputColumn::standard(&OutputConfig::default()));
+        let complete = render(None);
+
+        // Crashed after three full rows and part of a fourth
+        let cut = complete.match_indices('\n').nth(3).unwrap().0 + 5;
+        std::fs::write(&path, &complete[..cut]).unwrap();
+        resume_accounts(accounts(), &header, &path, None).unwrap();
+        let resumed = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(resumed, complete);
+
+        // Resuming a finished file is a no-op
+        resume_accounts(accounts(), &header, &path, None).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), complete);
+
+        // Nothing written yet, header included
+        std::fs::write(&path, "").unwrap();
+        resume_accounts(accounts(), &header, &path, None).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), complete);
+
+        std::fs::write(&path, "client,total\n1,1\n").unwrap();
+        assert!(resume_accounts(accounts(), &header, &path, None).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_unknown_output_column_rejected() {
+        let error = parse_output_columns("client,balance").unwrap_err();
+        assert!(error.to_string().contains("balance"));
+    }
+
+    #[test]
+    fn test_output_independent_of_flush_interval() {
+        let expected = render(None);
+        assert!(expected.starts_with("client,available,held,total,locked\n1,1,0,1,false\n"));
+
+        for interval in [1, 3, 10, 100] {
+            assert_eq!(render(Some(interval)), expected);
+        }
+    }
+}
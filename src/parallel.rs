@@ -0,0 +1,268 @@
+use crate::reader::{ByteParser, RecordError, RecordSource, TransactionReader};
+use crate::transaction::TransactionRecord;
+use csv::ByteRecord;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::num::NonZeroUsize;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::vec;
+
+/// Records per unit of work handed to a parse thread.
+const CHUNK_SIZE: usize = 4096;
+
+/// Chunks in flight per thread, bounding how far reading runs ahead of the engine.
+const CHUNKS_PER_THREAD: u64 = 4;
+
+/// Records paired with the input lines they started on.
+type Chunk = Vec<(u64, Result<ByteRecord, csv::Error>)>;
+type Records = Vec<(u64, Result<TransactionRecord, RecordError>)>;
+type Parsed = (u64, Records, u64);
+
+/// Parses records on a pool of threads while yielding them in input order, so the engine still
+/// applies them one by one exactly as the serial readers would. Splitting the CSV into records
+/// stays on the calling thread; only the field parsing, the expensive part, is spread out.
+///
+/// Uses the byte record parser whatever the configured parse mode, both accept the same input.
+pub struct ParallelReader<R: Read> {
+    reader: TransactionReader<R>,
+    jobs: Option<Sender<(u64, Chunk)>>,
+    results: Receiver<Parsed>,
+    workers: Vec<JoinHandle<()>>,
+    window: u64,
+    next_chunk: u64,
+    next_yield: u64,
+    done_reading: bool,
+    pending: BTreeMap<u64, Records>,
+    current: vec::IntoIter<(u64, Result<TransactionRecord, RecordError>)>,
+    precision_overflows: u64,
+    line: Option<u64>,
+}
+
+impl<R: Read> ParallelReader<R> {
+    pub fn new(reader: TransactionReader<R>, threads: NonZeroUsize) -> Self {
+        let (jobs, job_queue) = mpsc::channel::<(u64, Chunk)>();
+        let (results_tx, results) = mpsc::channel();
+        let job_queue = Arc::new(Mutex::new(job_queue));
+        let parser = reader.byte_parser();
+
+        let workers = (0..threads.get())
+            .map(|_| {
+                let parser = parser.clone();
+                let job_queue = Arc::clone(&job_queue);
+                let results = results_tx.clone();
+                thread::spawn(move || parse_chunks(parser, &job_queue, &results))
+            })
+            .collect();
+
+        Self {
+            reader,
+            jobs: Some(jobs),
+            results,
+            workers,
+            window: threads.get() as u64 * CHUNKS_PER_THREAD,
+            next_chunk: 0,
+            next_yield: 0,
+            done_reading: false,
+            pending: BTreeMap::new(),
+            current: Vec::new().into_iter(),
+            precision_overflows: 0,
+            line: None,
+        }
+    }
+
+    /// Amounts seen so far with more decimal places than `Decimal` supports,
+    /// whether they were rounded or rejected.
+    pub fn precision_overflows(&self) -> u64 {
+        self.precision_overflows
+    }
+
+    /// Lines skipped so far for exceeding `max_record_size`.
+    pub fn oversized(&self) -> u64 {
+        self.reader.oversized()
+    }
+
+    /// Reads ahead until the window of chunks being parsed is full or the input runs out.
+    fn fill_window(&mut self) {
+        while !self.done_reading && self.next_chunk - self.next_yield < self.window {
+            let chunk = self.reader.read_byte_records(CHUNK_SIZE);
+            if chunk.is_empty() {
+                self.done_reading = true;
+                break;
+            }
+
+            let jobs = self.jobs.as_ref().expect("jobs sender lives until drop");
+            jobs.send((self.next_chunk, chunk))
+                .expect("parse threads outlive the reader");
+            self.next_chunk += 1;
+        }
+    }
+}
+
+fn parse_chunks(
+    parser: ByteParser,
+    job_queue: &Mutex<Receiver<(u64, Chunk)>>,
+    results: &Sender<Parsed>,
+) {
+    loop {
+        // Hold the lock only to take a job, not while parsing it
+        let job = job_queue.lock().expect("job queue poisoned").recv();
+        let Ok((index, chunk)) = job else {
+            return;
+        };
+
+        let mut precision_overflows = 0;
+        let records = chunk
+            .into_iter()
+            .map(|(line, record)| {
+                let record = record
+                    .map_err(Into::into)
+                    .and_then(|record| parser.parse(&record, &mut precision_overflows));
+                (line, record)
+            })
+            .collect();
+
+        if results.send((index, records, precision_overflows)).is_err() {
+            return;
+        }
+    }
+}
+
+impl<R: Read> Iterator for ParallelReader<R> {
+    type Item = Result<TransactionRecord, RecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((line, record)) = self.current.next() {
+                self.line = Some(line);
+                return Some(record);
+            }
+
+            self.fill_window();
+            if self.next_yield == self.next_chunk {
+                return None;
+            }
+
+            // Chunks finish in any order, hold on to the early ones until their turn
+            while !self.pending.contains_key(&self.next_yield) {
+                let (index, records, precision_overflows) =
+                    self.results.recv().expect("parse thread panicked");
+                self.precision_overflows += precision_overflows;
+                self.pending.insert(index, records);
+            }
+
+            let records = self.pending.remove(&self.next_yield).unwrap_or_default();
+            self.current = records.into_iter();
+            self.next_yield += 1;
+        }
+    }
+}
+
+impl<R: Read> Drop for ParallelReader<R> {
+    fn drop(&mut self) {
+        // Closing the job queue lets the threads run out of work and exit
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl<R: Read> RecordSource for ParallelReader<R> {
+    fn line(&self) -> Option<u64> {
+        self.line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::PaymentEngine;
+    use crate::reader::{InputConfig, ParseMode};
+    use std::time::Instant;
+
+    fn input(rows: u32) -> String {
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 1..=rows {
+            let client = tx % 50;
+            let row = match tx % 10 {
+                0 => format!("dispute,{client},{},\n", tx.saturating_sub(10)),
+                3 => format!("resolve,{client},{},\n", tx.saturating_sub(13)),
+                5 => format!("withdrawal,{client},{tx},{}.25\n", tx % 7),
+                7 => format!("chargeback,{client},{},\n", tx.saturating_sub(17)),
+                8 => format!("deposit,{client},{tx},oops\n"),
+                9 => format!("deposit,{client},{tx},1.{}\n", "1".repeat(30)),
+                _ => format!("deposit,{client},{tx},{}.{:04}\n", tx % 100, tx % 10_000),
+            };
+            input.push_str(&row);
+        }
+        input
+    }
+
+    fn apply(records: impl Iterator<Item = Result<TransactionRecord, RecordError>>) -> String {
+        let mut engine = PaymentEngine::new();
+        for record in records.flatten() {
+            let _ = engine.process_transaction(record);
+        }
+        engine.to_json().to_string()
+    }
+
+    #[test]
+    fn test_parallel_matches_serial() {
+        let input = input(50_000);
+        let config = InputConfig {
+            parse_mode: ParseMode::ByteRecord,
+            ..InputConfig::default()
+        };
+
+        let serial: Vec<_> = TransactionReader::new(input.as_bytes(), &config)
+            .unwrap()
+            .map(|r| r.map_err(|e| e.to_string()))
+            .collect();
+
+        let reader = TransactionReader::new(input.as_bytes(), &config).unwrap();
+        let mut parallel = ParallelReader::new(reader, NonZeroUsize::new(4).unwrap());
+        let results: Vec<_> = parallel
+            .by_ref()
+            .map(|r| r.map_err(|e| e.to_string()))
+            .collect();
+
+        assert_eq!(results.len(), 50_000);
+        assert_eq!(results, serial);
+        assert_eq!(parallel.precision_overflows(), 5_000);
+
+        let reader = TransactionReader::new(input.as_bytes(), &config).unwrap();
+        let parallel = ParallelReader::new(reader, NonZeroUsize::new(3).unwrap());
+        let serial = TransactionReader::new(input.as_bytes(), &config).unwrap();
+        assert_eq!(apply(parallel), apply(serial));
+    }
+
+    /// Serial vs parallel parsing:
+    /// `cargo test --release bench_parallel_parse -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_parallel_parse() {
+        let input = input(2_000_000);
+        let config = InputConfig {
+            parse_mode: ParseMode::ByteRecord,
+            ..InputConfig::default()
+        };
+
+        let start = Instant::now();
+        let count = TransactionReader::new(input.as_bytes(), &config)
+            .unwrap()
+            .count();
+        println!("serial: {count} records in {:?}", start.elapsed());
+
+        for threads in [2, 4, 8] {
+            let reader = TransactionReader::new(input.as_bytes(), &config).unwrap();
+            let start = Instant::now();
+            let count = ParallelReader::new(reader, NonZeroUsize::new(threads).unwrap()).count();
+            println!(
+                "{threads} threads: {count} records in {:?}",
+                start.elapsed()
+            );
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use crate::account::AccountOutput;
+use crate::output::{DecimalFormat, OutputWriter};
+use anyhow::{Context, Result};
+use arrow_array::{ArrayRef, BooleanArray, Decimal128Array, RecordBatch, UInt16Array};
+use arrow_schema::{DataType, Field, Schema};
+use parquet::arrow::ArrowWriter;
+use rust_decimal::Decimal;
+use std::io::Write;
+use std::sync::Arc;
+
+/// Writes the account table as a single Parquet row group, with columns
+/// client (UInt16), available/held/total (Decimal128) and locked (Boolean).
+///
+/// Unlike the CSV output this isn't streamed: Parquet is columnar, so the rows are collected first.
+pub fn write_parquet<W: Write + Send>(
+    accounts: impl IntoIterator<Item = AccountOutput>,
+    output: W,
+    format: DecimalFormat,
+) -> Result<()> {
+    let accounts: Vec<_> = accounts.into_iter().collect();
+
+    let decimal_type = DataType::Decimal128(format.precision, format.scale);
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("client", DataType::UInt16, false),
+        Field::new("available", decimal_type.clone(), false),
+        Field::new("held", decimal_type.clone(), false),
+        Field::new("total", decimal_type, false),
+        Field::new("locked", DataType::Boolean, false),
+    ]));
+
+    let decimals = |value: fn(&AccountOutput) -> Decimal| -> Result<ArrayRef> {
+        let array = accounts
+            .iter()
+            .map(|account| mantissa(value(account), format.scale))
+            .collect::<Decimal128Array>()
+            .with_precision_and_scale(format.precision, format.scale)?;
+        array
+            .validate_decimal_precision(format.precision)
+            .context("Balance does not fit the configured decimal precision")?;
+        Ok(Arc::new(array))
+    };
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt16Array::from_iter_values(
+            accounts.iter().map(|account| account.client.0),
+        )),
+        decimals(|account| account.available)?,
+        decimals(|account| account.held)?,
+        decimals(|account| account.total)?,
+        Arc::new(BooleanArray::from_iter(
+            accounts.iter().map(|account| Some(account.locked)),
+        )),
+    ];
+
+    let batch = RecordBatch::try_new(schema.clone(), columns)?;
+    let mut writer = ArrowWriter::try_new(output, schema, None)?;
+    writer.write(&batch).context("Failed to write output")?;
+    writer.close().context("Failed to finish Parquet output")?;
+
+    Ok(())
+}
+
+/// [`write_parquet`] behind the [`OutputWriter`] interface.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParquetOutput {
+    pub format: DecimalFormat,
+}
+
+impl OutputWriter for ParquetOutput {
+    fn write_accounts(
+        &self,
+        accounts: &mut dyn Iterator<Item = AccountOutput>,
+        output: &mut (dyn Write + Send),
+    ) -> Result<u64> {
+        write_parquet(accounts, output, self.format).map(|()| 0)
+    }
+}
+
+/// The value as an integer count of `10^-scale` units.
+fn mantissa(mut value: Decimal, scale: i8) -> i128 {
+    value.rescale(scale as u32);
+    value.mantissa()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Options;
+    use crate::engine::PaymentEngine;
+    use crate::output::{CsvOutput, OutputFormat};
+    use crate::transaction::{TransactionRecord, TransactionType};
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Decimal128Type, UInt16Type};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::fs::File;
+    use std::path::Path;
+    use std::str::FromStr;
+
+    fn engine() -> PaymentEngine {
+        let mut engine = PaymentEngine::new();
+        let records = [
+            (TransactionType::Deposit, 1, 1, Some("10.12346")),
+            (TransactionType::Deposit, 2, 2, Some("2.5")),
+            (TransactionType::Withdrawal, 1, 3, Some("0.1")),
+            (TransactionType::Dispute, 2, 2, None),
+            (TransactionType::Chargeback, 2, 2, None),
+        ];
+        for (tx_type, client, tx, amount) in records {
+            engine
+                .process_transaction(TransactionRecord {
+                    tx_type,
+                    client: client.into(),
+                    tx: tx.into(),
+                    amount: amount.map(|a| Decimal::from_str(a).unwrap()),
+                    memo: None,
+                    timestamp: None,
+                })
+                .unwrap();
+        }
+        engine
+    }
+
+    type Row = (u16, Decimal, Decimal, Decimal, bool);
+
+    /// Writes the account table through `write_output`, as a run with `format` would.
+    fn write_output(engine: &PaymentEngine, format: OutputFormat, output: &mut (dyn Write + Send)) {
+        let options = Options {
+            output_format: format,
+            ..Options::default()
+        };
+        crate::write_output(engine, None, &options, output).unwrap();
+    }
+
+    fn parquet_rows(path: &Path, format: DecimalFormat) -> Vec<Row> {
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut rows = Vec::new();
+        for batch in reader {
+            let batch = batch.unwrap();
+            let decimal = |i: usize, row: usize| {
+                let value = batch.column(i).as_primitive::<Decimal128Type>().value(row);
+                Decimal::from_i128_with_scale(value, format.scale as u32).normalize()
+            };
+            for row in 0..batch.num_rows() {
+                rows.push((
+                    batch.column(0).as_primitive::<UInt16Type>().value(row),
+                    decimal(1, row),
+                    decimal(2, row),
+                    decimal(3, row),
+                    batch.column(4).as_boolean().value(row),
+                ));
+            }
+        }
+        rows
+    }
+
+    fn csv_rows(csv: &[u8]) -> Vec<Row> {
+        // Read the CSV balances as text, going through serde would parse them as f64
+        let d = |s: &str| Decimal::from_str(s).unwrap().normalize();
+        csv::Reader::from_reader(csv)
+            .records()
+            .map(Result::unwrap)
+            .map(|row| {
+                (
+                    row[0].parse::<u16>().unwrap(),
+                    d(&row[1]),
+                    d(&row[2]),
+                    d(&row[3]),
+                    row[4].parse::<bool>().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parquet_matches_csv_rows() {
+        let engine = engine();
+        let format = DecimalFormat::default();
+
+        let path = std::env::temp_dir().join(format!("accounts-{}.parquet", std::process::id()));
+        ParquetOutput { format }
+            .write_accounts(&mut engine.accounts(), &mut File::create(&path).unwrap())
+            .unwrap();
+        let mut from_parquet = parquet_rows(&path, format);
+        std::fs::remove_file(&path).unwrap();
+
+        let mut csv = Vec::new();
+        CsvOutput::default()
+            .write_accounts(&mut engine.accounts(), &mut csv)
+            .unwrap();
+        let mut from_csv = csv_rows(&csv);
+
+        from_parquet.sort_by_key(|row| row.0);
+        from_csv.sort_by_key(|row| row.0);
+        assert_eq!(from_parquet, from_csv);
+        assert_eq!(from_csv[0].1, Decimal::from_str("10.0235").unwrap());
+    }
+
+    #[test]
+    fn test_output_formats_write_the_same_rows() {
+        let engine = engine();
+
+        let path = std::env::temp_dir().join(format!("output-{}.parquet", std::process::id()));
+        write_output(
+            &engine,
+            OutputFormat::Parquet,
+            &mut File::create(&path).unwrap(),
+        );
+        let from_parquet = parquet_rows(&path, DecimalFormat::default());
+        std::fs::remove_file(&path).unwrap();
+
+        let mut csv = Vec::new();
+        write_output(&engine, OutputFormat::Csv, &mut csv);
+
+        // Both in client order, as every format writes them
+        assert_eq!(from_parquet, csv_rows(&csv));
+        assert_eq!(from_parquet.len(), 2);
+    }
+
+    #[test]
+    fn test_precision_overflow_is_an_error() {
+        let format = DecimalFormat {
+            precision: 5,
+            scale: 4,
+        };
+        let result = write_parquet(engine().accounts(), Vec::new(), format);
+        assert!(result.is_err());
+    }
+}
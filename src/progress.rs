@@ -0,0 +1,43 @@
+use std::time::{Duration, Instant};
+
+/// Periodic "still alive" line on stderr for long runs. Printed every `every` records or
+/// every `interval`, whichever comes first, and never touches stdout.
+pub struct Progress {
+    every: u64,
+    interval: Duration,
+    start: Instant,
+    last: Instant,
+}
+
+impl Progress {
+    pub fn new(every: u64, interval: Duration) -> Self {
+        let now = Instant::now();
+        Self {
+            every,
+            interval,
+            start: now,
+            last: now,
+        }
+    }
+
+    /// Called once per record read. Checking the clock is cheap enough to do every time.
+    pub fn tick(&mut self, records: u64) {
+        let now = Instant::now();
+        if !records.is_multiple_of(self.every) && now.duration_since(self.last) < self.interval {
+            return;
+        }
+
+        self.last = now;
+        let elapsed = now.duration_since(self.start).as_secs_f64();
+        eprintln!(
+            "Progress: {records} records in {elapsed:.1}s ({:.0} records/s)",
+            records as f64 / elapsed.max(f64::EPSILON)
+        );
+    }
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self::new(100_000, Duration::from_secs(1))
+    }
+}
@@ -0,0 +1,1024 @@
+use crate::transaction::{
+    AmountError, AmountFormat, EuropeanAmounts, PrecisionPolicy, RawRecord, StandardAmounts,
+    TransactionRecord, TransactionType, exceeds_max_scale, parse_amount, strip_grouping,
+};
+use anyhow::{Context, Result};
+use csv::{ByteRecord, Position, StringRecord};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::str::{self, FromStr};
+use std::sync::Arc;
+use thiserror::Error;
+
+/// The input columns, in the order the spec lays them out.
+pub const DEFAULT_COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+const REQUIRED_COLUMNS: [&str; 3] = ["type", "client", "tx"];
+
+/// Columns that may be present on top of the default ones: a memo under either name, and a
+/// Unix timestamp in seconds.
+const OPTIONAL_COLUMNS: [&str; 3] = ["memo", "description", "timestamp"];
+
+/// How rows are turned into records.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Deserialize each row through serde. Simple, but allocates a `String` per field.
+    #[default]
+    Serde,
+    /// Parse the fields straight out of a reused `ByteRecord`, without intermediate strings.
+    ByteRecord,
+}
+
+#[derive(Debug, Clone)]
+pub struct InputConfig {
+    /// Whether the first row is a header naming the columns.
+    pub has_headers: bool,
+    /// Column names, in order, for input without a header row.
+    pub columns: Vec<String>,
+    pub precision: PrecisionPolicy,
+    pub parse_mode: ParseMode,
+    pub delimiter: u8,
+    /// Which whitespace the CSV reader strips. Everything by default; memo-like fields may want
+    /// their surrounding spaces kept.
+    pub trim: csv::Trim,
+    /// Strip this digit grouping character from amounts, e.g. `1,000.50` in a file using another
+    /// delimiter. Off by default, since it can turn a typo into a valid looking amount.
+    pub grouping_separator: Option<char>,
+    /// The notation amounts are written in, applied before `grouping_separator`.
+    pub amount_format: Arc<dyn AmountFormat>,
+    /// Lines longer than this many bytes are skipped without being buffered in full, so a
+    /// garbage file with a gigantic line can't exhaust memory. `None` means unlimited.
+    pub max_record_size: Option<usize>,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        Self {
+            has_headers: true,
+            columns: DEFAULT_COLUMNS.map(String::from).to_vec(),
+            precision: PrecisionPolicy::default(),
+            parse_mode: ParseMode::default(),
+            delimiter: b',',
+            trim: csv::Trim::All,
+            grouping_separator: None,
+            amount_format: Arc::new(StandardAmounts),
+            max_record_size: None,
+        }
+    }
+}
+
+/// Why a row couldn't be turned into a `TransactionRecord`.
+#[derive(Debug, Error)]
+pub enum RecordError {
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error(transparent)]
+    Amount(#[from] AmountError),
+    #[cfg(feature = "sqlite")]
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+    #[cfg(feature = "binary")]
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("missing or invalid {0} field")]
+    Field(&'static str),
+}
+
+/// A source of transaction records, which may know where in the input each one came from.
+pub trait RecordSource: Iterator<Item = Result<TransactionRecord, RecordError>> {
+    /// The input line the last record returned started on, for inputs made of lines of text.
+    fn line(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// Parses a column spec such as `type,client,tx,amount`.
+pub fn parse_columns(spec: &str) -> Result<Vec<String>> {
+    let columns: Vec<String> = spec.split(',').map(|c| c.trim().to_string()).collect();
+
+    for (i, column) in columns.iter().enumerate() {
+        if !DEFAULT_COLUMNS.contains(&column.as_str())
+            && !OPTIONAL_COLUMNS.contains(&column.as_str())
+        {
+            anyhow::bail!("Column spec {spec:?} has an unknown column {column:?}");
+        }
+        if columns[..i].contains(column) {
+            anyhow::bail!("Column spec {spec:?} names the {column:?} column twice");
+        }
+    }
+
+    for required in REQUIRED_COLUMNS {
+        if !columns.iter().any(|c| c == required) {
+            anyhow::bail!("Column spec {spec:?} is missing the {required:?} column");
+        }
+    }
+
+    Ok(columns)
+}
+
+/// Parses a `--trim` value: `all`, `headers`, `fields` or `none`.
+pub fn parse_trim(name: &str) -> Result<csv::Trim> {
+    match name.trim().to_lowercase().as_str() {
+        "all" => Ok(csv::Trim::All),
+        "headers" => Ok(csv::Trim::Headers),
+        "fields" => Ok(csv::Trim::Fields),
+        "none" => Ok(csv::Trim::None),
+        other => {
+            anyhow::bail!("Unknown trim mode {other:?}, expected all, headers, fields or none")
+        }
+    }
+}
+
+/// How the input is encoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InputFormat {
+    #[default]
+    Csv,
+    /// Fixed-width binary records, see `binary::BinaryReader`. Requires the `binary` feature.
+    Binary,
+}
+
+impl FromStr for InputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "csv" => Ok(InputFormat::Csv),
+            "binary" => Ok(InputFormat::Binary),
+            other => anyhow::bail!("Unknown input format {other:?}, expected csv or binary"),
+        }
+    }
+}
+
+/// Parses an `--amount-locale` value: `us` for `1234.50` or `eu` for `1234,50`.
+pub fn parse_amount_locale(name: &str) -> Result<Arc<dyn AmountFormat>> {
+    match name.trim().to_lowercase().as_str() {
+        "us" => Ok(Arc::new(StandardAmounts)),
+        "eu" => Ok(Arc::new(EuropeanAmounts)),
+        other => anyhow::bail!("Unknown amount locale {other:?}, expected us or eu"),
+    }
+}
+
+/// Checks a header row against the expected columns, in any order, allowing the optional ones.
+/// A mismatch usually means the wrong file or a changed export format, and would otherwise only
+/// show up as every single row being skipped, so we fail up front and say exactly what differs.
+pub fn check_headers(headers: &StringRecord) -> Result<()> {
+    let missing: Vec<_> = DEFAULT_COLUMNS
+        .into_iter()
+        .filter(|column| !headers.iter().any(|h| h == *column))
+        .collect();
+    let unexpected: Vec<_> = headers
+        .iter()
+        .filter(|h| !DEFAULT_COLUMNS.contains(h) && !OPTIONAL_COLUMNS.contains(h))
+        .map(|h| format!("{h:?}"))
+        .collect();
+
+    if missing.is_empty() && unexpected.is_empty() {
+        return Ok(());
+    }
+
+    let mut problems = Vec::new();
+    if !missing.is_empty() {
+        problems.push(format!("missing columns: {}", missing.join(", ")));
+    }
+    if !unexpected.is_empty() {
+        problems.push(format!("unexpected columns: {}", unexpected.join(", ")));
+    }
+    anyhow::bail!(
+        "Header row does not match the expected columns {} ({})",
+        DEFAULT_COLUMNS.join(","),
+        problems.join("; ")
+    )
+}
+
+/// Opens the input file, or stdin for `-`. A `.zst` file is decompressed on the fly as it's
+/// read, which takes the zstd feature.
+pub fn open(path: &str) -> Result<Box<dyn Read>> {
+    if path == "-" {
+        return Ok(Box::new(io::stdin().lock()));
+    }
+
+    let file = File::open(path).with_context(|| format!("Failed to open file: {path}"))?;
+    if path.ends_with(".zst") {
+        #[cfg(feature = "zstd")]
+        return Ok(Box::new(zstd::Decoder::new(file).with_context(|| {
+            format!("Failed to start decompressing: {path}")
+        })?));
+        #[cfg(not(feature = "zstd"))]
+        anyhow::bail!("Reading {path} requires building with the zstd feature");
+    }
+    Ok(Box::new(file))
+}
+
+/// Passes the input through line by line, dropping lines longer than `max` bytes (line ending
+/// not included) while holding at most `max` bytes of them. Works on physical lines, so a quoted
+/// field spanning lines is measured per line.
+///
+/// A header line is never dropped, since the first record would then be taken for the header:
+/// an oversized one is an error instead.
+struct LineLimit<R> {
+    inner: BufReader<R>,
+    max: Option<usize>,
+    line: Vec<u8>,
+    pos: usize,
+    oversized: u64,
+    /// The next line is the header row.
+    header: bool,
+    /// Lines passed through so far.
+    passed: u64,
+    /// For each dropped line not yet accounted for by `physical_line`, how many lines had been
+    /// passed through before it.
+    dropped: VecDeque<u64>,
+    /// Dropped lines ahead of the last line asked for by `physical_line`.
+    dropped_before: u64,
+}
+
+impl<R: Read> LineLimit<R> {
+    fn new(inner: R, max: Option<usize>, has_headers: bool) -> Self {
+        Self {
+            inner: BufReader::new(inner),
+            max,
+            line: Vec::new(),
+            pos: 0,
+            oversized: 0,
+            header: has_headers,
+            passed: 0,
+            dropped: VecDeque::new(),
+            dropped_before: 0,
+        }
+    }
+
+    /// Maps the number of a line as passed through to its number in the input, counting the
+    /// dropped lines too. Lines have to be asked for in increasing order.
+    fn physical_line(&mut self, line: u64) -> u64 {
+        while self.dropped.front().is_some_and(|&passed| passed < line) {
+            self.dropped.pop_front();
+            self.dropped_before += 1;
+        }
+        line + self.dropped_before
+    }
+
+    /// Loads the next line that fits into `line`, leaving it empty at the end of the input.
+    fn next_line(&mut self, max: usize) -> io::Result<()> {
+        loop {
+            self.line.clear();
+            self.pos = 0;
+
+            let limit = max as u64 + 1;
+            let read = (&mut self.inner)
+                .take(limit)
+                .read_until(b'\n', &mut self.line)?;
+            if read as u64 <= max as u64 || self.line.ends_with(b"\n") {
+                self.header = false;
+                if read > 0 {
+                    self.passed += 1;
+                }
+                return Ok(());
+            }
+            if self.header {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("header row is longer than {max} bytes"),
+                ));
+            }
+
+            // Over the limit without a line ending yet: drop the rest of the line too
+            self.inner.skip_until(b'\n')?;
+            self.oversized += 1;
+            self.dropped.push_back(self.passed);
+        }
+    }
+}
+
+impl<R: Read> Read for LineLimit<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(max) = self.max else {
+            return self.inner.read(buf);
+        };
+
+        if self.pos == self.line.len() {
+            self.next_line(max)?;
+        }
+
+        let n = (&self.line[self.pos..]).read(buf)?;
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Positions of the known columns, resolved once from the headers for the byte record path.
+#[derive(Debug, Clone, Copy)]
+struct ColumnIndex {
+    tx_type: Option<usize>,
+    client: Option<usize>,
+    tx: Option<usize>,
+    amount: Option<usize>,
+    memo: Option<usize>,
+    timestamp: Option<usize>,
+}
+
+impl ColumnIndex {
+    fn new(headers: &StringRecord) -> Self {
+        let position = |name: &str| headers.iter().position(|h| h == name);
+
+        Self {
+            tx_type: position("type"),
+            client: position("client"),
+            tx: position("tx"),
+            amount: position("amount"),
+            memo: position("memo").or_else(|| position("description")),
+            timestamp: position("timestamp"),
+        }
+    }
+}
+
+fn field<'r>(
+    record: &'r ByteRecord,
+    index: Option<usize>,
+    name: &'static str,
+) -> Result<&'r [u8], RecordError> {
+    index
+        .and_then(|i| record.get(i))
+        .map(<[u8]>::trim_ascii)
+        .ok_or(RecordError::Field(name))
+}
+
+fn parse_number<T: FromStr>(bytes: &[u8], name: &'static str) -> Result<T, RecordError> {
+    str::from_utf8(bytes)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or(RecordError::Field(name))
+}
+
+/// Takes the common lowercase names without allocating, anything else goes through
+/// `TransactionType::from_name` like on the serde path, so both accept the same input.
+fn parse_type(bytes: &[u8]) -> Result<TransactionType, RecordError> {
+    match bytes {
+        b"" => Err(RecordError::Field("type")),
+        b"deposit" => Ok(TransactionType::Deposit),
+        b"withdrawal" => Ok(TransactionType::Withdrawal),
+        b"dispute" => Ok(TransactionType::Dispute),
+        b"resolve" => Ok(TransactionType::Resolve),
+        b"chargeback" => Ok(TransactionType::Chargeback),
+        // A name of nothing but padding is as good as missing
+        custom => str::from_utf8(custom)
+            .ok()
+            .map(|name| TransactionType::from_name(name.to_string()))
+            .filter(|tx_type| *tx_type != TransactionType::Custom(String::new()))
+            .ok_or(RecordError::Field("type")),
+    }
+}
+
+/// Reads transaction records, mapping fields to columns by name. The names come from the
+/// header row or, for header-less feeds, from the configured column order.
+pub struct TransactionReader<R: Read> {
+    reader: csv::Reader<LineLimit<R>>,
+    headers: StringRecord,
+    parser: ByteParser,
+    parse_mode: ParseMode,
+    byte_record: ByteRecord,
+    precision_overflows: u64,
+    line: Option<u64>,
+}
+
+impl<R: Read> TransactionReader<R> {
+    pub fn new(input: R, config: &InputConfig) -> Result<Self> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(config.trim)
+            .delimiter(config.delimiter)
+            .has_headers(config.has_headers)
+            .from_reader(LineLimit::new(
+                input,
+                config.max_record_size,
+                config.has_headers,
+            ));
+
+        let headers = if config.has_headers {
+            let headers = reader
+                .headers()
+                .context("Failed to read header row")?
+                .clone();
+            // A completely empty input has no header either, it's just no transactions
+            if !headers.is_empty() {
+                check_headers(&headers)?;
+            }
+            headers
+        } else {
+            StringRecord::from(config.columns.clone())
+        };
+
+        Ok(Self {
+            reader,
+            parser: ByteParser {
+                columns: ColumnIndex::new(&headers),
+                precision: config.precision,
+                grouping_separator: config.grouping_separator,
+                amount_format: Arc::clone(&config.amount_format),
+            },
+            headers,
+            parse_mode: config.parse_mode,
+            byte_record: ByteRecord::new(),
+            precision_overflows: 0,
+            line: None,
+        })
+    }
+
+    /// Amounts seen so far with more decimal places than `Decimal` supports,
+    /// whether they were rounded or rejected.
+    pub fn precision_overflows(&self) -> u64 {
+        self.precision_overflows
+    }
+
+    /// Lines skipped so far for exceeding `max_record_size`. The csv reader reads ahead, so this
+    /// is only final once the records have run out.
+    pub fn oversized(&self) -> u64 {
+        self.reader.get_ref().oversized
+    }
+
+    /// The input line a record read from `position`, or failing with `error`, started on.
+    fn line_of(&mut self, position: Option<&Position>, error: Option<&csv::Error>) -> u64 {
+        let line = position
+            .or_else(|| error.and_then(csv::Error::position))
+            .unwrap_or_else(|| self.reader.position())
+            .line();
+        self.reader.get_mut().physical_line(line)
+    }
+
+    fn next_serde(&mut self) -> Option<Result<TransactionRecord, RecordError>> {
+        let mut record = StringRecord::new();
+
+        match self.reader.read_record(&mut record) {
+            Ok(true) => self.line = Some(self.line_of(record.position(), None)),
+            Ok(false) => return None,
+            Err(e) => {
+                self.line = Some(self.line_of(None, Some(&e)));
+                return Some(Err(e.into()));
+            }
+        }
+
+        let mut raw: RawRecord = match record.deserialize(Some(&self.headers)) {
+            Ok(raw) => raw,
+            Err(e) => return Some(Err(e.into())),
+        };
+
+        if let Some(amount) = &mut raw.amount {
+            match self.parser.normalize_amount(amount) {
+                Ok(normalized) => *amount = normalized.into_owned(),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        if raw.amount.as_deref().is_some_and(exceeds_max_scale) {
+            self.precision_overflows += 1;
+        }
+
+        Some(raw.into_record(self.parser.precision).map_err(Into::into))
+    }
+
+    fn next_bytes(&mut self) -> Option<Result<TransactionRecord, RecordError>> {
+        match self.reader.read_byte_record(&mut self.byte_record) {
+            Ok(true) => {
+                let position = self.byte_record.position().cloned();
+                self.line = Some(self.line_of(position.as_ref(), None));
+                Some(self.parse_byte_record())
+            }
+            Ok(false) => None,
+            Err(e) => {
+                self.line = Some(self.line_of(None, Some(&e)));
+                Some(Err(e.into()))
+            }
+        }
+    }
+
+    fn parse_byte_record(&mut self) -> Result<TransactionRecord, RecordError> {
+        self.parser
+            .parse(&self.byte_record, &mut self.precision_overflows)
+    }
+
+    /// The byte record path's parsing, detached from the reader so it can run on other threads.
+    pub(crate) fn byte_parser(&self) -> ByteParser {
+        self.parser.clone()
+    }
+
+    /// Reads up to `count` raw records along with the lines they started on, fewer only at the
+    /// end of the input.
+    pub(crate) fn read_byte_records(
+        &mut self,
+        count: usize,
+    ) -> Vec<(u64, Result<ByteRecord, csv::Error>)> {
+        let mut records = Vec::with_capacity(count);
+        while records.len() < count {
+            let mut record = ByteRecord::new();
+            match self.reader.read_byte_record(&mut record) {
+                Ok(true) => {
+                    let position = record.position().cloned();
+                    records.push((self.line_of(position.as_ref(), None), Ok(record)));
+                }
+                Ok(false) => break,
+                Err(e) => records.push((self.line_of(None, Some(&e)), Err(e))),
+            }
+        }
+        records
+    }
+}
+
+/// Turns byte records into transaction records, see `ParseMode::ByteRecord`.
+#[derive(Debug, Clone)]
+pub(crate) struct ByteParser {
+    columns: ColumnIndex,
+    precision: PrecisionPolicy,
+    grouping_separator: Option<char>,
+    amount_format: Arc<dyn AmountFormat>,
+}
+
+impl ByteParser {
+    /// Brings an amount into plain notation, both on this path and the serde one.
+    fn normalize_amount<'a>(&self, raw: &'a str) -> Result<Cow<'a, str>, RecordError> {
+        let raw = self.amount_format.normalize(raw)?;
+        if let Some(separator) = self.grouping_separator
+            && let Cow::Owned(stripped) = strip_grouping(&raw, separator)
+        {
+            return Ok(Cow::Owned(stripped));
+        }
+        Ok(raw)
+    }
+
+    /// Counts amounts with more decimal places than `Decimal` supports in `precision_overflows`.
+    pub(crate) fn parse(
+        &self,
+        record: &ByteRecord,
+        precision_overflows: &mut u64,
+    ) -> Result<TransactionRecord, RecordError> {
+        let Self {
+            columns, precision, ..
+        } = self;
+
+        let tx_type = parse_type(field(record, columns.tx_type, "type")?)?;
+        let client = parse_number(field(record, columns.client, "client")?, "client")?;
+        let tx = parse_number(field(record, columns.tx, "tx")?, "tx")?;
+
+        let amount = match columns.amount.and_then(|i| record.get(i)) {
+            Some(bytes) if !bytes.trim_ascii().is_empty() => {
+                let raw =
+                    str::from_utf8(bytes.trim_ascii()).map_err(|_| RecordError::Field("amount"))?;
+                let raw = self.normalize_amount(raw)?;
+
+                if exceeds_max_scale(&raw) {
+                    *precision_overflows += 1;
+                }
+                Some(parse_amount(&raw, *precision)?)
+            }
+            _ => None,
+        };
+
+        let memo = match columns.memo.and_then(|i| record.get(i)) {
+            Some(bytes) if !bytes.is_empty() => Some(
+                str::from_utf8(bytes)
+                    .map_err(|_| RecordError::Field("memo"))?
+                    .to_string(),
+            ),
+            _ => None,
+        };
+
+        let timestamp = match columns.timestamp.and_then(|i| record.get(i)) {
+            Some(bytes) if !bytes.trim_ascii().is_empty() => {
+                Some(parse_number(bytes.trim_ascii(), "timestamp")?)
+            }
+            _ => None,
+        };
+
+        Ok(TransactionRecord {
+            tx_type,
+            client,
+            tx,
+            amount,
+            memo,
+            timestamp,
+        })
+    }
+}
+
+impl<R: Read> Iterator for TransactionReader<R> {
+    type Item = Result<TransactionRecord, RecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.parse_mode {
+            ParseMode::Serde => self.next_serde(),
+            ParseMode::ByteRecord => self.next_bytes(),
+        }
+    }
+}
+
+impl<R: Read> RecordSource for TransactionReader<R> {
+    fn line(&self) -> Option<u64> {
+        self.line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+    use std::time::Instant;
+
+    fn read_all(input: &str, config: &InputConfig) -> Vec<Result<TransactionRecord, RecordError>> {
+        TransactionReader::new(input.as_bytes(), config)
+            .unwrap()
+            .collect()
+    }
+
+    fn read_ok(input: &str, config: &InputConfig) -> Vec<TransactionRecord> {
+        read_all(input, config)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn test_headerless_matches_headered() {
+        let headered = "type,client,tx,amount\n\
+                        deposit,1,1,10.5\n\
+                        withdrawal,1,2,3\n\
+                        dispute,1,1,\n";
+        let expected = read_ok(headered, &InputConfig::default());
+        assert_eq!(expected.len(), 3);
+
+        let reordered = "1,deposit,10.5,1\n\
+                         2,withdrawal,3,1\n\
+                         1,dispute,,1\n";
+        let config = InputConfig {
+            has_headers: false,
+            columns: parse_columns("tx,type,amount,client").unwrap(),
+            ..InputConfig::default()
+        };
+        assert_eq!(read_ok(reordered, &config), expected);
+    }
+
+    #[test]
+    fn test_header_mismatch_fails_up_front() {
+        let input = "type,client,tx\ndeposit,1,1\n";
+        let error = TransactionReader::new(input.as_bytes(), &InputConfig::default())
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("missing columns: amount"), "{error}");
+
+        let input = "client,type,amount,tx,note\n1,deposit,1.0,1,x\n";
+        let error = TransactionReader::new(input.as_bytes(), &InputConfig::default())
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("unexpected columns: \"note\""), "{error}");
+        assert!(!error.contains("missing"), "{error}");
+
+        // Column order doesn't matter, and memo columns are optional
+        let input = "client,type,amount,tx\n1,deposit,1.0,1\n";
+        assert_eq!(read_ok(input, &InputConfig::default()).len(), 1);
+        let input = "type,client,tx,amount,description\ndeposit,1,1,1.0,hello\n";
+        let records = read_ok(input, &InputConfig::default());
+        assert_eq!(records[0].memo.as_deref(), Some("hello"));
+    }
+
+    #[test]
+    fn test_lenient_amounts_with_semicolons() {
+        let input = "type;client;tx;amount\n\
+                     deposit;1;1;1,000.50\n\
+                     deposit;1;2;12,345,678\n\
+                     deposit;1;3;1,00.5\n";
+        let strict = InputConfig {
+            delimiter: b';',
+            ..InputConfig::default()
+        };
+        let lenient = InputConfig {
+            grouping_separator: Some(','),
+            ..strict.clone()
+        };
+
+        let expected = vec![
+            Some(Decimal::from_str("1000.50").unwrap()),
+            Some(Decimal::from(12_345_678)),
+        ];
+        for parse_mode in [ParseMode::Serde, ParseMode::ByteRecord] {
+            let config = InputConfig {
+                parse_mode,
+                ..lenient.clone()
+            };
+            let results = read_all(input, &config);
+            let amounts: Vec<_> = results
+                .iter()
+                .filter_map(|r| r.as_ref().ok().map(|r| r.amount))
+                .collect();
+            assert_eq!(amounts, expected);
+            // Badly grouped amounts are still errors
+            assert!(results[2].is_err());
+
+            let config = InputConfig {
+                parse_mode,
+                ..strict.clone()
+            };
+            assert!(read_all(input, &config).iter().all(Result::is_err));
+        }
+    }
+
+    #[test]
+    fn test_us_and_eu_amount_locales() {
+        let us = "type,client,tx,amount\n\
+                  deposit,1,1,10.50\n\
+                  deposit,1,2,1234.5\n\
+                  withdrawal,1,3,0.0001\n\
+                  deposit,1,4,7\n";
+        let eu = "type;client;tx;amount\n\
+                  deposit;1;1;10,50\n\
+                  deposit;1;2;1.234,5\n\
+                  withdrawal;1;3;0,0001\n\
+                  deposit;1;4;7\n";
+        let eu_config = InputConfig {
+            delimiter: b';',
+            amount_format: parse_amount_locale("eu").unwrap(),
+            ..InputConfig::default()
+        };
+
+        for parse_mode in [ParseMode::Serde, ParseMode::ByteRecord] {
+            let us_config = InputConfig {
+                parse_mode,
+                ..InputConfig::default()
+            };
+            let eu_config = InputConfig {
+                parse_mode,
+                ..eu_config.clone()
+            };
+            let amounts = |input, config| -> Vec<_> {
+                read_ok(input, config).iter().map(|r| r.amount).collect()
+            };
+
+            let expected = ["10.50", "1234.5", "0.0001", "7"]
+                .map(|amount| Some(Decimal::from_str(amount).unwrap()));
+            assert_eq!(amounts(us, &us_config), expected);
+            assert_eq!(amounts(eu, &eu_config), expected);
+
+            // A dot is only ever grouping in EU mode, never a decimal point
+            let ambiguous = "type;client;tx;amount\ndeposit;1;1;10.50\n";
+            assert!(read_all(ambiguous, &eu_config)[0].is_err());
+        }
+    }
+
+    #[test]
+    fn test_mixed_case_types() {
+        let input = "type,client,tx,amount\n\
+                     Deposit,1,1,10.0\n\
+                     WITHDRAWAL,1,2,1.0\n\
+                     dispute,1,1,\n\
+                     ChargeBack,1,1,\n";
+
+        for parse_mode in [ParseMode::Serde, ParseMode::ByteRecord] {
+            let config = InputConfig {
+                parse_mode,
+                ..InputConfig::default()
+            };
+            let types: Vec<_> = read_ok(input, &config)
+                .into_iter()
+                .map(|record| record.tx_type)
+                .collect();
+            assert_eq!(
+                types,
+                [
+                    TransactionType::Deposit,
+                    TransactionType::Withdrawal,
+                    TransactionType::Dispute,
+                    TransactionType::Chargeback,
+                ]
+            );
+        }
+
+        // Without trimming the padding has to be ignored by the type parsing itself
+        let config = InputConfig {
+            trim: csv::Trim::None,
+            ..InputConfig::default()
+        };
+        let records = read_ok("type,client,tx,amount\n Deposit ,1,1,10.0\n", &config);
+        assert_eq!(records[0].tx_type, TransactionType::Deposit);
+    }
+
+    #[test]
+    fn test_unicode_types() {
+        let input = "type,client,tx,amount\n\
+                     \u{a0}deposit\u{a0},1,1,10.0\n\
+                     \u{feff}Withdrawal\u{200b},1,2,1.0\n\
+                     with\u{a0}drawal,1,3,1.0\n\
+                     \u{a0}Dépôt,1,4,1.0\n\
+                     \u{a0}\u{200b},1,5,1.0\n";
+
+        for parse_mode in [ParseMode::Serde, ParseMode::ByteRecord] {
+            let config = InputConfig {
+                parse_mode,
+                ..InputConfig::default()
+            };
+            let types: Vec<_> = read_all(input, &config)
+                .into_iter()
+                .map(|result| result.map(|record| record.tx_type).ok())
+                .collect();
+            assert_eq!(
+                types,
+                [
+                    Some(TransactionType::Deposit),
+                    Some(TransactionType::Withdrawal),
+                    // Only padding is stripped, these are unknown types for the engine to reject
+                    Some(TransactionType::Custom("with\u{a0}drawal".to_string())),
+                    Some(TransactionType::Custom("Dépôt".to_string())),
+                    None,
+                ],
+                "{parse_mode:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_timestamp_column() {
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,1.0,1700000000\n\
+                     deposit,1,2,1.0,\n\
+                     deposit,1,3,1.0,yesterday\n";
+
+        for parse_mode in [ParseMode::Serde, ParseMode::ByteRecord] {
+            let config = InputConfig {
+                parse_mode,
+                ..InputConfig::default()
+            };
+            let records: Vec<_> = TransactionReader::new(input.as_bytes(), &config)
+                .unwrap()
+                .collect();
+
+            assert_eq!(records[0].as_ref().unwrap().timestamp, Some(1_700_000_000));
+            assert_eq!(records[1].as_ref().unwrap().timestamp, None);
+            assert!(records[2].is_err());
+        }
+    }
+
+    #[test]
+    fn test_trim_none_keeps_memo_whitespace() {
+        let input = "type,client,tx,amount,memo\n\
+                     deposit,1,1,1.0,  padded  \n";
+
+        for parse_mode in [ParseMode::Serde, ParseMode::ByteRecord] {
+            let config = InputConfig {
+                parse_mode,
+                ..InputConfig::default()
+            };
+            let records = read_ok(input, &config);
+            assert_eq!(records[0].memo.as_deref(), Some("padded"));
+
+            let config = InputConfig {
+                trim: parse_trim("none").unwrap(),
+                ..config
+            };
+            let records = read_ok(input, &config);
+            assert_eq!(records[0].memo.as_deref(), Some("  padded  "));
+        }
+
+        assert!(parse_trim("both").is_err());
+    }
+
+    #[test]
+    fn test_oversized_lines_skipped() {
+        let long_memo = "x".repeat(1_000_000);
+        let input = format!(
+            "type,client,tx,amount,memo\n\
+             deposit,1,1,1.0,short\n\
+             deposit,1,2,2.0,{long_memo}\n\
+             deposit,1,3,3.0,\n"
+        );
+
+        let config = InputConfig {
+            max_record_size: Some(64),
+            ..InputConfig::default()
+        };
+        let mut reader = TransactionReader::new(input.as_bytes(), &config).unwrap();
+        let txs: Vec<u32> = reader.by_ref().map(|r| r.unwrap().tx.into()).collect();
+        assert_eq!(txs, [1, 3]);
+        assert_eq!(reader.oversized(), 1);
+
+        // The limit is exclusive of the line ending, and off by default
+        let exact = "type,client,tx,amount,memo\ndeposit,1,1,1.0,abcdefghij\n";
+        let config = InputConfig {
+            max_record_size: Some("deposit,1,1,1.0,abcdefghij".len()),
+            ..InputConfig::default()
+        };
+        assert_eq!(read_ok(exact, &config).len(), 1);
+        assert_eq!(read_ok(&input, &InputConfig::default()).len(), 3);
+
+        // Dropping an oversized header would make the first record the header
+        let config = InputConfig {
+            max_record_size: Some(16),
+            ..InputConfig::default()
+        };
+        let error = TransactionReader::new(exact.as_bytes(), &config)
+            .err()
+            .unwrap();
+        assert!(format!("{error:#}").contains("header row is longer than 16 bytes"));
+
+        // Without a header row, the first line is a record like any other
+        let config = InputConfig {
+            has_headers: false,
+            max_record_size: Some(16),
+            ..InputConfig::default()
+        };
+        let headerless = "deposit,1,1,1.0,abcdefghij\ndeposit,1,2,2.0\n";
+        let mut reader = TransactionReader::new(headerless.as_bytes(), &config).unwrap();
+        let txs: Vec<u32> = reader.by_ref().map(|r| r.unwrap().tx.into()).collect();
+        assert_eq!(txs, [2]);
+        assert_eq!(reader.oversized(), 1);
+    }
+
+    #[test]
+    fn test_column_spec_requires_core_columns() {
+        assert!(parse_columns("type, client ,tx").is_ok());
+        assert!(parse_columns("type,client,amount").is_err());
+    }
+
+    #[test]
+    fn test_column_spec_rejects_unknown_and_repeated_columns() {
+        assert!(parse_columns("type,client,tx,amount,memo").is_ok());
+
+        let error = parse_columns("type,client,tx,amonut").unwrap_err();
+        assert!(
+            error.to_string().contains("unknown column \"amonut\""),
+            "{error}"
+        );
+
+        let error = parse_columns("type,client,tx,client").unwrap_err();
+        assert!(
+            error.to_string().contains("\"client\" column twice"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn test_byte_record_parity_with_serde() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 1.0\n\
+                     withdrawal,2,2,  0.123456789012345678\n\
+                     dispute,1,1,\n\
+                     resolve , 1 , 1 , \n\
+                     chargeback,1,1,\n\
+                     deposit,65535,4294967295,1000\n\
+                     Deposit,1,3,1.0\n\
+                     transfer,1,3,1.0\n\
+                     deposit,-1,3,1.0\n\
+                     deposit,65536,3,1.0\n\
+                     deposit,1,x,1.0\n\
+                     deposit,1,3,12.x\n\
+                     deposit,1,3,1.12345678901234567890123456789012345\n\
+                     deposit,1,3\n\
+                     deposit,,3,1.0\n";
+
+        let serde = read_all(input, &InputConfig::default());
+        let bytes = read_all(
+            input,
+            &InputConfig {
+                parse_mode: ParseMode::ByteRecord,
+                ..InputConfig::default()
+            },
+        );
+
+        assert_eq!(serde.len(), bytes.len());
+        for (i, (a, b)) in serde.iter().zip(&bytes).enumerate() {
+            match (a, b) {
+                (Ok(a), Ok(b)) => assert_eq!(a, b, "row {i}"),
+                (Err(_), Err(_)) => {}
+                _ => panic!("row {i} differs: {a:?} vs {b:?}"),
+            }
+        }
+        assert_eq!(serde.iter().filter(|r| r.is_ok()).count(), 8);
+    }
+
+    /// Rough throughput comparison of the two parse paths:
+    /// `cargo test --release bench_parse_modes -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_parse_modes() {
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 0..1_000_000 {
+            input.push_str(&format!(
+                "deposit,{},{tx},{}.{:04}\n",
+                tx % 1000,
+                tx % 97,
+                tx % 10_000
+            ));
+        }
+
+        for parse_mode in [ParseMode::Serde, ParseMode::ByteRecord] {
+            let config = InputConfig {
+                parse_mode,
+                ..InputConfig::default()
+            };
+            let start = Instant::now();
+            let count = TransactionReader::new(input.as_bytes(), &config)
+                .unwrap()
+                .filter(Result::is_ok)
+                .count();
+            println!("{parse_mode:?}: {count} records in {:?}", start.elapsed());
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use crate::summary::ProcessSummary;
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::net::{Shutdown, TcpStream};
+
+/// Sends the summary as a single line of JSON to `target`, for an orchestrator collecting
+/// results without reading files. A `tcp:` prefix means a TCP address such as
+/// `tcp:127.0.0.1:9000`, anything else is the path of a unix domain socket.
+pub fn send_summary(summary: &ProcessSummary, target: &str) -> Result<()> {
+    let mut report = serde_json::to_vec(summary).context("Failed to serialize summary")?;
+    report.push(b'\n');
+
+    match target.strip_prefix("tcp:") {
+        Some(address) => {
+            let mut stream = TcpStream::connect(address)
+                .with_context(|| format!("Failed to connect to {address}"))?;
+            stream
+                .write_all(&report)
+                .context("Failed to send summary")?;
+            stream
+                .shutdown(Shutdown::Write)
+                .context("Failed to send summary")
+        }
+        #[cfg(unix)]
+        None => {
+            let mut stream = std::os::unix::net::UnixStream::connect(target)
+                .with_context(|| format!("Failed to connect to {target}"))?;
+            stream
+                .write_all(&report)
+                .context("Failed to send summary")?;
+            stream
+                .shutdown(Shutdown::Write)
+                .context("Failed to send summary")
+        }
+        #[cfg(not(unix))]
+        None => anyhow::bail!("Unix domain sockets aren't supported here, use tcp:<ADDRESS>"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn summary() -> ProcessSummary {
+        ProcessSummary {
+            records_read: 3,
+            malformed: 1,
+            ..ProcessSummary::default()
+        }
+    }
+
+    fn read_report(stream: impl std::io::Read) -> serde_json::Value {
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[test]
+    fn test_send_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = format!("tcp:{}", listener.local_addr().unwrap());
+        let sender = thread::spawn(move || send_summary(&summary(), &target));
+
+        let (stream, _) = listener.accept().unwrap();
+        let report = read_report(stream);
+        sender.join().unwrap().unwrap();
+        assert_eq!(report["records_read"], 3);
+        assert_eq!(report["malformed"], 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_send_over_unix_socket() {
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("report-{}.sock", std::process::id()));
+        let listener = UnixListener::bind(&path).unwrap();
+        let target = path.to_str().unwrap().to_string();
+        let sender = thread::spawn(move || send_summary(&summary(), &target));
+
+        let (stream, _) = listener.accept().unwrap();
+        let report = read_report(stream);
+        sender.join().unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(report["records_read"], 3);
+
+        assert!(send_summary(&summary(), path.to_str().unwrap()).is_err());
+    }
+}
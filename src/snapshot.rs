@@ -0,0 +1,115 @@
+use crate::account::Account;
+use crate::transaction::{ClientId, StoredTransaction, TxId};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+/// A point-in-time copy of the engine state: every account and every stored transaction.
+/// Saved as JSON so a later run can pick up where this one left off, e.g. to replay only the
+/// dispute workflow against a known set of deposits and withdrawals.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub accounts: BTreeMap<ClientId, Account>,
+    pub transactions: BTreeMap<TxId, StoredTransaction>,
+}
+
+impl Snapshot {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open snapshot: {}", path.display()))?;
+
+        serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Invalid snapshot: {}", path.display()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create snapshot: {}", path.display()))?;
+
+        serde_json::to_writer(BufWriter::new(file), self).context("Failed to write snapshot")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::LockCause;
+    use crate::transaction::TransactionType;
+    use rust_decimal::Decimal;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut snapshot = Snapshot::default();
+        snapshot.accounts.insert(
+            ClientId(7),
+            Account {
+                available: Decimal::from_str("1.123456789012345678").unwrap(),
+                held: Decimal::from_str("2.5").unwrap(),
+                locked: true,
+                lock_cause: Some(LockCause::Chargeback),
+                chargebacks: 1,
+                had_dispute: true,
+                deposits: 1,
+                disputes: 1,
+                dispute_ratio_breached: true,
+                suspicious_disputes: 2,
+                funded: true,
+                deposited: Decimal::from_str("3.623456789012345678").unwrap(),
+                withdrawn: Decimal::ZERO,
+                overdraft_limit: Decimal::from_str("100").unwrap(),
+                output_scale: Some(2),
+                last_activity: Some(1_700_000_000),
+                memo: Some("flagged, see \"case 12\"".to_string()),
+            },
+        );
+        snapshot.transactions.insert(
+            TxId(42),
+            StoredTransaction {
+                client: ClientId(7),
+                amount: Decimal::from_str("2.5").unwrap(),
+                tx_type: TransactionType::Deposit,
+                disputed: true,
+                held: Decimal::from_str("2.5").unwrap(),
+                sequence: 3,
+                stored_at: 1,
+                timestamp: Some(1_700_000_000),
+                had_dispute: true,
+                resolved: false,
+                finalized: false,
+                charged_back: Decimal::ZERO,
+            },
+        );
+
+        let path = std::env::temp_dir().join(format!("snapshot-{}.json", std::process::id()));
+        snapshot.save(&path).unwrap();
+        let loaded = Snapshot::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, snapshot);
+    }
+
+    #[test]
+    fn test_snapshot_without_held_amounts() {
+        // Saved before per-tx holds were tracked: an open dispute held the whole amount
+        let json = r#"{
+            "accounts": {},
+            "transactions": {
+                "1": {"client": 1, "amount": "10.5", "tx_type": "deposit", "disputed": true},
+                "2": {"client": 1, "amount": "3", "tx_type": "deposit", "disputed": false}
+            }
+        }"#;
+        let snapshot: Snapshot = serde_json::from_str(json).unwrap();
+
+        assert_eq!(
+            snapshot.transactions[&TxId(1)].held,
+            Decimal::from_str("10.5").unwrap()
+        );
+        assert_eq!(snapshot.transactions[&TxId(2)].held, Decimal::ZERO);
+    }
+}
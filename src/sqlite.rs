@@ -0,0 +1,178 @@
+use crate::reader::{RecordError, RecordSource};
+use crate::transaction::{
+    ClientId, PrecisionPolicy, RawRecord, TransactionRecord, TransactionType, TxId,
+    exceeds_max_scale,
+};
+use anyhow::{Context, Result};
+use rusqlite::Connection;
+use rusqlite::types::ValueRef;
+use std::vec;
+
+/// Reads transactions from a SQLite table with the same columns as the CSV input
+/// (`type`, `client`, `tx`, `amount`), in tx id order. Rows sharing a tx id, such as a deposit
+/// and its dispute, keep their order in the table.
+///
+/// The rows are fetched up front, since a prepared statement borrows its connection.
+/// Amounts are best stored as TEXT; REAL columns work but carry the usual float rounding.
+pub struct SqliteReader {
+    rows: vec::IntoIter<Result<RawRecord, RecordError>>,
+    precision: PrecisionPolicy,
+    precision_overflows: u64,
+}
+
+impl SqliteReader {
+    pub fn open(path: &str, table: &str, precision: PrecisionPolicy) -> Result<Self> {
+        let connection =
+            Connection::open(path).with_context(|| format!("Failed to open database {path}"))?;
+        Self::from_connection(&connection, table, precision)
+    }
+
+    pub fn from_connection(
+        connection: &Connection,
+        table: &str,
+        precision: PrecisionPolicy,
+    ) -> Result<Self> {
+        // Table names can't be bound as parameters, so only accept plain identifiers
+        if table.is_empty() || !table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            anyhow::bail!("Invalid table name: {table}");
+        }
+
+        let mut statement = connection
+            .prepare(&format!(
+                "SELECT type, client, tx, amount FROM \"{table}\" ORDER BY tx, rowid"
+            ))
+            .with_context(|| format!("Failed to query table {table}"))?;
+
+        let rows = statement
+            .query_map([], |row| Ok(raw_record(row)))?
+            .map(|row| row.map_err(RecordError::from).and_then(|raw| raw))
+            .collect::<Vec<_>>();
+
+        Ok(Self {
+            rows: rows.into_iter(),
+            precision,
+            precision_overflows: 0,
+        })
+    }
+
+    /// Amounts seen so far with more decimal places than `Decimal` supports,
+    /// whether they were rounded or rejected.
+    pub fn precision_overflows(&self) -> u64 {
+        self.precision_overflows
+    }
+}
+
+fn raw_record(row: &rusqlite::Row) -> Result<RawRecord, RecordError> {
+    let tx_type = match row.get_ref(0)? {
+        ValueRef::Text(bytes) if !bytes.is_empty() => {
+            let name = std::str::from_utf8(bytes).map_err(|_| RecordError::Field("type"))?;
+            TransactionType::from_name(name.trim().to_string())
+        }
+        _ => return Err(RecordError::Field("type")),
+    };
+
+    let client = row
+        .get::<_, u16>(1)
+        .map(ClientId)
+        .map_err(|_| RecordError::Field("client"))?;
+    let tx = row
+        .get::<_, u32>(2)
+        .map(TxId)
+        .map_err(|_| RecordError::Field("tx"))?;
+
+    let amount = match row.get_ref(3)? {
+        ValueRef::Null => None,
+        ValueRef::Integer(value) => Some(value.to_string()),
+        ValueRef::Real(value) => Some(value.to_string()),
+        ValueRef::Text(bytes) => {
+            let text = std::str::from_utf8(bytes).map_err(|_| RecordError::Field("amount"))?;
+            Some(text.trim().to_string()).filter(|text| !text.is_empty())
+        }
+        ValueRef::Blob(_) => return Err(RecordError::Field("amount")),
+    };
+
+    Ok(RawRecord {
+        tx_type,
+        client,
+        tx,
+        amount,
+        memo: None,
+        timestamp: None,
+    })
+}
+
+impl Iterator for SqliteReader {
+    type Item = Result<TransactionRecord, RecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let raw = match self.rows.next()? {
+            Ok(raw) => raw,
+            Err(e) => return Some(Err(e)),
+        };
+
+        if raw.amount.as_deref().is_some_and(exceeds_max_scale) {
+            self.precision_overflows += 1;
+        }
+
+        Some(raw.into_record(self.precision).map_err(Into::into))
+    }
+}
+
+impl RecordSource for SqliteReader {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+    use std::str::FromStr;
+
+    fn database() -> Connection {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE transactions (type TEXT, client INTEGER, tx INTEGER, amount TEXT);
+                 INSERT INTO transactions VALUES ('withdrawal', 1, 3, '1.5');
+                 INSERT INTO transactions VALUES ('deposit', 1, 1, '10.0');
+                 INSERT INTO transactions VALUES ('dispute', 1, 1, NULL);
+                 INSERT INTO transactions VALUES ('deposit', 70000, 4, '1.0');
+                 INSERT INTO transactions VALUES ('deposit', 2, 2, '2.0001');
+                 INSERT INTO transactions VALUES ('dispute', 3, 5, NULL);
+                 INSERT INTO transactions VALUES ('deposit', 3, 5, '4.0');",
+            )
+            .unwrap();
+        connection
+    }
+
+    #[test]
+    fn test_reads_rows_in_tx_order() {
+        let records: Vec<_> =
+            SqliteReader::from_connection(&database(), "transactions", PrecisionPolicy::Reject)
+                .unwrap()
+                .collect();
+
+        let ok: Vec<_> = records.iter().filter_map(|r| r.as_ref().ok()).collect();
+        let txs: Vec<u32> = ok.iter().map(|r| r.tx.into()).collect();
+        assert_eq!(txs, [1, 1, 2, 3, 5, 5]);
+
+        assert_eq!(ok[0].amount, Some(Decimal::from_str("10.0").unwrap()));
+        assert_eq!(ok[1].tx_type, TransactionType::Dispute);
+        assert_eq!(ok[1].amount, None);
+        assert_eq!(ok[2].amount, Some(Decimal::from_str("2.0001").unwrap()));
+        // Same tx id, so the table's order stands even with the dispute ahead of its deposit
+        assert_eq!(ok[4].tx_type, TransactionType::Dispute);
+        assert_eq!(ok[5].tx_type, TransactionType::Deposit);
+
+        // Client 70000 doesn't fit a u16
+        assert!(matches!(records[4], Err(RecordError::Field("client"))));
+    }
+
+    #[test]
+    fn test_rejects_unsafe_table_names() {
+        let result = SqliteReader::from_connection(
+            &database(),
+            "transactions; DROP TABLE transactions",
+            PrecisionPolicy::Reject,
+        );
+        assert!(result.is_err());
+    }
+}
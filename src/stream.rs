@@ -0,0 +1,95 @@
+use crate::account::AccountOutput;
+use crate::engine::PaymentEngine;
+use futures_core::Stream;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Rows handed out before yielding back to the executor once, so streaming a huge account
+/// table doesn't starve the other tasks on the same thread.
+const ROWS_PER_POLL: usize = 1024;
+
+/// The output rows as a `Stream`, for async services sending account state out as it's
+/// produced, e.g. into an HTTP response, without collecting a `Vec` first.
+pub struct AccountStream<I> {
+    accounts: I,
+    since_yield: usize,
+}
+
+impl<I: Iterator<Item = AccountOutput> + Unpin> Stream for AccountStream<I> {
+    type Item = AccountOutput;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<AccountOutput>> {
+        if self.since_yield == ROWS_PER_POLL {
+            self.since_yield = 0;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+
+        self.since_yield += 1;
+        Poll::Ready(self.accounts.next())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.accounts.size_hint()
+    }
+}
+
+impl PaymentEngine {
+    /// Like `accounts`, as a `Stream`. Requires the `async` feature.
+    pub fn account_stream(&self) -> AccountStream<impl Iterator<Item = AccountOutput> + '_> {
+        AccountStream {
+            accounts: self.accounts(),
+            since_yield: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{ClientId, TransactionRecord, TransactionType};
+    use rust_decimal::Decimal;
+    use std::task::Waker;
+
+    /// Drives the stream to the end, counting how often it yielded.
+    fn collect(
+        mut stream: impl Stream<Item = AccountOutput> + Unpin,
+    ) -> (Vec<AccountOutput>, usize) {
+        let mut cx = Context::from_waker(Waker::noop());
+        let (mut rows, mut yields) = (Vec::new(), 0);
+        loop {
+            match Pin::new(&mut stream).poll_next(&mut cx) {
+                Poll::Ready(Some(row)) => rows.push(row),
+                Poll::Ready(None) => return (rows, yields),
+                Poll::Pending => yields += 1,
+            }
+        }
+    }
+
+    #[test]
+    fn test_stream_matches_get_accounts() {
+        let mut engine = PaymentEngine::new();
+        for client in 0..3000u16 {
+            engine
+                .process_transaction(TransactionRecord {
+                    tx_type: TransactionType::Deposit,
+                    client: ClientId(client),
+                    tx: u32::from(client).into(),
+                    amount: Some(Decimal::from(client)),
+                    memo: None,
+                    timestamp: None,
+                })
+                .unwrap();
+        }
+
+        let (rows, yields) = collect(engine.account_stream());
+        let key =
+            |row: &AccountOutput| (row.client, row.available, row.held, row.total, row.locked);
+        assert_eq!(
+            rows.iter().map(key).collect::<Vec<_>>(),
+            engine.get_accounts().iter().map(key).collect::<Vec<_>>()
+        );
+        assert_eq!(rows.len(), 3000);
+        assert_eq!(yields, 2);
+    }
+}
@@ -0,0 +1,154 @@
+use anyhow::{Context, Result};
+use rust_decimal::Decimal;
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Counters collected over a processing run, for reporting alongside the account output.
+/// The trailing block describes the end state and is filled in by `PaymentEngine::summary`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ProcessSummary {
+    pub records_read: u64,
+    /// Records the engine applied, including no-op disputes/resolves/chargebacks.
+    pub records_processed: u64,
+    /// Records the engine refused with an error.
+    pub records_rejected: u64,
+    /// Rows that could not be parsed into a transaction record at all.
+    pub malformed: u64,
+    /// Input lines skipped unread for exceeding `--max-record-size`.
+    pub oversized: u64,
+    /// Amounts with more decimal places than `Decimal` supports, whether rounded or dropped.
+    pub precision_overflows: u64,
+    /// Amounts beyond the plausible range, see `transaction::MAX_AMOUNT`. Not counted as
+    /// malformed, since the row itself parsed fine.
+    pub out_of_range_amounts: u64,
+    /// Records skipped because the ingestion mode doesn't apply their type.
+    pub filtered: u64,
+    /// Records skipped by `--dedup` for repeating the record right before them.
+    pub deduped: u64,
+    /// Non-deposit records for unknown clients, rejected under the deposit-first policy.
+    pub unknown_client_rejections: u64,
+    /// Records for new clients rejected because the account limit was reached.
+    pub account_limit_rejections: u64,
+    /// Disputes rejected because they would exceed the held funds cap.
+    pub held_cap_rejections: u64,
+    /// Disputes whose amount disagreed with the disputed transaction, when cross-checking.
+    pub dispute_amount_mismatches: u64,
+    /// Disputes rejected for naming another client's transaction.
+    pub cross_client_disputes: u64,
+    /// Disputes rejected for coming after the dispute window closed.
+    pub out_of_window_disputes: u64,
+    /// Accounts whose disputes exceeded the allowed share of their deposits.
+    pub dispute_ratio_breaches: u64,
+    /// Disputes of a transaction already under dispute, e.g. a replayed dispute event.
+    pub duplicate_disputes: u64,
+    /// Resolves of a dispute that was already resolved, usually a replayed resolve event.
+    pub duplicate_resolves: u64,
+    /// Withdrawals refused because the account was locked, usually fraud related.
+    pub withdrawals_failed_locked: u64,
+    /// Withdrawals refused for lack of available funds, usually routine.
+    pub withdrawals_failed_insufficient: u64,
+    /// Withdrawal fees charged over the run.
+    pub fees_collected: Decimal,
+    /// Disputes released at the end of the run for having been open too long.
+    pub disputes_auto_resolved: u64,
+    /// Disputes charged back at the end of the run for having been open too long.
+    pub disputes_auto_charged_back: u64,
+    /// Applied records by transaction type name.
+    pub transaction_types: BTreeMap<String, TypeStats>,
+
+    pub accounts: u64,
+    pub locked_accounts: u64,
+    /// Disputes still open at the end of the run, neither resolved nor charged back.
+    pub open_disputes: u64,
+    /// Accounts whose balances add up to more than a `Decimal` holds. Their totals, and the
+    /// totals here, are capped.
+    pub total_overflows: u64,
+    pub total_available: Decimal,
+    pub total_held: Decimal,
+    pub total: Decimal,
+}
+
+/// How many records of one transaction type were applied, and the money they moved.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TypeStats {
+    pub count: u64,
+    /// Only tracked for deposits and withdrawals. Refused withdrawals and deposits to locked
+    /// accounts count as records but move nothing, and withdrawal fees aren't included.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume: Option<Decimal>,
+    /// Only kept for deposits and withdrawals, and only with `EngineConfig::amount_histogram`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub histogram: Option<AmountHistogram>,
+}
+
+/// Upper bounds of the amount histogram's buckets. Each bucket counts the amounts from the
+/// previous bound up to its own, the last one everything from 100000 up.
+pub const HISTOGRAM_BOUNDS: [u32; 6] = [1, 10, 100, 1_000, 10_000, 100_000];
+
+/// Counts amounts into order of magnitude buckets, a fixed handful of counters however long
+/// the input.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AmountHistogram {
+    pub buckets: [u64; HISTOGRAM_BOUNDS.len() + 1],
+}
+
+impl AmountHistogram {
+    pub fn add(&mut self, amount: Decimal) {
+        let bucket = HISTOGRAM_BOUNDS
+            .iter()
+            .position(|&bound| amount < Decimal::from(bound))
+            .unwrap_or(HISTOGRAM_BOUNDS.len());
+        self.buckets[bucket] += 1;
+    }
+}
+
+/// Written as `under_1`, `under_10` and so on up to `100000_and_over`, in bucket order.
+impl Serialize for AmountHistogram {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.buckets.len()))?;
+        for (bound, count) in HISTOGRAM_BOUNDS.iter().zip(&self.buckets) {
+            map.serialize_entry(&format!("under_{bound}"), count)?;
+        }
+        let last = HISTOGRAM_BOUNDS[HISTOGRAM_BOUNDS.len() - 1];
+        map.serialize_entry(
+            &format!("{last}_and_over"),
+            &self.buckets[HISTOGRAM_BOUNDS.len()],
+        )?;
+        map.end()
+    }
+}
+
+impl ProcessSummary {
+    /// Counts an applied record of the named type, with the money it moved if it can move any.
+    pub fn count_type(&mut self, name: &str, moved: Option<Decimal>) {
+        let stats = match self.transaction_types.get_mut(name) {
+            Some(stats) => stats,
+            None => self.transaction_types.entry(name.to_string()).or_default(),
+        };
+        stats.count += 1;
+        if let Some(moved) = moved {
+            *stats.volume.get_or_insert_default() += moved;
+        }
+    }
+
+    /// Adds an amount to the named type's histogram, for a record already counted by
+    /// `count_type`.
+    pub fn bucket_amount(&mut self, name: &str, amount: Decimal) {
+        if let Some(stats) = self.transaction_types.get_mut(name) {
+            stats.histogram.get_or_insert_default().add(amount);
+        }
+    }
+
+    /// Writes every counter and total as a JSON object, for monitoring systems to pick up.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create summary file: {}", path.display()))?;
+
+        serde_json::to_writer_pretty(BufWriter::new(file), self).context("Failed to write summary")
+    }
+}
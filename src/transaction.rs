@@ -1,62 +1,525 @@
 use anyhow::Result;
 use rust_decimal::Decimal;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
+use std::fmt;
+use std::num::ParseIntError;
 use std::str::FromStr;
+use thiserror::Error;
 
-#[derive(Debug, Clone, Deserialize)]
-#[serde(rename_all = "lowercase")]
+/// The maximum number of decimal places `rust_decimal` can represent.
+pub const MAX_SCALE: usize = 28;
+
+/// The largest amount a single transaction may carry. Anything above is far more likely to be
+/// a corrupted or mis-scaled value than a real payment.
+pub const MAX_AMOUNT: Decimal = Decimal::from_parts(0xA764_0000, 0x0DE0_B6B3, 0, false, 0); // 10^18
+
+/// Declares an id newtype that serializes, parses and displays exactly like the wrapped integer,
+/// so the CSV and snapshot formats are unaffected.
+macro_rules! id_type {
+    ($(#[$doc:meta])* $name:ident($inner:ty)) => {
+        $(#[$doc])*
+        #[derive(
+            Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+        )]
+        #[serde(transparent)]
+        pub struct $name(pub $inner);
+
+        impl From<$inner> for $name {
+            fn from(id: $inner) -> Self {
+                Self(id)
+            }
+        }
+
+        impl From<$name> for $inner {
+            fn from(id: $name) -> Self {
+                id.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = ParseIntError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                s.parse().map(Self)
+            }
+        }
+    };
+}
+
+id_type!(
+    /// A client (account) id. Kept distinct from `TxId` so the two can't be swapped by accident.
+    ClientId(u16)
+);
+id_type!(
+    /// A globally unique transaction id.
+    TxId(u32)
+);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TransactionType {
     Deposit,
     Withdrawal,
     Dispute,
     Resolve,
     Chargeback,
+    /// Manual ledger correction, adjusting available by a signed amount.
+    Correction,
+    /// Admin command locking the account without moving any funds.
+    Freeze,
+    /// Admin command lifting a lock placed by `Freeze`. Chargeback locks are left alone.
+    Unfreeze,
+    /// Undoes the chargeback of the referenced tx after review, see
+    /// `Account::reverse_chargeback`.
+    ChargebackReversal,
+    /// Any other type name, applied by a `CustomHandler` registered on the engine under that name.
+    Custom(String),
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct TransactionRecord {
     #[serde(rename = "type")]
     pub tx_type: TransactionType,
-    pub client: u16,
-    pub tx: u32,
+    pub client: ClientId,
+    pub tx: TxId,
+    #[serde(default, deserialize_with = "deserialize_amount")]
     pub amount: Option<Decimal>,
+    /// Free text from an optional `memo` (or `description`) column, passed through to the output.
+    #[serde(default, alias = "description")]
+    pub memo: Option<String>,
+    /// Unix time in seconds from an optional `timestamp` column.
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+}
+
+/// A transaction row as it appears in the input, with the amount still in its raw textual form.
+/// Deserializing the amount straight into a `Decimal` goes through `f64` in the csv crate,
+/// which silently loses precision, so we keep the string and parse it ourselves.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RawRecord {
+    #[serde(rename = "type")]
+    pub tx_type: TransactionType,
+    pub client: ClientId,
+    pub tx: TxId,
+    pub amount: Option<String>,
+    #[serde(default, alias = "description")]
+    pub memo: Option<String>,
+    #[serde(default)]
+    pub timestamp: Option<u64>,
 }
 
-#[derive(Debug, Clone)]
+/// What to do with an amount carrying more decimal places than `Decimal` can hold.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PrecisionPolicy {
+    /// Drop the record.
+    #[default]
+    Reject,
+    /// Round the amount to the maximum supported scale and keep the record.
+    Round,
+}
+
+#[derive(Debug, Error)]
+pub enum AmountError {
+    #[error("amount {0:?} has more than {MAX_SCALE} decimal places")]
+    PrecisionOverflow(String),
+    #[error("invalid amount {0:?}")]
+    Invalid(String),
+    #[error("amount {0:?} is larger than {MAX_AMOUNT} in magnitude")]
+    OutOfRange(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "SavedTransaction")]
 pub struct StoredTransaction {
-    pub client: u16,
+    pub client: ClientId,
     pub amount: Decimal,
     pub tx_type: TransactionType,
     pub disputed: bool,
+    /// How much of this transaction is currently held by its dispute. Usually the full amount,
+    /// but a partial dispute or a refused hold leaves it lower, and resolves/chargebacks must
+    /// only move what this transaction actually contributed to the account's held funds.
+    /// Snapshots from before it was tracked lack it, see `SavedTransaction`.
+    pub held: Decimal,
+    /// Engine sequence number of the record that stored this transaction, re-stamped when a
+    /// dispute is opened against it so the dispute's age can be told.
+    #[serde(default)]
+    pub sequence: u64,
+    /// Engine sequence number of the record that stored this transaction, never re-stamped.
+    /// Zero for transactions from older snapshots, which count as the oldest there are.
+    #[serde(default)]
+    pub stored_at: u64,
+    /// Unix time in seconds of the record that stored this transaction, if it had one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    /// Set the first time the transaction is disputed, and never cleared.
+    #[serde(default)]
+    pub had_dispute: bool,
+    /// Set once a resolve releases the rest of the dispute, cleared when it's disputed again.
+    #[serde(default)]
+    pub resolved: bool,
+    /// Set once a chargeback settles (part of) the dispute, cleared if it's reversed.
+    #[serde(default)]
+    pub finalized: bool,
+    /// How much the chargebacks took, for reversing them.
+    #[serde(default)]
+    pub charged_back: Decimal,
+}
+
+/// One disputed transaction in the dispute audit trail, see `PaymentEngine::disputes_ledger`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DisputeEntry {
+    pub tx: TxId,
+    pub client: ClientId,
+    pub amount: Decimal,
+    /// Still (partly) under dispute.
+    pub disputed: bool,
+    /// Charged back.
+    pub finalized: bool,
+}
+
+/// A `StoredTransaction` as saved in a snapshot, which may predate some of its fields.
+#[derive(Deserialize)]
+struct SavedTransaction {
+    client: ClientId,
+    amount: Decimal,
+    tx_type: TransactionType,
+    disputed: bool,
+    held: Option<Decimal>,
+    #[serde(default)]
+    sequence: u64,
+    #[serde(default)]
+    stored_at: u64,
+    #[serde(default)]
+    timestamp: Option<u64>,
+    #[serde(default)]
+    had_dispute: bool,
+    #[serde(default)]
+    resolved: bool,
+    #[serde(default)]
+    finalized: bool,
+    #[serde(default)]
+    charged_back: Decimal,
+}
+
+impl From<SavedTransaction> for StoredTransaction {
+    fn from(saved: SavedTransaction) -> Self {
+        // Before per-tx holds were tracked, a dispute always held the whole amount
+        let held = saved.held.unwrap_or(if saved.disputed {
+            saved.amount
+        } else {
+            Decimal::ZERO
+        });
+        Self {
+            client: saved.client,
+            amount: saved.amount,
+            tx_type: saved.tx_type,
+            disputed: saved.disputed,
+            held,
+            sequence: saved.sequence,
+            stored_at: saved.stored_at,
+            timestamp: saved.timestamp,
+            had_dispute: saved.had_dispute,
+            resolved: saved.resolved,
+            finalized: saved.finalized,
+            charged_back: saved.charged_back,
+        }
+    }
+}
+
+impl StoredTransaction {
+    pub fn new(client: ClientId, amount: Decimal, tx_type: TransactionType) -> Self {
+        Self {
+            client,
+            amount,
+            tx_type,
+            disputed: false,
+            held: Decimal::ZERO,
+            sequence: 0,
+            stored_at: 0,
+            timestamp: None,
+            had_dispute: false,
+            resolved: false,
+            finalized: false,
+            charged_back: Decimal::ZERO,
+        }
+    }
+
+    /// Stamps the engine sequence number the transaction was stored at.
+    pub fn at(self, sequence: u64) -> Self {
+        Self {
+            sequence,
+            stored_at: sequence,
+            ..self
+        }
+    }
+
+    /// Stamps the timestamp of the record the transaction was stored by.
+    pub fn with_timestamp(self, timestamp: Option<u64>) -> Self {
+        Self { timestamp, ..self }
+    }
+}
+
+impl TransactionType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Correction => "correction",
+            TransactionType::Freeze => "freeze",
+            TransactionType::Unfreeze => "unfreeze",
+            TransactionType::ChargebackReversal => "chargeback_reversal",
+            TransactionType::Custom(name) => name,
+        }
+    }
+
+    /// Matches the built-in names ignoring case and surrounding whitespace, so `Deposit` and
+    /// `DEPOSIT` are deposits. Some partner feeds say `credit` and `debit` for deposits and
+    /// withdrawals, which are accepted on the way in but never written out. Whitespace here
+    /// means any Unicode whitespace, such as the non-breaking spaces spreadsheets like to leave
+    /// behind, plus zero width spaces and byte order marks. Anything else, non-ASCII names
+    /// included, is taken as a custom type with the padding stripped, which the engine rejects
+    /// unless a handler is registered for it.
+    pub fn from_name(name: String) -> Self {
+        let trimmed = name.trim_matches(is_name_padding);
+        match trimmed.to_lowercase().as_str() {
+            "deposit" | "credit" => TransactionType::Deposit,
+            "withdrawal" | "debit" => TransactionType::Withdrawal,
+            "dispute" => TransactionType::Dispute,
+            "resolve" => TransactionType::Resolve,
+            "chargeback" => TransactionType::Chargeback,
+            "correction" => TransactionType::Correction,
+            "freeze" => TransactionType::Freeze,
+            "unfreeze" => TransactionType::Unfreeze,
+            "chargeback_reversal" => TransactionType::ChargebackReversal,
+            _ if trimmed.len() == name.len() => TransactionType::Custom(name),
+            _ => TransactionType::Custom(trimmed.to_string()),
+        }
+    }
+}
+
+fn is_name_padding(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '\u{200B}' | '\u{FEFF}')
+}
+
+impl Serialize for TransactionType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for TransactionType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match Self::from_name(String::deserialize(deserializer)?) {
+            TransactionType::Custom(name) if name.is_empty() => {
+                Err(serde::de::Error::custom("missing transaction type"))
+            }
+            tx_type => Ok(tx_type),
+        }
+    }
 }
 
 impl FromStr for TransactionType {
     type Err = anyhow::Error;
 
+    /// Only the built-in types, see `from_name`.
     fn from_str(s: &str) -> Result<Self> {
-        match s.trim().to_lowercase().as_str() {
-            "deposit" => Ok(TransactionType::Deposit),
-            "withdrawal" => Ok(TransactionType::Withdrawal),
-            "dispute" => Ok(TransactionType::Dispute),
-            "resolve" => Ok(TransactionType::Resolve),
-            "chargeback" => Ok(TransactionType::Chargeback),
-            _ => Err(anyhow::anyhow!("Unknown transaction type: {}", s)),
+        match Self::from_name(s.to_string()) {
+            TransactionType::Custom(_) => Err(anyhow::anyhow!("Unknown transaction type: {}", s)),
+            tx_type => Ok(tx_type),
+        }
+    }
+}
+
+/// Returns true if the fractional part of `raw` has more digits than `Decimal` can represent.
+pub fn exceeds_max_scale(raw: &str) -> bool {
+    raw.split_once('.')
+        .map(|(_, fraction)| fraction.chars().take_while(char::is_ascii_digit).count() > MAX_SCALE)
+        .unwrap_or(false)
+}
+
+/// Parses an amount in plain (`1000.5`) or scientific (`1.0005e3`, `2E-1`) notation.
+/// A scientific amount whose exponent would push it beyond the representable scale is rejected
+/// outright rather than rounded, since the input is clearly not a sane monetary amount.
+pub fn parse_amount(raw: &str, policy: PrecisionPolicy) -> Result<Decimal, AmountError> {
+    let amount = if raw.contains(['e', 'E']) {
+        Decimal::from_scientific(raw).map_err(|_| AmountError::Invalid(raw.to_string()))?
+    } else {
+        if exceeds_max_scale(raw) && policy == PrecisionPolicy::Reject {
+            return Err(AmountError::PrecisionOverflow(raw.to_string()));
         }
+
+        // `from_str` rounds anything beyond the representable scale for us
+        Decimal::from_str(raw).map_err(|_| AmountError::Invalid(raw.to_string()))?
+    };
+
+    check_amount(amount, raw)
+}
+
+/// Removes digit grouping such as the commas in `1,000,000.50`. Only well formed grouping is
+/// stripped (groups of exactly three digits in the integer part), anything else is returned
+/// unchanged so it still fails to parse instead of silently turning into a different number.
+pub fn strip_grouping(raw: &str, separator: char) -> Cow<'_, str> {
+    if !raw.contains(separator) {
+        return Cow::Borrowed(raw);
+    }
+
+    let unsigned = raw.trim_start_matches(['-', '+']);
+    let integer = unsigned.split('.').next().unwrap_or_default();
+    let mut groups = integer.split(separator);
+    let first = groups.next().unwrap_or_default();
+
+    let well_formed = (1..=3).contains(&first.len())
+        && first.bytes().all(|b| b.is_ascii_digit())
+        && groups.all(|group| group.len() == 3 && group.bytes().all(|b| b.is_ascii_digit()))
+        && !unsigned[integer.len()..].contains(separator);
+
+    if well_formed {
+        Cow::Owned(raw.replace(separator, ""))
+    } else {
+        Cow::Borrowed(raw)
+    }
+}
+
+/// Rewrites amounts from the input's notation into the plain `1234.5` form `parse_amount`
+/// accepts, e.g. for locales writing the decimal separator as a comma. Meant for callers to
+/// plug in their own notation; the reader applies it before any other amount handling.
+pub trait AmountFormat: fmt::Debug + Send + Sync {
+    fn normalize<'a>(&self, raw: &'a str) -> Result<Cow<'a, str>, AmountError>;
+}
+
+/// The default: amounts must already be in plain notation.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StandardAmounts;
+
+impl AmountFormat for StandardAmounts {
+    fn normalize<'a>(&self, raw: &'a str) -> Result<Cow<'a, str>, AmountError> {
+        Ok(Cow::Borrowed(raw))
+    }
+}
+
+/// European notation: a comma separates the decimals and dots optionally group thousands, as
+/// in `1.234,50`. A dot anywhere but between well formed groups is an error rather than read
+/// as a decimal point, so `10.50` can't silently pass for ten and a half.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EuropeanAmounts;
+
+impl AmountFormat for EuropeanAmounts {
+    fn normalize<'a>(&self, raw: &'a str) -> Result<Cow<'a, str>, AmountError> {
+        if !raw.contains(['.', ',']) {
+            return Ok(Cow::Borrowed(raw));
+        }
+
+        let invalid = || AmountError::Invalid(raw.to_string());
+        let (integer, fraction) = match raw.split_once(',') {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (raw, None),
+        };
+        if fraction.is_some_and(|fraction| fraction.contains(['.', ','])) {
+            return Err(invalid());
+        }
+
+        let unsigned = integer.trim_start_matches(['-', '+']);
+        if unsigned.contains('.') {
+            let mut groups = unsigned.split('.');
+            let first = groups.next().unwrap_or_default();
+            if !(1..=3).contains(&first.len()) || !groups.all(|group| group.len() == 3) {
+                return Err(invalid());
+            }
+        }
+
+        let mut normalized = integer.replace('.', "");
+        if let Some(fraction) = fraction {
+            normalized.push('.');
+            normalized.push_str(fraction);
+        }
+        Ok(Cow::Owned(normalized))
+    }
+}
+
+/// The one place amounts are sanity checked, whatever their textual form. `Decimal` has no NaN
+/// or infinity, so what's left to catch is implausibly large values. The sign depends on the
+/// transaction type and is checked by `TransactionRecord::validate`.
+pub fn check_amount(amount: Decimal, raw: &str) -> Result<Decimal, AmountError> {
+    if amount.abs() > MAX_AMOUNT {
+        return Err(AmountError::OutOfRange(raw.to_string()));
+    }
+    Ok(amount)
+}
+
+/// Deserializes an optional amount from its text through `parse_amount`, rather than the
+/// `Decimal` default which goes through `f64` in the csv crate.
+fn deserialize_amount<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Decimal>, D::Error> {
+    let raw: Option<String> = Option::deserialize(deserializer)?;
+
+    raw.filter(|raw| !raw.is_empty())
+        .map(|raw| parse_amount(&raw, PrecisionPolicy::default()))
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+impl RawRecord {
+    pub fn into_record(self, policy: PrecisionPolicy) -> Result<TransactionRecord, AmountError> {
+        let amount = self
+            .amount
+            .map(|raw| parse_amount(&raw, policy))
+            .transpose()?;
+
+        Ok(TransactionRecord {
+            tx_type: self.tx_type,
+            client: self.client,
+            tx: self.tx,
+            amount,
+            memo: self.memo,
+            timestamp: self.timestamp,
+        })
     }
 }
 
 impl TransactionRecord {
+    /// Disputes, resolves and chargebacks act on a previously stored transaction
+    /// rather than moving money in or out themselves.
+    pub fn is_dispute_family(&self) -> bool {
+        matches!(
+            self.tx_type,
+            TransactionType::Dispute
+                | TransactionType::Resolve
+                | TransactionType::Chargeback
+                | TransactionType::ChargebackReversal
+        )
+    }
+
     pub fn validate(&self) -> Result<()> {
         match self.tx_type {
-            TransactionType::Deposit | TransactionType::Withdrawal => {
-                if self.amount.is_none() {
-                    anyhow::bail!("Deposit/Withdrawal requires amount");
-                }
+            TransactionType::Deposit
+            | TransactionType::Withdrawal
+            | TransactionType::Correction
+                if self.amount.is_none() =>
+            {
+                anyhow::bail!("{} requires amount", self.tx_type.as_str());
             }
             _ => {
                 // Dispute, Resolve, Chargeback don't have amounts
             }
         }
+
+        // Only corrections can take money away by their sign
+        if self.tx_type != TransactionType::Correction
+            && self.amount.is_some_and(|amount| amount < Decimal::ZERO)
+        {
+            anyhow::bail!("Negative amount on a {} transaction", self.tx_type.as_str());
+        }
+
         Ok(())
     }
 }
@@ -90,40 +553,130 @@ mod tests {
         assert!(TransactionType::from_str("invalid").is_err());
     }
 
+    #[test]
+    fn test_credit_and_debit_aliases() {
+        for (name, expected) in [
+            ("credit", TransactionType::Deposit),
+            ("Credit", TransactionType::Deposit),
+            ("debit", TransactionType::Withdrawal),
+            ("DEBIT", TransactionType::Withdrawal),
+        ] {
+            assert_eq!(TransactionType::from_str(name).unwrap(), expected, "{name}");
+        }
+
+        let data = "type,client,tx,amount\nCREDIT,1,1,2.0\nDebit,1,2,1.0\n";
+        let types: Vec<_> = csv::Reader::from_reader(data.as_bytes())
+            .deserialize::<RawRecord>()
+            .map(|r| r.unwrap().tx_type)
+            .collect();
+        assert_eq!(
+            types,
+            [TransactionType::Deposit, TransactionType::Withdrawal]
+        );
+
+        // Written out under the canonical names
+        assert_eq!(types[0].as_str(), "deposit");
+        assert_eq!(serde_json::to_string(&types[1]).unwrap(), "\"withdrawal\"");
+    }
+
+    #[test]
+    fn test_ids_serialize_as_plain_integers() {
+        let data = "type,client,tx,amount\ndeposit,65535,4294967295,1.0\n";
+        let mut reader = csv::Reader::from_reader(data.as_bytes());
+        let record: TransactionRecord = reader.deserialize().next().unwrap().unwrap();
+        assert_eq!(record.client, ClientId(u16::MAX));
+        assert_eq!(record.tx, TxId(u32::MAX));
+
+        let stored = StoredTransaction::new(record.client, Decimal::ONE, record.tx_type);
+        let json = serde_json::to_string(&stored).unwrap();
+        assert!(json.starts_with(r#"{"client":65535,"#), "{json}");
+        assert_eq!(
+            serde_json::from_str::<StoredTransaction>(&json).unwrap(),
+            stored
+        );
+
+        assert_eq!(ClientId(7).to_string(), "7");
+        assert_eq!("42".parse::<TxId>().unwrap(), TxId(42));
+        assert!("70000".parse::<ClientId>().is_err());
+    }
+
+    #[test]
+    fn test_unknown_types_deserialize_as_custom() {
+        let data = "type,client,tx,amount\nbonus,1,1,2.0\ndeposit,1,2,1.0\n,1,3,1.0\n";
+        let mut reader = csv::Reader::from_reader(data.as_bytes());
+        let types: Vec<_> = reader
+            .deserialize::<RawRecord>()
+            .map(|r| r.map(|r| r.tx_type).ok())
+            .collect();
+
+        assert_eq!(
+            types,
+            vec![
+                Some(TransactionType::Custom("bonus".to_string())),
+                Some(TransactionType::Deposit),
+                None,
+            ]
+        );
+
+        let json = serde_json::to_string(&TransactionType::Custom("bonus".to_string())).unwrap();
+        assert_eq!(json, "\"bonus\"");
+    }
+
     #[test]
     fn test_transaction_record_validate_with_amount() {
         let valid_deposit = TransactionRecord {
             tx_type: TransactionType::Deposit,
-            client: 1,
-            tx: 1,
+            client: ClientId(1),
+            tx: TxId(1),
             amount: Some(Decimal::from_str("10.0").unwrap()),
+            memo: None,
+            timestamp: None,
         };
         assert!(valid_deposit.validate().is_ok());
 
         let invalid_deposit = TransactionRecord {
             tx_type: TransactionType::Deposit,
-            client: 1,
-            tx: 1,
+            client: ClientId(1),
+            tx: TxId(1),
             amount: None,
+            memo: None,
+            timestamp: None,
         };
         assert!(invalid_deposit.validate().is_err());
+
+        // Only corrections may carry a negative amount
+        let negative = |tx_type| TransactionRecord {
+            tx_type,
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Decimal::from_str("-0.0001").unwrap()),
+            memo: None,
+            timestamp: None,
+        };
+        assert!(negative(TransactionType::Deposit).validate().is_err());
+        assert!(negative(TransactionType::Withdrawal).validate().is_err());
+        assert!(negative(TransactionType::Correction).validate().is_ok());
     }
 
     #[test]
     fn test_transaction_record_validate_without_amount() {
         let dispute = TransactionRecord {
             tx_type: TransactionType::Dispute,
-            client: 1,
-            tx: 1,
+            client: ClientId(1),
+            tx: TxId(1),
             amount: None,
+            memo: None,
+            timestamp: None,
         };
         assert!(dispute.validate().is_ok());
 
         let resolve = TransactionRecord {
             tx_type: TransactionType::Resolve,
-            client: 1,
-            tx: 1,
+            client: ClientId(1),
+            tx: TxId(1),
             amount: None,
+            memo: None,
+            timestamp: None,
         };
         assert!(resolve.validate().is_ok());
     }
@@ -132,31 +685,162 @@ mod tests {
     fn test_withdrawal_validation() {
         let valid_withdrawal = TransactionRecord {
             tx_type: TransactionType::Withdrawal,
-            client: 1,
-            tx: 1,
+            client: ClientId(1),
+            tx: TxId(1),
             amount: Some(Decimal::from_str("5.0").unwrap()),
+            memo: None,
+            timestamp: None,
         };
         assert!(valid_withdrawal.validate().is_ok());
 
         let invalid_withdrawal = TransactionRecord {
             tx_type: TransactionType::Withdrawal,
-            client: 1,
-            tx: 1,
+            client: ClientId(1),
+            tx: TxId(1),
             amount: None,
+            memo: None,
+            timestamp: None,
         };
         assert!(invalid_withdrawal.validate().is_err());
     }
 
+    const EXCESS_PRECISION: &str = "1.12345678901234567890123456789012345";
+
+    #[test]
+    fn test_excess_precision_rejected_by_default() {
+        assert!(exceeds_max_scale(EXCESS_PRECISION));
+        assert!(!exceeds_max_scale("1.1234"));
+
+        let result = parse_amount(EXCESS_PRECISION, PrecisionPolicy::default());
+        assert!(matches!(result, Err(AmountError::PrecisionOverflow(_))));
+    }
+
+    #[test]
+    fn test_excess_precision_rounded_to_max_scale() {
+        let amount = parse_amount(EXCESS_PRECISION, PrecisionPolicy::Round).unwrap();
+        assert_eq!(
+            amount,
+            Decimal::from_str("1.1234567890123456789012345679").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_raw_record_keeps_full_precision() {
+        let data = "type,client,tx,amount\ndeposit,1,1,0.1234567890123456789\n";
+        let mut reader = csv::Reader::from_reader(data.as_bytes());
+        let raw: RawRecord = reader.deserialize().next().unwrap().unwrap();
+
+        let record = raw.into_record(PrecisionPolicy::Reject).unwrap();
+        assert_eq!(
+            record.amount,
+            Some(Decimal::from_str("0.1234567890123456789").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_scientific_notation_amounts() {
+        let parse = |raw: &str| parse_amount(raw, PrecisionPolicy::default());
+
+        assert_eq!(parse("1e3").unwrap(), Decimal::from(1000));
+        assert_eq!(parse("1.5e2").unwrap(), Decimal::from(150));
+        assert_eq!(parse("2E-1").unwrap(), Decimal::from_str("0.2").unwrap());
+        assert_eq!(parse("1.5e+2").unwrap(), Decimal::from(150));
+
+        // Beyond the representable scale, or not a number at all
+        assert!(matches!(parse("1e-30"), Err(AmountError::Invalid(_))));
+        assert!(matches!(parse("1e100"), Err(AmountError::Invalid(_))));
+        assert!(matches!(parse("1e3.5"), Err(AmountError::Invalid(_))));
+        assert!(matches!(parse("e5"), Err(AmountError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_strip_grouping() {
+        assert_eq!(strip_grouping("1,000.50", ','), "1000.50");
+        assert_eq!(strip_grouping("-12,345,678", ','), "-12345678");
+        assert_eq!(strip_grouping("1.000,50", '.'), "1.000,50");
+        assert_eq!(strip_grouping("1 000.5", ' '), "1000.5");
+        assert_eq!(strip_grouping("10.5", ','), "10.5");
+
+        // Malformed grouping is left alone, and then fails to parse
+        for raw in ["1,00.5", "1000,000", ",100", "1,000.000,1", "1,,000"] {
+            assert_eq!(strip_grouping(raw, ','), raw);
+        }
+    }
+
+    #[test]
+    fn test_european_amounts() {
+        let normalize = |raw| EuropeanAmounts.normalize(raw).map(Cow::into_owned);
+        assert_eq!(normalize("10,50").unwrap(), "10.50");
+        assert_eq!(normalize("-1.234.567,8").unwrap(), "-1234567.8");
+        assert_eq!(normalize("1.000").unwrap(), "1000");
+        assert_eq!(normalize("1,5e3").unwrap(), "1.5e3");
+        assert_eq!(normalize("42").unwrap(), "42");
+
+        for raw in ["10.50", "1,000,5", "1.00,5", "1,5.0", "12.34.567"] {
+            assert!(
+                matches!(normalize(raw), Err(AmountError::Invalid(_))),
+                "{raw}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_amount_sanity() {
+        let parse = |raw: &str| parse_amount(raw, PrecisionPolicy::default());
+
+        for raw in ["NaN", "nan", "inf", "-inf", "Infinity", "1/0", "0x10", ""] {
+            assert!(matches!(parse(raw), Err(AmountError::Invalid(_))), "{raw}");
+        }
+        for raw in ["-1e19", "1e19", "1000000000000000000.0001"] {
+            assert!(
+                matches!(parse(raw), Err(AmountError::OutOfRange(_))),
+                "{raw}"
+            );
+        }
+
+        assert_eq!(parse("-0").unwrap(), Decimal::ZERO);
+        assert_eq!(parse("-1.5e2").unwrap(), Decimal::from(-150));
+        assert_eq!(parse("1e18").unwrap(), MAX_AMOUNT);
+        assert_eq!(MAX_AMOUNT, Decimal::from(1_000_000_000_000_000_000u64));
+    }
+
+    #[test]
+    fn test_scientific_notation_through_serde() {
+        let data = "type,client,tx,amount\ndeposit,1,1,1.5e2\ndeposit,1,2,2E-1\ndispute,1,1,\n";
+        let mut reader = csv::Reader::from_reader(data.as_bytes());
+        let amounts: Vec<_> = reader
+            .deserialize::<TransactionRecord>()
+            .map(|r| r.unwrap().amount)
+            .collect();
+
+        assert_eq!(
+            amounts,
+            vec![
+                Some(Decimal::from(150)),
+                Some(Decimal::from_str("0.2").unwrap()),
+                None
+            ]
+        );
+    }
+
     #[test]
     fn test_stored_transaction_creation() {
         let stored_tx = StoredTransaction {
-            client: 123,
+            client: ClientId(123),
             amount: Decimal::from_str("15.5").unwrap(),
             tx_type: TransactionType::Deposit,
             disputed: false,
+            held: Decimal::ZERO,
+            sequence: 0,
+            stored_at: 0,
+            timestamp: None,
+            had_dispute: false,
+            resolved: false,
+            finalized: false,
+            charged_back: Decimal::ZERO,
         };
 
-        assert_eq!(stored_tx.client, 123);
+        assert_eq!(stored_tx.client, ClientId(123));
         assert_eq!(stored_tx.amount, Decimal::from_str("15.5").unwrap());
         assert!(matches!(stored_tx.tx_type, TransactionType::Deposit));
         assert!(!stored_tx.disputed);
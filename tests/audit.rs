@@ -0,0 +1,49 @@
+mod common;
+
+use assert_cmd::Command;
+use common::TempFile;
+use std::fs;
+
+#[test]
+fn audit_exit_code_follows_the_checks() {
+    let snapshot = TempFile::new("audit.json");
+    Command::cargo_bin("tx-processor")
+        .unwrap()
+        .arg("--save-snapshot")
+        .arg(&snapshot)
+        .arg("-")
+        .write_stdin(
+            "type,client,tx,amount\n\
+             deposit,1,1,10.0\n\
+             deposit,1,2,5.0\n\
+             dispute,1,2,\n",
+        )
+        .assert()
+        .success();
+
+    let clean = Command::cargo_bin("tx-processor")
+        .unwrap()
+        .arg("audit")
+        .arg(&snapshot)
+        .assert()
+        .success();
+    let report = String::from_utf8(clean.get_output().stdout.clone()).unwrap();
+    assert!(!report.contains("FAIL"), "{report}");
+
+    // Release the account's held funds by hand, accounts come first, leaving the dispute open
+    let json = fs::read_to_string(&snapshot).unwrap();
+    let json = json.replacen(r#""held":"5.0""#, r#""held":"0""#, 1);
+    fs::write(&snapshot, json).unwrap();
+
+    let tampered = Command::cargo_bin("tx-processor")
+        .unwrap()
+        .arg("audit")
+        .arg(&snapshot)
+        .assert()
+        .code(3);
+    let report = String::from_utf8(tampered.get_output().stdout.clone()).unwrap();
+    assert!(
+        report.contains("held_consistency: FAIL\n  client 1: held doesn't match"),
+        "{report}"
+    );
+}
@@ -0,0 +1,70 @@
+// Runs the binary end to end. Unlike the fixtures harness, these pin stderr and the exit
+// status as well as stdout, so a change in how `main` reports skipped rows shows up here.
+
+use assert_cmd::Command;
+use std::fs;
+use std::path::PathBuf;
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+fn expected(name: &str) -> String {
+    fs::read_to_string(fixture(name)).unwrap()
+}
+
+#[test]
+fn disputes_and_chargebacks() {
+    Command::cargo_bin("tx-processor")
+        .unwrap()
+        .arg(fixture("chargebacks.in.csv"))
+        .assert()
+        .success()
+        .stdout(expected("chargebacks.out.csv"))
+        .stderr("");
+}
+
+#[test]
+fn malformed_rows_are_skipped() {
+    Command::cargo_bin("tx-processor")
+        .unwrap()
+        .arg(fixture("malformed.in.csv"))
+        .assert()
+        .success()
+        .stdout(expected("malformed.out.csv"))
+        // Rows that don't parse go silently, rows the engine rejects are warned about
+        .stderr(
+            "Warning: Failed to process transaction: Invalid transaction\n\
+             Warning: Failed to process transaction: Unknown transaction type: bogus\n",
+        );
+}
+
+#[test]
+fn missing_input_fails() {
+    Command::cargo_bin("tx-processor")
+        .unwrap()
+        .arg(fixture("missing.in.csv"))
+        .assert()
+        .failure()
+        .stdout("");
+}
+
+#[test]
+fn validate_only_reports_invalid_rows() {
+    Command::cargo_bin("tx-processor")
+        .unwrap()
+        .arg("--validate-only")
+        .arg(fixture("malformed.in.csv"))
+        .assert()
+        .failure()
+        .stdout(
+            "line 3: invalid amount \"abc\"\n\
+             line 4: Invalid transaction: withdrawal requires amount\n\
+             line 5: Unknown transaction type: bogus\n\
+             line 6: CSV deserialize error: record 5 (line: 6, byte: 84): field 1: \
+             invalid digit found in string\n\
+             4 of 8 record(s) invalid\n",
+        );
+}
@@ -0,0 +1,37 @@
+//! Helpers shared by the integration tests.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A file in cargo's scratch directory for integration tests, removed again when dropped so a
+/// failing test doesn't leave it behind.
+pub struct TempFile(PathBuf);
+
+impl TempFile {
+    /// Tests run in parallel, so `name` has to be unique across them.
+    pub fn new(name: &str) -> Self {
+        let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+        // Left over from a run that was killed
+        let _ = fs::remove_file(&path);
+        Self(path)
+    }
+}
+
+impl AsRef<Path> for TempFile {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<OsStr> for TempFile {
+    fn as_ref(&self) -> &OsStr {
+        self.0.as_os_str()
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
@@ -0,0 +1,82 @@
+use assert_cmd::Command;
+
+/// Each client's own records, in the order they have to be applied.
+fn client_records(client: u32) -> Vec<String> {
+    let tx = client * 10;
+    let mut records = vec![
+        format!(
+            "deposit,{client},{tx},{}.{:04}",
+            client % 97,
+            client * 31 % 10_000
+        ),
+        format!("deposit,{client},{},2.5", tx + 1),
+        format!("withdrawal,{client},{},1.25", tx + 2),
+    ];
+    match client % 4 {
+        0 => records.push(format!("dispute,{client},{tx},")),
+        1 => records.extend([
+            format!("dispute,{client},{},", tx + 1),
+            format!("chargeback,{client},{},", tx + 1),
+        ]),
+        _ => {}
+    }
+    records
+}
+
+/// Interleaves the clients' records in a pseudo-random order picked by `seed`, keeping each
+/// client's own records in order, so every shuffle leaves the accounts the same.
+fn shuffled_input(seed: u64) -> String {
+    let mut queues: Vec<_> = (1..=500)
+        .map(|client| client_records(client).into_iter())
+        .collect();
+    let mut state = seed;
+    let mut input = String::from("type,client,tx,amount\n");
+    while !queues.is_empty() {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        let i = (state >> 33) as usize % queues.len();
+        match queues[i].next() {
+            Some(record) => {
+                input.push_str(&record);
+                input.push('\n');
+            }
+            None => {
+                queues.swap_remove(i);
+            }
+        }
+    }
+    input
+}
+
+fn run(input: &str) -> Vec<u8> {
+    Command::cargo_bin("tx-processor")
+        .unwrap()
+        .arg("-")
+        .write_stdin(input)
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone()
+}
+
+#[test]
+fn output_is_byte_identical_across_runs_and_shuffles() {
+    let first = shuffled_input(1);
+    let expected = run(&first);
+
+    let text = String::from_utf8(expected.clone()).unwrap();
+    let clients: Vec<u32> = text
+        .lines()
+        .skip(1)
+        .map(|row| row.split(',').next().unwrap().parse().unwrap())
+        .collect();
+    assert_eq!(clients, (1..=500).collect::<Vec<_>>());
+
+    for seed in [1, 2, 3] {
+        let input = shuffled_input(seed);
+        assert_eq!(seed == 1, input == first);
+        assert_eq!(run(&input), expected);
+    }
+}
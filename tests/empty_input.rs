@@ -0,0 +1,23 @@
+use assert_cmd::Command;
+
+#[test]
+fn empty_input_prints_only_the_header() {
+    Command::cargo_bin("tx-processor")
+        .unwrap()
+        .arg("-")
+        .write_stdin("")
+        .assert()
+        .success()
+        .stdout("client,available,held,total,locked\n");
+}
+
+#[test]
+fn empty_input_fails_on_request() {
+    Command::cargo_bin("tx-processor")
+        .unwrap()
+        .args(["--fail-on-empty", "-"])
+        .write_stdin("")
+        .assert()
+        .code(2)
+        .stdout("client,available,held,total,locked\n");
+}
@@ -0,0 +1,44 @@
+use assert_cmd::Command;
+use std::fs;
+use std::path::Path;
+
+/// Runs every `tests/fixtures/<name>.in.csv` through the binary and compares the output with
+/// `<name>.out.csv`. Add a regression case by dropping in a new pair of files.
+///
+/// Account rows come out sorted by client, so the output must match byte for byte.
+#[test]
+fn fixtures_match_expected_output() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let mut inputs: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.to_string_lossy().ends_with(".in.csv"))
+        .collect();
+    inputs.sort();
+    assert!(!inputs.is_empty(), "no fixtures in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for input in &inputs {
+        let name = input
+            .to_string_lossy()
+            .trim_end_matches(".in.csv")
+            .to_string();
+        let expected = fs::read_to_string(format!("{name}.out.csv"))
+            .unwrap_or_else(|e| panic!("{name}.out.csv: {e}"));
+
+        let output = Command::cargo_bin("tx-processor")
+            .unwrap()
+            .arg(input)
+            .assert()
+            .success();
+
+        let actual = String::from_utf8(output.get_output().stdout.clone()).unwrap();
+        if actual != expected {
+            failures.push(format!(
+                "{name}:\n--- expected\n{expected}--- actual\n{actual}"
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
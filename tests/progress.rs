@@ -0,0 +1,44 @@
+use assert_cmd::Command;
+use std::process::Output;
+
+fn run(args: &[&str], input: &str) -> Output {
+    Command::cargo_bin("tx-processor")
+        .unwrap()
+        .args(args)
+        .arg("-")
+        .write_stdin(input)
+        .assert()
+        .success()
+        .get_output()
+        .clone()
+}
+
+#[test]
+fn progress_does_not_change_output() {
+    let mut input = String::from("type,client,tx,amount\n");
+    for tx in 1..=120_000 {
+        input.push_str(&format!("deposit,{},{tx},1.0\n", tx % 3));
+    }
+
+    let plain = run(&[], &input);
+    let with_progress = run(&["--progress"], &input);
+
+    // Account order isn't deterministic between runs
+    let sorted_lines = |stdout: &[u8]| {
+        let mut lines: Vec<_> = String::from_utf8(stdout.to_vec())
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        lines.sort();
+        lines
+    };
+    assert_eq!(
+        sorted_lines(&with_progress.stdout),
+        sorted_lines(&plain.stdout)
+    );
+
+    let stderr = String::from_utf8(with_progress.stderr).unwrap();
+    assert!(stderr.contains("Progress: 100000 records"), "{stderr}");
+    assert!(plain.stderr.is_empty());
+}
@@ -0,0 +1,65 @@
+use assert_cmd::Command;
+
+#[test]
+fn stats_only_prints_summary_without_accounts() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,10.0\n\
+                 deposit,2,2,5.0\n\
+                 withdrawal,2,3,1.5\n\
+                 dispute,1,1,\n\
+                 chargeback,1,1,\n\
+                 deposit,3,4,not-a-number\n\
+                 withdrawal,2,5,100.0\n\
+                 withdrawal,1,6,1.0\n";
+
+    let output = Command::cargo_bin("tx-processor")
+        .unwrap()
+        .args(["--stats-only", "-"])
+        .write_stdin(input)
+        .assert()
+        .success();
+
+    let stdout = String::from_utf8(output.get_output().stdout.clone()).unwrap();
+    assert!(!stdout.contains("client,available"));
+
+    let expected = [
+        "metric,value",
+        "records_read,8",
+        "records_processed,7",
+        "records_rejected,0",
+        "malformed,1",
+        "oversized,0",
+        "precision_overflows,0",
+        "out_of_range_amounts,0",
+        "filtered,0",
+        "deduped,0",
+        "unknown_client_rejections,0",
+        "account_limit_rejections,0",
+        "held_cap_rejections,0",
+        "dispute_amount_mismatches,0",
+        "cross_client_disputes,0",
+        "out_of_window_disputes,0",
+        "dispute_ratio_breaches,0",
+        "duplicate_disputes,0",
+        "duplicate_resolves,0",
+        "withdrawals_failed_locked,1",
+        "withdrawals_failed_insufficient,1",
+        "fees_collected,0",
+        "disputes_auto_resolved,0",
+        "disputes_auto_charged_back,0",
+        "transaction_types.chargeback.count,1",
+        "transaction_types.deposit.count,2",
+        "transaction_types.deposit.volume,15.0",
+        "transaction_types.dispute.count,1",
+        "transaction_types.withdrawal.count,3",
+        "transaction_types.withdrawal.volume,1.5",
+        "accounts,2",
+        "locked_accounts,1",
+        "open_disputes,0",
+        "total_overflows,0",
+        "total_available,3.5",
+        "total_held,0",
+        "total,3.5",
+    ];
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), expected);
+}
@@ -0,0 +1,16 @@
+use assert_cmd::Command;
+
+#[test]
+fn reads_transactions_from_stdin() {
+    Command::cargo_bin("tx-processor")
+        .unwrap()
+        .arg("-")
+        .write_stdin(
+            "type,client,tx,amount\n\
+             deposit,1,1,10.0\n\
+             withdrawal,1,2,4.5\n",
+        )
+        .assert()
+        .success()
+        .stdout("client,available,held,total,locked\n1,5.5,0,5.5,false\n");
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use assert_cmd::Command;
+use common::TempFile;
+
+#[test]
+fn summary_json_written_next_to_the_output() {
+    let summary = TempFile::new("summary.json");
+    let output = Command::cargo_bin("tx-processor")
+        .unwrap()
+        .arg("--summary-json")
+        .arg(&summary)
+        .arg("-")
+        .write_stdin(
+            "type,client,tx,amount\n\
+             deposit,1,1,10.0\n\
+             deposit,2,2,5.0\n\
+             withdrawal,2,3,1.5\n\
+             dispute,1,1,\n\
+             chargeback,1,1,\n\
+             deposit,3,4,not-a-number\n\
+             withdrawal,2,5,100.0\n\
+             withdrawal,1,6,1.0\n",
+        )
+        .assert()
+        .success();
+
+    // The account table still goes to stdout as usual
+    let stdout = String::from_utf8(output.get_output().stdout.clone()).unwrap();
+    assert!(stdout.starts_with("client,available,held,total,locked\n"));
+
+    let json = std::fs::read_to_string(&summary).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(json["records_read"], 8);
+    assert_eq!(json["records_processed"], 7);
+    assert_eq!(json["malformed"], 1);
+    assert_eq!(json["withdrawals_failed_locked"], 1);
+    assert_eq!(json["withdrawals_failed_insufficient"], 1);
+    assert_eq!(json["accounts"], 2);
+    assert_eq!(json["locked_accounts"], 1);
+    assert_eq!(json["open_disputes"], 0);
+    assert_eq!(json["total_available"], "3.5");
+    assert_eq!(json["total"], "3.5");
+}
@@ -0,0 +1,16 @@
+use assert_cmd::Command;
+
+#[test]
+fn spent_time_budget_stops_reading() {
+    // A zero budget is spent before the first record, whatever the machine's speed
+    Command::cargo_bin("tx-processor")
+        .unwrap()
+        .args(["--time-budget", "0", "-"])
+        .write_stdin("type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,1.0\n")
+        .assert()
+        .success()
+        .stdout("client,available,held,total,locked\n")
+        .stderr(
+            "Warning: Time budget spent after 0 records (0 processed), the output is partial\n",
+        );
+}
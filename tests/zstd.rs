@@ -0,0 +1,27 @@
+#![cfg(feature = "zstd")]
+
+use assert_cmd::Command;
+use std::path::Path;
+
+fn run(input: &Path) -> Vec<u8> {
+    Command::cargo_bin("tx-processor")
+        .unwrap()
+        .arg(input)
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone()
+}
+
+#[test]
+fn zstd_input_matches_uncompressed() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let compressed = run(&dir.join("disputes.in.csv.zst"));
+
+    assert_eq!(compressed, run(&dir.join("disputes.in.csv")));
+    assert_eq!(
+        compressed,
+        std::fs::read(dir.join("disputes.out.csv")).unwrap()
+    );
+}